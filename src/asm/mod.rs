@@ -0,0 +1,786 @@
+/*
+ * A small SM83 assembler for building test ROMs in Rust. Code is emitted through
+ * a builder with one method per instruction family, and jump or load targets
+ * can be labels which are resolved when the ROM is built:
+ *
+ *     let mut asm = Assembler::new("SCENE");
+ *     asm.ld_n(R8::A, 0x91)
+ *         .ld_ind_a(Ind::High(0x40))
+ *         .label("loop")
+ *         .jr("loop");
+ *     let rom = asm.build();
+ *
+ * Code starts at 0x0150 in bank 0, and the entry point at 0x0100 jumps there.
+ * `section` and `bank_section` move the location counter elsewhere. `build`
+ * fills in the header (logo, title, cartridge type, ROM size and both
+ * checksums) so that the ROM passes the boot ROM checks.
+ */
+
+// Not every instruction is needed by the tests that use the assembler
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+
+const BANK_SIZE: usize = 0x4000;
+const ENTRY_POINT: usize = 0x0100;
+const CODE_START: u16 = 0x0150;
+
+const NINTENDO_LOGO: [u8; 0x30] = [
+    0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D,
+    0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E, 0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99,
+    0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
+];
+
+/// 8 bit operands, in encoding order. `HL` is the byte pointed to by HL.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum R8 {
+    B,
+    C,
+    D,
+    E,
+    H,
+    L,
+    HL,
+    A,
+}
+
+/// 16 bit register pairs. `SP` is only valid outside of push/pop and `AF` only within.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum R16 {
+    BC,
+    DE,
+    HL,
+    SP,
+    AF,
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Cond {
+    NZ,
+    Z,
+    NC,
+    C,
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Alu {
+    Add,
+    Adc,
+    Sub,
+    Sbc,
+    And,
+    Xor,
+    Or,
+    Cp,
+}
+
+/// Extended (0xCB prefixed) rotates and shifts
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Shift {
+    Rlc,
+    Rrc,
+    Rl,
+    Rr,
+    Sla,
+    Sra,
+    Swap,
+    Srl,
+}
+
+/// Either a fixed address or a label resolved at build time
+#[derive(Debug, PartialEq, Clone)]
+pub enum Target {
+    Address(u16),
+    Label(String),
+}
+
+impl From<u16> for Target {
+    fn from(address: u16) -> Target {
+        Target::Address(address)
+    }
+}
+
+impl From<&str> for Target {
+    fn from(label: &str) -> Target {
+        Target::Label(label.to_string())
+    }
+}
+
+/// Memory operands for loads to and from A
+#[derive(Debug, PartialEq, Clone)]
+pub enum Ind {
+    BC,
+    DE,
+    HLInc,
+    HLDec,
+    /// 0xFF00 + C
+    C,
+    /// 0xFF00 + n
+    High(u8),
+    Addr(Target),
+}
+
+impl R8 {
+    fn index(self) -> u8 {
+        self as u8
+    }
+}
+
+impl R16 {
+    fn index(self) -> u8 {
+        match self {
+            R16::BC => 0,
+            R16::DE => 1,
+            R16::HL => 2,
+            R16::SP => 3,
+            R16::AF => panic!("AF can only be pushed or popped"),
+        }
+    }
+
+    fn stack_index(self) -> u8 {
+        match self {
+            R16::BC => 0,
+            R16::DE => 1,
+            R16::HL => 2,
+            R16::AF => 3,
+            R16::SP => panic!("SP cannot be pushed or popped"),
+        }
+    }
+}
+
+enum Fixup {
+    Absolute,
+    Relative,
+}
+
+pub struct Assembler {
+    title: String,
+    cartridge_type: Option<u8>,
+    rom: Vec<u8>,
+    written: Vec<bool>,
+    bank: usize,
+    address: u16,
+    labels: HashMap<String, u16>,
+    fixups: Vec<(usize, u16, Fixup, String)>,
+}
+
+impl Assembler {
+    pub fn new(title: &str) -> Assembler {
+        assert!(title.len() <= 16, "title is longer than 16 bytes");
+
+        Assembler {
+            title: title.to_string(),
+            cartridge_type: None,
+            rom: vec![0; 2 * BANK_SIZE],
+            written: vec![false; 2 * BANK_SIZE],
+            bank: 1,
+            address: CODE_START,
+            labels: HashMap::new(),
+            fixups: Vec::new(),
+        }
+    }
+
+    /// Overrides the cartridge type, which otherwise is ROM only for 32 kB
+    /// images and MBC1 beyond that
+    pub fn cartridge_type(&mut self, byte: u8) -> &mut Assembler {
+        self.cartridge_type = Some(byte);
+        self
+    }
+
+    /// Continues assembling at the address, in bank 0 or the current switchable bank
+    pub fn section(&mut self, address: u16) -> &mut Assembler {
+        assert!(
+            address < 0x8000,
+            "sections must be in ROM: 0x{:04X}",
+            address
+        );
+        self.address = address;
+        self
+    }
+
+    /// Continues assembling at the address within the given switchable bank
+    pub fn bank_section(&mut self, bank: usize, address: u16) -> &mut Assembler {
+        assert!(bank > 0, "bank 0 is not switchable");
+        assert!(
+            (0x4000..0x8000).contains(&address),
+            "banked sections must be within 0x4000-0x7FFF: 0x{:04X}",
+            address
+        );
+        self.bank = bank;
+        self.address = address;
+        self
+    }
+
+    /// The address the next instruction will be assembled at
+    pub fn here(&self) -> u16 {
+        self.address
+    }
+
+    pub fn label(&mut self, name: &str) -> &mut Assembler {
+        if self.labels.insert(name.to_string(), self.address).is_some() {
+            panic!("label defined twice: {}", name);
+        }
+        self
+    }
+
+    pub fn db(&mut self, bytes: &[u8]) -> &mut Assembler {
+        for &byte in bytes {
+            self.emit(byte);
+        }
+        self
+    }
+
+    pub fn dw<T: Into<Target>>(&mut self, target: T) -> &mut Assembler {
+        self.emit_target(target.into())
+    }
+
+    pub fn nop(&mut self) -> &mut Assembler {
+        self.db(&[0x00])
+    }
+
+    pub fn halt(&mut self) -> &mut Assembler {
+        self.db(&[0x76])
+    }
+
+    pub fn stop(&mut self) -> &mut Assembler {
+        self.db(&[0x10, 0x00])
+    }
+
+    pub fn di(&mut self) -> &mut Assembler {
+        self.db(&[0xF3])
+    }
+
+    pub fn ei(&mut self) -> &mut Assembler {
+        self.db(&[0xFB])
+    }
+
+    pub fn rlca(&mut self) -> &mut Assembler {
+        self.db(&[0x07])
+    }
+
+    pub fn rrca(&mut self) -> &mut Assembler {
+        self.db(&[0x0F])
+    }
+
+    pub fn rla(&mut self) -> &mut Assembler {
+        self.db(&[0x17])
+    }
+
+    pub fn rra(&mut self) -> &mut Assembler {
+        self.db(&[0x1F])
+    }
+
+    pub fn daa(&mut self) -> &mut Assembler {
+        self.db(&[0x27])
+    }
+
+    pub fn cpl(&mut self) -> &mut Assembler {
+        self.db(&[0x2F])
+    }
+
+    pub fn scf(&mut self) -> &mut Assembler {
+        self.db(&[0x37])
+    }
+
+    pub fn ccf(&mut self) -> &mut Assembler {
+        self.db(&[0x3F])
+    }
+
+    /// LD r,r'
+    pub fn ld(&mut self, dest: R8, src: R8) -> &mut Assembler {
+        assert!(
+            dest != R8::HL || src != R8::HL,
+            "LD (HL),(HL) is encoded as HALT"
+        );
+        self.db(&[0x40 | dest.index() << 3 | src.index()])
+    }
+
+    /// LD r,n
+    pub fn ld_n(&mut self, dest: R8, value: u8) -> &mut Assembler {
+        self.db(&[0x06 | dest.index() << 3, value])
+    }
+
+    /// LD rr,nn
+    pub fn ld16<T: Into<Target>>(&mut self, dest: R16, value: T) -> &mut Assembler {
+        self.db(&[0x01 | dest.index() << 4]);
+        self.emit_target(value.into())
+    }
+
+    /// Stores A into memory
+    pub fn ld_ind_a(&mut self, dest: Ind) -> &mut Assembler {
+        self.ld_ind(dest, false)
+    }
+
+    /// Loads A from memory
+    pub fn ld_a_ind(&mut self, src: Ind) -> &mut Assembler {
+        self.ld_ind(src, true)
+    }
+
+    /// LD (nn),SP
+    pub fn ld_addr_sp<T: Into<Target>>(&mut self, dest: T) -> &mut Assembler {
+        self.db(&[0x08]);
+        self.emit_target(dest.into())
+    }
+
+    /// LD SP,HL
+    pub fn ld_sp_hl(&mut self) -> &mut Assembler {
+        self.db(&[0xF9])
+    }
+
+    /// LD HL,SP+e
+    pub fn ld_hl_sp(&mut self, offset: i8) -> &mut Assembler {
+        self.db(&[0xF8, offset as u8])
+    }
+
+    pub fn alu(&mut self, op: Alu, src: R8) -> &mut Assembler {
+        self.db(&[0x80 | (op as u8) << 3 | src.index()])
+    }
+
+    pub fn alu_n(&mut self, op: Alu, value: u8) -> &mut Assembler {
+        self.db(&[0xC6 | (op as u8) << 3, value])
+    }
+
+    pub fn inc(&mut self, loc: R8) -> &mut Assembler {
+        self.db(&[0x04 | loc.index() << 3])
+    }
+
+    pub fn dec(&mut self, loc: R8) -> &mut Assembler {
+        self.db(&[0x05 | loc.index() << 3])
+    }
+
+    pub fn inc16(&mut self, loc: R16) -> &mut Assembler {
+        self.db(&[0x03 | loc.index() << 4])
+    }
+
+    pub fn dec16(&mut self, loc: R16) -> &mut Assembler {
+        self.db(&[0x0B | loc.index() << 4])
+    }
+
+    /// ADD HL,rr
+    pub fn add_hl(&mut self, src: R16) -> &mut Assembler {
+        self.db(&[0x09 | src.index() << 4])
+    }
+
+    /// ADD SP,e
+    pub fn add_sp(&mut self, offset: i8) -> &mut Assembler {
+        self.db(&[0xE8, offset as u8])
+    }
+
+    pub fn push(&mut self, src: R16) -> &mut Assembler {
+        self.db(&[0xC5 | src.stack_index() << 4])
+    }
+
+    pub fn pop(&mut self, dest: R16) -> &mut Assembler {
+        self.db(&[0xC1 | dest.stack_index() << 4])
+    }
+
+    pub fn shift(&mut self, op: Shift, loc: R8) -> &mut Assembler {
+        self.db(&[0xCB, (op as u8) << 3 | loc.index()])
+    }
+
+    pub fn bit(&mut self, index: u8, loc: R8) -> &mut Assembler {
+        self.cb_bit(0x40, index, loc)
+    }
+
+    pub fn res(&mut self, index: u8, loc: R8) -> &mut Assembler {
+        self.cb_bit(0x80, index, loc)
+    }
+
+    pub fn set(&mut self, index: u8, loc: R8) -> &mut Assembler {
+        self.cb_bit(0xC0, index, loc)
+    }
+
+    pub fn jp<T: Into<Target>>(&mut self, target: T) -> &mut Assembler {
+        self.db(&[0xC3]);
+        self.emit_target(target.into())
+    }
+
+    pub fn jp_cc<T: Into<Target>>(&mut self, cond: Cond, target: T) -> &mut Assembler {
+        self.db(&[0xC2 | (cond as u8) << 3]);
+        self.emit_target(target.into())
+    }
+
+    /// JP (HL)
+    pub fn jp_hl(&mut self) -> &mut Assembler {
+        self.db(&[0xE9])
+    }
+
+    pub fn jr<T: Into<Target>>(&mut self, target: T) -> &mut Assembler {
+        self.db(&[0x18]);
+        self.emit_relative(target.into())
+    }
+
+    pub fn jr_cc<T: Into<Target>>(&mut self, cond: Cond, target: T) -> &mut Assembler {
+        self.db(&[0x20 | (cond as u8) << 3]);
+        self.emit_relative(target.into())
+    }
+
+    pub fn call<T: Into<Target>>(&mut self, target: T) -> &mut Assembler {
+        self.db(&[0xCD]);
+        self.emit_target(target.into())
+    }
+
+    pub fn call_cc<T: Into<Target>>(&mut self, cond: Cond, target: T) -> &mut Assembler {
+        self.db(&[0xC4 | (cond as u8) << 3]);
+        self.emit_target(target.into())
+    }
+
+    pub fn ret(&mut self) -> &mut Assembler {
+        self.db(&[0xC9])
+    }
+
+    pub fn ret_cc(&mut self, cond: Cond) -> &mut Assembler {
+        self.db(&[0xC0 | (cond as u8) << 3])
+    }
+
+    pub fn reti(&mut self) -> &mut Assembler {
+        self.db(&[0xD9])
+    }
+
+    pub fn rst(&mut self, vector: u8) -> &mut Assembler {
+        assert!(vector & !0x38 == 0, "invalid RST vector 0x{:02X}", vector);
+        self.db(&[0xC7 | vector])
+    }
+
+    /// Resolves labels and fills in the header, returning the ROM image
+    pub fn build(&mut self) -> Vec<u8> {
+        let fixups = std::mem::take(&mut self.fixups);
+        for (offset, from, kind, label) in fixups {
+            let address = match self.labels.get(&label) {
+                Some(&address) => address,
+                None => panic!("undefined label: {}", label),
+            };
+
+            match kind {
+                Fixup::Absolute => {
+                    self.rom[offset] = address as u8;
+                    self.rom[offset + 1] = (address >> 8) as u8;
+                }
+                Fixup::Relative => self.rom[offset] = relative_offset(from, address),
+            }
+        }
+
+        self.write_header();
+        self.rom.clone()
+    }
+
+    fn write_header(&mut self) {
+        let banks = self.rom.len() / BANK_SIZE;
+        let cartridge_type = self
+            .cartridge_type
+            .unwrap_or(if banks > 2 { 0x01 } else { 0x00 });
+
+        // nop; jp CODE_START
+        let entry = [0x00, 0xC3, CODE_START as u8, (CODE_START >> 8) as u8];
+        self.rom[ENTRY_POINT..ENTRY_POINT + 4].copy_from_slice(&entry);
+        self.rom[0x104..0x134].copy_from_slice(&NINTENDO_LOGO);
+
+        let title = self.title.as_bytes();
+        self.rom[0x134..0x134 + title.len()].copy_from_slice(title);
+        self.rom[0x147] = cartridge_type;
+        self.rom[0x148] = banks.trailing_zeros() as u8 - 1;
+
+        self.rom[0x14D] = self.rom[0x134..0x14D]
+            .iter()
+            .fold(0u8, |sum, &byte| sum.wrapping_sub(byte).wrapping_sub(1));
+
+        self.rom[0x14E] = 0;
+        self.rom[0x14F] = 0;
+        let checksum = self
+            .rom
+            .iter()
+            .fold(0u16, |sum, &byte| sum.wrapping_add(byte as u16));
+        self.rom[0x14E] = (checksum >> 8) as u8;
+        self.rom[0x14F] = checksum as u8;
+    }
+
+    fn ld_ind(&mut self, ind: Ind, load: bool) -> &mut Assembler {
+        // Loads into A differ from stores by bit 3 in the 0x0_-0x3_ rows
+        // and by bit 4 in the 0xE_/0xF_ rows
+        let (low, high) = if load { (0x08, 0x10) } else { (0x00, 0x00) };
+        match ind {
+            Ind::BC => self.db(&[0x02 | low]),
+            Ind::DE => self.db(&[0x12 | low]),
+            Ind::HLInc => self.db(&[0x22 | low]),
+            Ind::HLDec => self.db(&[0x32 | low]),
+            Ind::C => self.db(&[0xE2 | high]),
+            Ind::High(offset) => self.db(&[0xE0 | high, offset]),
+            Ind::Addr(target) => {
+                self.db(&[0xEA | high]);
+                self.emit_target(target)
+            }
+        }
+    }
+
+    fn cb_bit(&mut self, base: u8, index: u8, loc: R8) -> &mut Assembler {
+        assert!(index < 8, "invalid bit index {}", index);
+        self.db(&[0xCB, base | index << 3 | loc.index()])
+    }
+
+    fn emit_target(&mut self, target: Target) -> &mut Assembler {
+        match target {
+            Target::Address(address) => self.db(&[address as u8, (address >> 8) as u8]),
+            Target::Label(label) => {
+                let offset = self.offset();
+                self.fixups
+                    .push((offset, self.address, Fixup::Absolute, label));
+                self.db(&[0, 0])
+            }
+        }
+    }
+
+    fn emit_relative(&mut self, target: Target) -> &mut Assembler {
+        // Offsets are relative to the address following the operand
+        let from = self.address.wrapping_add(1);
+        match target {
+            Target::Address(address) => self.db(&[relative_offset(from, address)]),
+            Target::Label(label) => {
+                let offset = self.offset();
+                self.fixups.push((offset, from, Fixup::Relative, label));
+                self.db(&[0])
+            }
+        }
+    }
+
+    fn offset(&self) -> usize {
+        let address = self.address as usize;
+        if address < BANK_SIZE {
+            address
+        } else {
+            self.bank * BANK_SIZE + address - BANK_SIZE
+        }
+    }
+
+    fn emit(&mut self, byte: u8) {
+        assert!(self.address < 0x8000, "assembled past the end of ROM");
+        assert!(
+            self.address as usize >= CODE_START as usize || (self.address as usize) < ENTRY_POINT,
+            "0x{:04X} is within the cartridge header",
+            self.address
+        );
+
+        let offset = self.offset();
+        if offset >= self.rom.len() {
+            // Grow to the next power of two number of banks
+            let size = (offset + 1).next_power_of_two().max(2 * BANK_SIZE);
+            self.rom.resize(size, 0);
+            self.written.resize(size, false);
+        }
+
+        assert!(
+            !self.written[offset],
+            "overlapping code at 0x{:04X}",
+            self.address
+        );
+        self.rom[offset] = byte;
+        self.written[offset] = true;
+        self.address += 1;
+    }
+}
+
+fn relative_offset(from: u16, to: u16) -> u8 {
+    let delta = to as i32 - from as i32;
+    assert!(
+        (-128..128).contains(&delta),
+        "relative jump from 0x{:04X} to 0x{:04X} is out of range",
+        from,
+        to
+    );
+    delta as i8 as u8
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn assemble(f: impl Fn(&mut Assembler)) -> Vec<u8> {
+        let mut asm = Assembler::new("TEST");
+        f(&mut asm);
+        let end = asm.here() as usize;
+        asm.build()[CODE_START as usize..end].to_vec()
+    }
+
+    #[test]
+    fn encode_loads() {
+        let code = assemble(|asm| {
+            asm.ld(R8::B, R8::C)
+                .ld(R8::HL, R8::A)
+                .ld_n(R8::A, 0x12)
+                .ld_n(R8::HL, 0x34)
+                .ld16(R16::SP, 0xFFFE)
+                .ld16(R16::HL, 0x9800)
+                .ld_addr_sp(0xC000)
+                .ld_sp_hl()
+                .ld_hl_sp(-2);
+        });
+
+        assert_eq!(
+            code,
+            vec![
+                0x41, 0x77, 0x3E, 0x12, 0x36, 0x34, 0x31, 0xFE, 0xFF, 0x21, 0x00, 0x98, 0x08, 0x00,
+                0xC0, 0xF9, 0xF8, 0xFE
+            ]
+        );
+    }
+
+    #[test]
+    fn encode_indirect_loads() {
+        let code = assemble(|asm| {
+            asm.ld_ind_a(Ind::BC)
+                .ld_ind_a(Ind::DE)
+                .ld_ind_a(Ind::HLInc)
+                .ld_ind_a(Ind::HLDec)
+                .ld_ind_a(Ind::C)
+                .ld_ind_a(Ind::High(0x40))
+                .ld_ind_a(Ind::Addr(Target::Address(0xC000)))
+                .ld_a_ind(Ind::BC)
+                .ld_a_ind(Ind::DE)
+                .ld_a_ind(Ind::HLInc)
+                .ld_a_ind(Ind::HLDec)
+                .ld_a_ind(Ind::C)
+                .ld_a_ind(Ind::High(0x44))
+                .ld_a_ind(Ind::Addr(Target::Address(0xC000)));
+        });
+
+        assert_eq!(
+            code,
+            vec![
+                0x02, 0x12, 0x22, 0x32, 0xE2, 0xE0, 0x40, 0xEA, 0x00, 0xC0, 0x0A, 0x1A, 0x2A, 0x3A,
+                0xF2, 0xF0, 0x44, 0xFA, 0x00, 0xC0
+            ]
+        );
+    }
+
+    #[test]
+    fn encode_arithmetic() {
+        let code = assemble(|asm| {
+            asm.alu(Alu::Add, R8::B)
+                .alu(Alu::Cp, R8::A)
+                .alu_n(Alu::Xor, 0xFF)
+                .inc(R8::A)
+                .dec(R8::HL)
+                .inc16(R16::DE)
+                .dec16(R16::SP)
+                .add_hl(R16::HL)
+                .add_sp(-1)
+                .push(R16::AF)
+                .pop(R16::BC);
+        });
+
+        assert_eq!(
+            code,
+            vec![0x80, 0xBF, 0xEE, 0xFF, 0x3C, 0x35, 0x13, 0x3B, 0x29, 0xE8, 0xFF, 0xF5, 0xC1]
+        );
+    }
+
+    #[test]
+    fn encode_extended() {
+        let code = assemble(|asm| {
+            asm.shift(Shift::Rlc, R8::B)
+                .shift(Shift::Swap, R8::A)
+                .shift(Shift::Srl, R8::HL)
+                .bit(7, R8::H)
+                .res(0, R8::A)
+                .set(3, R8::HL);
+        });
+
+        assert_eq!(
+            code,
+            vec![0xCB, 0x00, 0xCB, 0x37, 0xCB, 0x3E, 0xCB, 0x7C, 0xCB, 0x87, 0xCB, 0xDE]
+        );
+    }
+
+    #[test]
+    fn encode_control_flow() {
+        let code = assemble(|asm| {
+            asm.label("start")
+                .jp("start")
+                .jp_cc(Cond::Z, 0x1234)
+                .jr("end")
+                .jr_cc(Cond::NC, "start")
+                .call("start")
+                .call_cc(Cond::C, "end")
+                .ret()
+                .ret_cc(Cond::NZ)
+                .reti()
+                .rst(0x38)
+                .jp_hl()
+                .label("end");
+        });
+
+        assert_eq!(
+            code,
+            vec![
+                0xC3, 0x50, 0x01, 0xCA, 0x34, 0x12, 0x18, 0x0D, 0x30, 0xF6, 0xCD, 0x50, 0x01, 0xDC,
+                0x65, 0x01, 0xC9, 0xC0, 0xD9, 0xFF, 0xE9
+            ]
+        );
+    }
+
+    #[test]
+    fn sections_and_data() {
+        let mut asm = Assembler::new("TEST");
+        asm.section(0x0200)
+            .label("table")
+            .dw("data")
+            .bank_section(3, 0x4000)
+            .label("data")
+            .db(b"hi");
+
+        let rom = asm.build();
+
+        assert_eq!(rom.len(), 4 * BANK_SIZE);
+        assert_eq!(&rom[0x200..0x202], &[0x00, 0x40]);
+        assert_eq!(&rom[3 * BANK_SIZE..3 * BANK_SIZE + 2], b"hi");
+        assert_eq!(rom[0x147], 0x01);
+        assert_eq!(rom[0x148], 0x01);
+    }
+
+    #[test]
+    fn header() {
+        let mut asm = Assembler::new("RUSTBOY");
+        asm.halt();
+
+        let rom = asm.build();
+
+        assert_eq!(&rom[0x100..0x104], &[0x00, 0xC3, 0x50, 0x01]);
+        assert_eq!(&rom[0x104..0x134], &NINTENDO_LOGO[..]);
+        assert_eq!(&rom[0x134..0x13B], b"RUSTBOY");
+        assert_eq!(rom[0x147], 0x00);
+        assert_eq!(rom[0x148], 0x00);
+
+        let header_sum = rom[0x134..0x14D].iter().fold(rom[0x14D], |sum, &byte| {
+            sum.wrapping_add(byte).wrapping_add(1)
+        });
+        assert_eq!(header_sum, 0);
+
+        let global_sum = rom
+            .iter()
+            .enumerate()
+            .filter(|&(i, _)| i != 0x14E && i != 0x14F)
+            .fold(0u16, |sum, (_, &byte)| sum.wrapping_add(byte as u16));
+        assert_eq!(global_sum, (rom[0x14E] as u16) << 8 | rom[0x14F] as u16);
+    }
+
+    #[test]
+    #[should_panic(expected = "undefined label: nowhere")]
+    fn undefined_label() {
+        let mut asm = Assembler::new("TEST");
+        asm.jp("nowhere");
+        asm.build();
+    }
+
+    #[test]
+    #[should_panic(expected = "overlapping code at 0x0150")]
+    fn overlapping_sections() {
+        let mut asm = Assembler::new("TEST");
+        asm.nop().section(0x0150).nop();
+    }
+
+    #[test]
+    #[should_panic(expected = "out of range")]
+    fn relative_jump_out_of_range() {
+        let mut asm = Assembler::new("TEST");
+        asm.jr(0x0300);
+    }
+}
@@ -1,41 +1,80 @@
 extern crate sdl2;
 
-use std::process;
+use std::cell::Cell;
+use std::rc::Rc;
+use std::thread;
 use std::time::Duration;
 use std::time::Instant;
 
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
 use sdl2::pixels::Color as SDLColor;
+use sdl2::pixels::PixelFormatEnum;
 use sdl2::rect::Rect;
 use sdl2::render::Canvas;
+use sdl2::render::Texture;
+use sdl2::video::FullscreenType;
 use sdl2::video::Window;
 use sdl2::EventPump;
 use sdl2::Sdl;
 
-use crate::gameboy::clock::WallClock;
 use crate::gameboy::display::VideoDisplay;
+use crate::gameboy::palette::ColorPalette;
+use crate::gameboy::palette::Rgb;
 use crate::gameboy::Button;
-use crate::gameboy::Color;
 use crate::gameboy::GameBoy;
 
 const SCREEN_WIDTH: u32 = 160;
 const SCREEN_HEIGHT: u32 = 144;
 
-pub struct SDLFrontend {}
+pub struct SDLFrontend {
+    sdl_context: Sdl,
+    display_scale: u32,
+    /// Toggled with F11 and applied by the display at the next frame
+    fullscreen: Rc<Cell<bool>>,
+}
 
 impl SDLFrontend {
-    pub fn run(cartridge: &Vec<u8>, display_scale: u32) {
-        let sdl_context = sdl2::init().unwrap();
-        let mut controller = SDLController::new(&sdl_context);
+    pub fn new(display_scale: u32) -> SDLFrontend {
+        SDLFrontend {
+            sdl_context: sdl2::init().unwrap(),
+            display_scale,
+            fullscreen: Rc::new(Cell::new(false)),
+        }
+    }
 
-        let clock = WallClock::z80();
-        let display = SDLDisplay::new(display_scale, &sdl_context);
-        let mut gameboy = GameBoy::new(&cartridge, Box::new(clock), Box::new(display));
+    /// Opens the window that the GameBoy draws to
+    pub fn display(&self) -> Box<dyn VideoDisplay> {
+        Box::new(SDLDisplay::new(
+            self.display_scale,
+            &self.sdl_context,
+            self.fullscreen.clone(),
+        ))
+    }
+
+    /// Runs the GameBoy until the window is closed, in the first of the
+    /// palettes, which P cycles through. Emulation pauses at breakpoints
+    /// until F5 is pressed.
+    pub fn run(&self, gameboy: &mut GameBoy, palettes: Vec<ColorPalette>) {
+        gameboy.set_color_palette(palettes[0]);
+        let mut controller =
+            SDLController::new(&self.sdl_context, self.fullscreen.clone(), palettes);
+
+        while controller.process_input(gameboy) {
+            if controller.paused {
+                thread::sleep(controller.processing_delay);
+                continue;
+            }
 
-        loop {
-            controller.process_input(&mut gameboy);
             gameboy.step();
+
+            if gameboy.at_breakpoint() {
+                let location = gameboy.location(gameboy.registers().pc);
+                println!("[break] {}", gameboy.symbols().describe(location));
+                println!("[break] {}", gameboy.registers());
+                println!("[break] paused, F5 to continue");
+                controller.paused = true;
+            }
         }
     }
 }
@@ -44,22 +83,37 @@ struct SDLController {
     event_pump: EventPump,
     last_processed: Instant,
     processing_delay: Duration,
+    fullscreen: Rc<Cell<bool>>,
+    palettes: Vec<ColorPalette>,
+    palette_index: usize,
+    /// Set at a breakpoint and cleared with F5
+    paused: bool,
 }
 
 impl SDLController {
-    fn new(sdl_context: &Sdl) -> SDLController {
+    fn new(
+        sdl_context: &Sdl,
+        fullscreen: Rc<Cell<bool>>,
+        palettes: Vec<ColorPalette>,
+    ) -> SDLController {
         SDLController {
             event_pump: sdl_context.event_pump().unwrap(),
             last_processed: Instant::now(),
             processing_delay: Duration::from_millis(3),
+            fullscreen,
+            palettes,
+            palette_index: 0,
+            paused: false,
         }
     }
 
-    fn process_input(&mut self, gameboy: &mut GameBoy) {
+    /// Returns false once the user asked to quit
+    fn process_input(&mut self, gameboy: &mut GameBoy) -> bool {
         if self.ready_to_process() {
-            self.pump_events(gameboy);
             self.reset();
+            return self.pump_events(gameboy);
         }
+        true
     }
 
     fn ready_to_process(&self) -> bool {
@@ -70,10 +124,32 @@ impl SDLController {
         self.last_processed = Instant::now();
     }
 
-    fn pump_events(&mut self, gameboy: &mut GameBoy) {
+    fn pump_events(&mut self, gameboy: &mut GameBoy) -> bool {
         for event in self.event_pump.poll_iter() {
             match event {
-                Event::Quit { .. } => process::exit(0),
+                Event::Quit { .. } => return false,
+                Event::KeyDown {
+                    keycode: Some(Keycode::F11),
+                    repeat: false,
+                    ..
+                } => {
+                    self.fullscreen.set(!self.fullscreen.get());
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F5),
+                    repeat: false,
+                    ..
+                } => {
+                    self.paused = false;
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::P),
+                    repeat: false,
+                    ..
+                } => {
+                    self.palette_index = (self.palette_index + 1) % self.palettes.len();
+                    gameboy.set_color_palette(self.palettes[self.palette_index]);
+                }
                 Event::KeyDown {
                     keycode: Some(key), ..
                 } => {
@@ -91,6 +167,7 @@ impl SDLController {
                 _ => (),
             }
         }
+        true
     }
 
     fn key_to_button(key: Keycode) -> Option<Button> {
@@ -108,50 +185,125 @@ impl SDLController {
     }
 }
 
+/// Uploads each frame to a streaming texture, which SDL scales to the largest
+/// whole multiple of the screen size that fits the window
 struct SDLDisplay {
-    scale: u32,
     canvas: Canvas<Window>,
+    /// Destroyed along with the canvas
+    texture: Texture,
+    fullscreen: Rc<Cell<bool>>,
 }
 
 impl SDLDisplay {
-    fn new(scale: u32, sdl_context: &Sdl) -> SDLDisplay {
+    fn new(scale: u32, sdl_context: &Sdl, fullscreen: Rc<Cell<bool>>) -> SDLDisplay {
         let width = SCREEN_WIDTH * scale;
         let height = SCREEN_HEIGHT * scale;
 
         let video_subsystem = sdl_context.video().unwrap();
-        let window = video_subsystem
+        let mut window = video_subsystem
             .window("RustBoy", width, height)
             .position_centered()
+            .resizable()
             .build()
             .unwrap();
+        window
+            .set_minimum_size(SCREEN_WIDTH, SCREEN_HEIGHT)
+            .unwrap();
 
         let canvas = window.into_canvas().build().unwrap();
+        let texture = canvas
+            .create_texture_streaming(PixelFormatEnum::RGB24, SCREEN_WIDTH, SCREEN_HEIGHT)
+            .unwrap();
 
         SDLDisplay {
-            scale: scale,
-            canvas: canvas,
+            canvas,
+            texture,
+            fullscreen,
+        }
+    }
+
+    fn upload(&mut self, frame: &[Rgb]) {
+        self.texture
+            .with_lock(None, |buffer, pitch| {
+                for (i, &rgb) in frame.iter().enumerate() {
+                    let x = i % SCREEN_WIDTH as usize;
+                    let y = i / SCREEN_WIDTH as usize;
+                    let offset = y * pitch + x * 3;
+                    buffer[offset..offset + 3].copy_from_slice(&rgb.to_be_bytes()[1..]);
+                }
+            })
+            .unwrap();
+    }
+
+    fn present(&mut self) {
+        self.apply_fullscreen();
+
+        let viewport = viewport(self.canvas.output_size().unwrap());
+        self.canvas.set_draw_color(SDLColor::RGB(0, 0, 0));
+        self.canvas.clear();
+        self.canvas.copy(&self.texture, None, viewport).unwrap();
+        self.canvas.present();
+    }
+
+    fn apply_fullscreen(&mut self) {
+        let fullscreen = self.fullscreen.get();
+        let window = self.canvas.window_mut();
+        if (window.fullscreen_state() != FullscreenType::Off) == fullscreen {
+            return;
         }
+
+        let state = if fullscreen {
+            FullscreenType::Desktop
+        } else {
+            FullscreenType::Off
+        };
+        window.set_fullscreen(state).unwrap();
     }
 }
 
 impl VideoDisplay for SDLDisplay {
-    fn set_pixel(&mut self, x: u8, y: u8, color: Color) {
-        let x_pos = x as i32 * self.scale as i32;
-        let y_pos = y as i32 * self.scale as i32;
-
-        let pixel = Rect::new(x_pos, y_pos, self.scale, self.scale);
-        let sdl_color = match color {
-            Color::White => SDLColor::RGB(0x9b, 0xbc, 0x0f),
-            Color::Light => SDLColor::RGB(0x8b, 0xac, 0x0f),
-            Color::Dark => SDLColor::RGB(0x30, 0x62, 0x30),
-            Color::Black => SDLColor::RGB(0x0f, 0x38, 0x0f),
-        };
+    fn vsync(&mut self, frame: &[Rgb]) {
+        self.upload(frame);
+        self.present();
+    }
 
-        self.canvas.set_draw_color(sdl_color);
-        self.canvas.fill_rect(pixel).unwrap();
+    fn blank(&mut self, white: Rgb) {
+        let frame = [white; (SCREEN_WIDTH * SCREEN_HEIGHT) as usize];
+        self.upload(&frame);
+        self.present();
     }
+}
 
-    fn vsync(&mut self) {
-        self.canvas.present();
+/// The largest whole multiple of the screen size that fits in the output,
+/// centered in it
+fn viewport((width, height): (u32, u32)) -> Rect {
+    let scale = (width / SCREEN_WIDTH).min(height / SCREEN_HEIGHT).max(1);
+    let scaled_width = SCREEN_WIDTH * scale;
+    let scaled_height = SCREEN_HEIGHT * scale;
+
+    Rect::new(
+        (width as i32 - scaled_width as i32) / 2,
+        (height as i32 - scaled_height as i32) / 2,
+        scaled_width,
+        scaled_height,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn viewport_integer_scale() {
+        assert_eq!(viewport((320, 288)), Rect::new(0, 0, 320, 288));
+
+        // Wider than the screen: bars on the sides
+        assert_eq!(viewport((1000, 288)), Rect::new(340, 0, 320, 288));
+
+        // Between two scales: the smaller, centered
+        assert_eq!(viewport((500, 470)), Rect::new(10, 19, 480, 432));
+
+        // Never smaller than the screen itself
+        assert_eq!(viewport((100, 100)), Rect::new(-30, -22, 160, 144));
     }
 }
@@ -0,0 +1,131 @@
+/// The parts of the cartridge header at 0x0100-0x014F that the emulator
+/// looks at
+pub struct Header {
+    /// 0x0134-0x0143. Newer cartridges end it early with a manufacturer code
+    /// and the CGB flag, but the CGB boot ROM still reads all of it.
+    pub title: [u8; 16],
+    pub cgb_flag: u8,
+    pub new_licensee: [u8; 2],
+    pub cartridge_type: u8,
+    pub ram_size: u8,
+    pub old_licensee: u8,
+    pub header_checksum: u8,
+}
+
+impl Header {
+    pub fn parse(rom: &[u8]) -> Header {
+        let byte = |address: usize| rom.get(address).copied().unwrap_or(0);
+
+        let mut title = [0; 16];
+        for (i, letter) in title.iter_mut().enumerate() {
+            *letter = byte(0x134 + i);
+        }
+
+        Header {
+            title,
+            cgb_flag: byte(0x143),
+            new_licensee: [byte(0x144), byte(0x145)],
+            cartridge_type: byte(0x147),
+            ram_size: byte(0x149),
+            old_licensee: byte(0x14B),
+            header_checksum: byte(0x14D),
+        }
+    }
+
+    /// The title as text, up to the padding and without the CGB flag
+    pub fn title_text(&self) -> String {
+        let end = if self.supports_cgb() { 15 } else { 16 };
+        self.title[..end]
+            .iter()
+            .take_while(|&&letter| letter != 0)
+            .map(|&letter| letter as char)
+            .collect()
+    }
+
+    /// Games with bit 7 of the CGB flag set use CGB features when they can
+    pub fn supports_cgb(&self) -> bool {
+        self.cgb_flag & 0x80 != 0
+    }
+
+    /// The sum of the title bytes, which the CGB boot ROM picks colors for
+    /// DMG games by
+    pub fn title_checksum(&self) -> u8 {
+        self.title
+            .iter()
+            .fold(0u8, |sum, &letter| sum.wrapping_add(letter))
+    }
+
+    /// The size of the cartridge RAM in bytes, from the code at 0x0149
+    pub fn ram_bytes(&self) -> usize {
+        match self.ram_size {
+            0x01 => 0x800,
+            0x02 => 0x2000,
+            0x03 => 0x8000,
+            0x04 => 0x20000,
+            0x05 => 0x10000,
+            _ => 0,
+        }
+    }
+
+    /// Nintendo's licensee code is 0x01, or "01" when the old code is 0x33
+    /// to point to the new one
+    pub fn is_nintendo(&self) -> bool {
+        match self.old_licensee {
+            0x01 => true,
+            0x33 => &self.new_licensee == b"01",
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rom(title: &[u8], cgb_flag: u8, old_licensee: u8, new_licensee: &[u8; 2]) -> Vec<u8> {
+        let mut rom = vec![0; 0x150];
+        rom[0x134..0x134 + title.len()].copy_from_slice(title);
+        rom[0x143] = cgb_flag;
+        rom[0x144..0x146].copy_from_slice(new_licensee);
+        rom[0x147] = 0x01;
+        rom[0x14B] = old_licensee;
+        rom
+    }
+
+    #[test]
+    fn parse_header() {
+        let header = Header::parse(&rom(b"TETRIS", 0x00, 0x01, b"\0\0"));
+        assert_eq!(&header.title[..7], b"TETRIS\0");
+        assert_eq!(header.title_text(), "TETRIS");
+        assert_eq!(header.cartridge_type, 0x01);
+        assert_eq!(header.ram_bytes(), 0);
+        assert!(!header.supports_cgb());
+        assert!(header.is_nintendo());
+    }
+
+    #[test]
+    fn cgb_title() {
+        let header = Header::parse(&rom(b"POKEMON YELLOW\0\0", 0x80, 0x33, b"01"));
+        assert_eq!(header.title_text(), "POKEMON YELLOW");
+        assert!(header.supports_cgb());
+        assert!(header.is_nintendo());
+
+        // The last letter of a full length title is the CGB flag
+        let header = Header::parse(&rom(b"ABCDEFGHIJKLMNO", 0xC0, 0x33, b"08"));
+        assert_eq!(header.title_text(), "ABCDEFGHIJKLMNO");
+        assert!(!header.is_nintendo());
+    }
+
+    #[test]
+    fn ram_size() {
+        let mut data = rom(b"X", 0, 0x01, b"\0\0");
+        data[0x149] = 0x03;
+        assert_eq!(Header::parse(&data).ram_bytes(), 0x8000);
+    }
+
+    #[test]
+    fn licensee() {
+        assert!(!Header::parse(&rom(b"X", 0, 0x33, b"08")).is_nintendo());
+        assert!(!Header::parse(&rom(b"X", 0, 0x08, b"01")).is_nintendo());
+    }
+}
@@ -87,6 +87,28 @@ impl MBC for MBC1 {
             ram[relative_address + offset] = byte;
         }
     }
+
+    fn rom_bank(&self) -> usize {
+        self.get_rom_bank()
+    }
+
+    fn ram_bank(&self) -> usize {
+        self.get_ram_bank()
+    }
+
+    fn describe(&self) -> String {
+        format!(
+            "MBC1 rom bank {:02X}, ram bank {:02X}, ram {}, {:?} banking",
+            self.get_rom_bank(),
+            self.get_ram_bank(),
+            if self.ram_enabled {
+                "enabled"
+            } else {
+                "disabled"
+            },
+            self.banking_mode
+        )
+    }
 }
 
 #[cfg(test)]
@@ -215,6 +237,21 @@ mod test {
         assert_eq!(BankingMode::ROM, mbc.banking_mode);
     }
 
+    #[test]
+    fn describe() {
+        let mut mbc = MBC1::new();
+
+        mbc.write_registers(0x2000, 0x05);
+        mbc.write_registers(0x0000, 0x0A);
+        mbc.write_registers(0x6000, 0x01);
+        mbc.write_registers(0x4000, 0x02);
+
+        assert_eq!(
+            "MBC1 rom bank 05, ram bank 02, ram enabled, RAM banking",
+            mbc.describe()
+        );
+    }
+
     #[test]
     fn write_ram() {
         let mut mbc = MBC1::new();
@@ -9,6 +9,15 @@ pub trait MBC {
     fn read_ram(&self, ram: &[u8], address: u16) -> u8;
     fn write_registers(&mut self, address: u16, byte: u8);
     fn write_ram(&mut self, ram: &mut [u8], address: u16, byte: u8);
+
+    /// The ROM bank currently mapped into 0x4000-0x7FFF
+    fn rom_bank(&self) -> usize;
+
+    /// The RAM bank currently mapped into 0xA000-0xBFFF
+    fn ram_bank(&self) -> usize;
+
+    /// Describes the banking state, for crash dumps
+    fn describe(&self) -> String;
 }
 
 pub fn mbc_from_byte(byte: u8) -> Box<dyn MBC> {
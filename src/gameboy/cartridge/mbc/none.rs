@@ -20,4 +20,16 @@ impl MBC for NoMBC {
     fn write_registers(&mut self, _address: u16, _byte: u8) {}
 
     fn write_ram(&mut self, _ram: &mut [u8], _address: u16, _byte: u8) {}
+
+    fn rom_bank(&self) -> usize {
+        1
+    }
+
+    fn ram_bank(&self) -> usize {
+        0
+    }
+
+    fn describe(&self) -> String {
+        String::from("none")
+    }
 }
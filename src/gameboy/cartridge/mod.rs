@@ -1,9 +1,12 @@
+mod header;
 mod mbc;
 
+pub use self::header::Header;
 use self::mbc::mbc_from_byte;
 use self::mbc::MBC;
 
 pub struct Cartridge {
+    header: Header,
     mbc: Box<dyn MBC>,
     rom: Box<[u8]>,
     ram: Box<[u8]>,
@@ -12,14 +15,20 @@ pub struct Cartridge {
 impl Cartridge {
     pub fn new(data: Vec<u8>) -> Cartridge {
         let empty_ram = vec![0; 0x8000];
+        let header = Header::parse(&data);
 
         Cartridge {
-            mbc: mbc_from_byte(data[0x147]),
+            mbc: mbc_from_byte(header.cartridge_type),
+            header,
             rom: data.into_boxed_slice(),
             ram: empty_ram.into_boxed_slice(),
         }
     }
 
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
     pub fn read_rom_bank0(&self, address: u16) -> u8 {
         self.rom[address as usize]
     }
@@ -39,4 +48,34 @@ impl Cartridge {
     pub fn write_ram(&mut self, address: u16, byte: u8) {
         self.mbc.write_ram(&mut self.ram, address, byte)
     }
+
+    /// The offset into the ROM that an address below 0x8000 reads from
+    pub fn rom_offset(&self, address: u16) -> usize {
+        match address {
+            0x0000..=0x3FFF => address as usize,
+            _ => 0x4000 * self.rom_bank() + (address - 0x4000) as usize,
+        }
+    }
+
+    /// The offset into the RAM that an address in 0xA000-0xBFFF maps to
+    pub fn ram_offset(&self, address: u16) -> usize {
+        0x2000 * self.mbc.ram_bank() + (address - 0xA000) as usize
+    }
+
+    pub fn rom_bank(&self) -> usize {
+        self.mbc.rom_bank()
+    }
+
+    pub fn describe_mapper(&self) -> String {
+        self.mbc.describe()
+    }
+
+    /// Replaces the contents of RAM, such as with its power-on contents
+    pub fn fill_ram(&mut self, contents: &[u8]) {
+        self.ram.copy_from_slice(contents);
+    }
+
+    pub fn ram(&self) -> &[u8] {
+        &self.ram
+    }
 }
@@ -67,3 +67,17 @@ impl Clock for WallClock {
         }
     }
 }
+
+/// A clock that never waits. Useful for headless runs where emulation
+/// should proceed as fast as the host allows.
+pub struct NoClock {}
+
+impl NoClock {
+    pub fn new() -> NoClock {
+        NoClock {}
+    }
+}
+
+impl Clock for NoClock {
+    fn tick(&mut self, _cycles: u8) {}
+}
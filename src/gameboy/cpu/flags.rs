@@ -1,6 +1,6 @@
 use crate::bits;
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone, PartialEq)]
 pub struct Flags {
     pub zero: bool,
     pub subtract: bool,
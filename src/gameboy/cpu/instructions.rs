@@ -390,10 +390,7 @@ fn execute_standard(op: u8, cpu: &mut CPU, memory: &mut MemoryBus) {
         0xFB => cpu.set_ime_delayed(),
         0xFE => cp(cpu, memory, Imm8),
         0xFF => rst(cpu, memory, 0x38),
-        _ => {
-            println!("{:?}", cpu.registers);
-            panic!(format!("Unknown operation 0x{:X}", op));
-        }
+        _ => panic!("Unknown operation 0x{:X}", op),
     }
 }
 
@@ -658,10 +655,7 @@ fn execute_extended(op: u8, cpu: &mut CPU, memory: &mut MemoryBus) {
         0xFD => set(cpu, memory, L, 7),
         0xFE => set(cpu, memory, AddrHL, 7),
         0xFF => set(cpu, memory, A, 7),
-        _ => {
-            println!("{:?}", cpu.registers);
-            panic!(format!("Unknown operation 0xCB{:X}", op));
-        }
+        _ => panic!("Unknown operation 0xCB{:X}", op),
     }
 }
 
@@ -1098,6 +1092,7 @@ fn pop(cpu: &mut CPU, memory: &mut MemoryBus, loc: Loc16) {
 fn rst(cpu: &mut CPU, memory: &mut MemoryBus, new_pc: u16) {
     push(cpu, memory, Loc16::PC);
     cpu.registers.pc = new_pc;
+    cpu.record_call(memory, cpu.last_pc, false);
 }
 
 fn call(cpu: &mut CPU, memory: &mut MemoryBus, check: Check) {
@@ -1107,12 +1102,14 @@ fn call(cpu: &mut CPU, memory: &mut MemoryBus, check: Check) {
     if check.evaluate(cpu) {
         push(cpu, memory, Loc16::PC);
         cpu.registers.pc = address;
+        cpu.record_call(memory, cpu.last_pc, false);
     }
 }
 
 fn ret(cpu: &mut CPU, memory: &mut MemoryBus, check: Check) {
     if check.evaluate(cpu) {
         pop(cpu, memory, Loc16::PC);
+        cpu.record_return();
     }
 }
 
@@ -2,15 +2,35 @@ mod flags;
 mod instructions;
 mod registers;
 
-use self::registers::Registers;
+pub use self::registers::Registers;
 
 use crate::bits;
+use crate::gameboy::debug::callstack::CallStack;
+use crate::gameboy::debug::callstack::StackFrame;
+use crate::gameboy::debug::Location;
+
+/// The reason the CPU is reading from the instruction stream
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Fetch {
+    Opcode,
+    Operand,
+}
 
 pub trait MemoryBus {
     fn ack_interrupt(&mut self) -> Option<u16>;
     fn get_byte(&mut self, address: u16) -> u8;
     fn set_byte(&mut self, address: u16, byte: u8);
 
+    /// The ROM bank mapped into 0x4000-0x7FFF, which tells apart code at the
+    /// same address in different banks
+    fn rom_bank(&self) -> usize;
+
+    /// Reads a byte of the instruction stream. Behaves like `get_byte` unless
+    /// the bus keeps track of how memory is used.
+    fn fetch_byte(&mut self, address: u16, _fetch: Fetch) -> u8 {
+        self.get_byte(address)
+    }
+
     fn get_word(&mut self, address: u16) -> u16 {
         let lsb = self.get_byte(address);
         let msb = self.get_byte(address.wrapping_add(1));
@@ -23,6 +43,16 @@ pub trait MemoryBus {
     }
 }
 
+/// A change in control flow caused by the most recent step, used by the
+/// debugging tools to follow subroutine entries and exits. The stack pointer
+/// is recorded after the return address was pushed or popped.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Flow {
+    Call { target: u16, sp: u16 },
+    Interrupt { target: u16, sp: u16 },
+    Return { sp: u16 },
+}
+
 #[derive(Default)]
 pub struct CPU {
     registers: Registers,
@@ -31,23 +61,88 @@ pub struct CPU {
     // hardware implementation since they were a part of the CPU on the Gameboy.
     ime: bool,
     ime_queued: bool,
+
+    last_op_code: u8,
+    last_pc: u16,
+    flow: Vec<Flow>,
+    call_stack: CallStack<StackFrame>,
 }
 
 impl CPU {
     pub fn step(&mut self, bus: &mut MemoryBus) {
+        self.flow.clear();
         self.service_interrupts(bus);
         if self.ime_queued {
             self.ime = true;
             self.ime_queued = false;
         }
 
-        let op_code = self.get_byte(bus);
+        self.last_pc = self.registers.pc;
+        let op_code = self.fetch(bus, Fetch::Opcode);
+        self.last_op_code = op_code;
         instructions::execute(op_code, self, bus);
     }
 
+    pub fn get_registers(&self) -> &Registers {
+        &self.registers
+    }
+
+    pub fn set_registers(&mut self, registers: Registers) {
+        self.registers = registers;
+    }
+
+    /// Returns the op code of the most recently executed instruction. For
+    /// extended instructions this is the 0xCB prefix.
+    pub fn get_last_op_code(&self) -> u8 {
+        self.last_op_code
+    }
+
+    pub fn get_ime(&self) -> bool {
+        self.ime
+    }
+
+    /// Returns the address of the most recently executed instruction
+    pub fn get_last_pc(&self) -> u16 {
+        self.last_pc
+    }
+
+    /// Returns the control flow changes made by the most recent step, in
+    /// the order they happened
+    pub fn get_flow(&self) -> &[Flow] {
+        &self.flow
+    }
+
+    /// Returns the subroutines that are currently active, as far as can be
+    /// told from calls and returns
+    pub fn get_call_stack(&self) -> &CallStack<StackFrame> {
+        &self.call_stack
+    }
+
+    /// Follows a call, reset or interrupt that just jumped to the program
+    /// counter from the instruction at `caller`
+    pub fn record_call(&mut self, bus: &dyn MemoryBus, caller: u16, interrupt: bool) {
+        let target = self.registers.pc;
+        let sp = self.registers.sp;
+
+        if interrupt {
+            self.flow.push(Flow::Interrupt { target, sp });
+        } else {
+            self.flow.push(Flow::Call { target, sp });
+        }
+
+        let caller = Location::at(caller, bus.rom_bank());
+        self.call_stack.enter(sp, StackFrame { caller, interrupt });
+    }
+
+    /// Follows a return that just popped the program counter
+    pub fn record_return(&mut self) {
+        let sp = self.registers.sp;
+        self.flow.push(Flow::Return { sp });
+        self.call_stack.exit(sp);
+    }
+
     pub fn get_byte(&mut self, bus: &mut MemoryBus) -> u8 {
-        let old_pc = self.registers.increment_pc();
-        bus.get_byte(old_pc)
+        self.fetch(bus, Fetch::Operand)
     }
 
     pub fn get_word(&mut self, bus: &mut MemoryBus) -> u16 {
@@ -70,15 +165,22 @@ impl CPU {
         self.ime_queued = false;
     }
 
+    fn fetch(&mut self, bus: &mut dyn MemoryBus, fetch: Fetch) -> u8 {
+        let old_pc = self.registers.increment_pc();
+        bus.fetch_byte(old_pc, fetch)
+    }
+
     fn service_interrupts(&mut self, bus: &mut MemoryBus) {
         if !self.ime {
             return;
         }
 
         bus.ack_interrupt().map(|address| {
+            let interrupted = self.registers.pc;
             self.reset_ime();
             self.push_pc_onto_stack(bus);
             self.registers.pc = address;
+            self.record_call(bus, interrupted, true);
         });
     }
 
@@ -1,8 +1,10 @@
+use std::fmt;
+
 use crate::bits;
 
 use super::flags::Flags;
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone, PartialEq)]
 pub struct Registers {
     pub pc: u16,
     pub sp: u16,
@@ -80,6 +82,21 @@ impl Registers {
     }
 }
 
+impl fmt::Display for Registers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "AF={:04X} BC={:04X} DE={:04X} HL={:04X} SP={:04X} PC={:04X}",
+            self.get_af(),
+            self.get_bc(),
+            self.get_de(),
+            self.get_hl(),
+            self.sp,
+            self.pc
+        )
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -157,4 +174,21 @@ mod test {
         assert_eq!(regs.l, 0x23);
         assert_eq!(regs.get_hl(), 0xF123);
     }
+
+    #[test]
+    fn display() {
+        let mut regs = Registers::default();
+
+        regs.set_af(0x01B0);
+        regs.set_bc(0x0013);
+        regs.set_de(0x00D8);
+        regs.set_hl(0x014D);
+        regs.sp = 0xFFFE;
+        regs.pc = 0x0100;
+
+        assert_eq!(
+            "AF=01B0 BC=0013 DE=00D8 HL=014D SP=FFFE PC=0100",
+            regs.to_string()
+        );
+    }
 }
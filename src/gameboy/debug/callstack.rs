@@ -0,0 +1,149 @@
+use super::symbols::Symbols;
+use super::Location;
+
+/// Follows subroutines by the stack pointer at which their return address
+/// was pushed. A frame is dropped as soon as the stack pointer moves above its
+/// return address, so code that discards return addresses or jumps through
+/// `push; ret` doesn't leave stale frames behind.
+pub struct CallStack<T> {
+    frames: Vec<(u16, T)>,
+}
+
+impl<T> CallStack<T> {
+    pub fn new() -> CallStack<T> {
+        CallStack { frames: Vec::new() }
+    }
+
+    /// Enters a subroutine whose return address was just pushed to `sp`
+    pub fn enter(&mut self, sp: u16, frame: T) {
+        self.overwrite(sp);
+        self.frames.push((sp, frame));
+    }
+
+    /// Drops every frame whose return address was overwritten by a push to
+    /// `sp`, which are the frames at or below it
+    pub fn overwrite(&mut self, sp: u16) {
+        self.unwind(|frame_sp| frame_sp <= sp);
+    }
+
+    /// Leaves every subroutine whose return address is below `sp`
+    pub fn exit(&mut self, sp: u16) {
+        self.unwind(|frame_sp| frame_sp < sp);
+    }
+
+    pub fn top(&self) -> Option<&T> {
+        self.frames.last().map(|(_, frame)| frame)
+    }
+
+    /// Active frames, innermost first
+    pub fn frames(&self) -> impl Iterator<Item = &T> {
+        self.frames.iter().rev().map(|(_, frame)| frame)
+    }
+
+    fn unwind<F: Fn(u16) -> bool>(&mut self, is_dead: F) {
+        while self.frames.last().is_some_and(|(sp, _)| is_dead(*sp)) {
+            self.frames.pop();
+        }
+    }
+}
+
+impl<T> Default for CallStack<T> {
+    fn default() -> CallStack<T> {
+        CallStack::new()
+    }
+}
+
+/// A subroutine entered by a call, reset or interrupt from the caller. For
+/// interrupts the caller is the instruction that was about to execute.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct StackFrame {
+    pub caller: Location,
+    pub interrupt: bool,
+}
+
+/// Describes the current location and the call site of every active frame,
+/// innermost first
+pub fn backtrace(current: Location, stack: &CallStack<StackFrame>, symbols: &Symbols) -> String {
+    let mut lines = vec![String::from("backtrace:")];
+    lines.push(describe_frame(0, current, symbols));
+
+    for (depth, frame) in stack.frames().enumerate() {
+        let mut line = describe_frame(depth + 1, frame.caller, symbols);
+        if frame.interrupt {
+            line.push_str(" (interrupted)");
+        }
+        lines.push(line);
+    }
+
+    lines.join("\n")
+}
+
+fn describe_frame(depth: usize, location: Location, symbols: &Symbols) -> String {
+    let name = symbols.describe(location);
+    if name == location.to_string() {
+        format!("  #{} {}", depth, location)
+    } else {
+        format!("  #{} {} {}", depth, location, name)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn frame(caller: u16, interrupt: bool) -> StackFrame {
+        StackFrame {
+            caller: Location::at(caller, 1),
+            interrupt,
+        }
+    }
+
+    #[test]
+    fn enter_and_exit() {
+        let mut stack = CallStack::new();
+
+        stack.enter(0xFFFC, 1);
+        stack.enter(0xFFFA, 2);
+        assert_eq!(vec![&2, &1], stack.frames().collect::<Vec<_>>());
+
+        stack.exit(0xFFFC);
+        assert_eq!(Some(&1), stack.top());
+
+        stack.exit(0xFFFE);
+        assert_eq!(None, stack.top());
+    }
+
+    #[test]
+    fn drops_discarded_frames() {
+        let mut stack = CallStack::new();
+
+        stack.enter(0xFFFC, 1);
+        stack.enter(0xFFF8, 2);
+
+        // `push hl; ret` inside a subroutine keeps it
+        stack.exit(0xFFF8);
+        assert_eq!(Some(&2), stack.top());
+
+        // Resetting the stack pointer and calling again drops both frames
+        stack.enter(0xFFFC, 3);
+        assert_eq!(vec![&3], stack.frames().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn symbolized_backtrace() {
+        let symbols = Symbols::parse("00:0150 Main\n01:4000 PlayerUpdate\n");
+        let mut stack = CallStack::new();
+
+        stack.enter(0xFFFC, frame(0x0153, false));
+        stack.enter(0xFFFA, frame(0x4010, true));
+
+        let expected = "backtrace:
+  #0 00:0049
+  #1 01:4010 PlayerUpdate+0x10 (interrupted)
+  #2 00:0153 Main+0x3";
+        assert_eq!(
+            expected,
+            backtrace(Location::new(0, 0x0049), &stack, &symbols)
+        );
+    }
+}
@@ -0,0 +1,454 @@
+/*
+ * Code/data logs use the flags of BizHawk's Game Boy core (Gambatte), which
+ * tell opcodes from operands, and are saved in BizHawk's container so that
+ * its CDL tool can open and merge them. The container is what .NET's
+ * BinaryWriter writes: the "BIZHAWK-CDL-2" id, the core's subtype padded to
+ * 15 characters, a 32-bit count of blocks, and then each block as its name,
+ * a 32-bit length and one byte of flags per byte of memory. Strings start
+ * with their length in 7-bit groups and all integers are little endian.
+ */
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// The byte was executed as the first byte of an instruction
+pub const OPCODE: u8 = 0x01;
+/// The byte was executed as an operand of an instruction
+pub const OPERAND: u8 = 0x02;
+/// The byte was read as data, by an instruction or by OAM DMA
+pub const DATA: u8 = 0x04;
+/// The byte was executed, either as an opcode or as an operand
+pub const CODE: u8 = OPCODE | OPERAND;
+
+const ID: &str = "BIZHAWK-CDL-2";
+const SUBTYPE: &str = "GB";
+const SUBTYPE_LEN: usize = 15;
+
+const HRAM_SIZE: usize = 0x80;
+const WRAM_SIZE: usize = 0x2000;
+
+/// The memories that BizHawk's Game Boy core keeps a block of flags for
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Block {
+    Rom,
+    Hram,
+    Wram,
+    CartRam,
+}
+
+impl Block {
+    const ALL: [Block; 4] = [Block::Rom, Block::Hram, Block::Wram, Block::CartRam];
+
+    /// The name that the block is saved under
+    fn name(self) -> &'static str {
+        match self {
+            Block::Rom => "ROM",
+            Block::Hram => "HRAM",
+            Block::Wram => "WRAM",
+            Block::CartRam => "CartRAM",
+        }
+    }
+}
+
+/// A code/data log: one byte of flags per byte of ROM, HRAM, WRAM and
+/// cartridge RAM, the last only when the cartridge has any. Accesses to a
+/// switchable region are logged at the offset of the bank that was mapped at
+/// the time, so the bank of every byte is its offset divided by the size of
+/// the region.
+pub struct CodeDataLog {
+    blocks: Vec<(Block, Vec<u8>)>,
+}
+
+/// Number of ROM bytes with each kind of use
+#[derive(Debug, PartialEq)]
+pub struct Coverage {
+    pub code: usize,
+    pub data: usize,
+    pub used: usize,
+    pub total: usize,
+}
+
+impl CodeDataLog {
+    pub fn new(rom_size: usize, cart_ram_size: usize) -> CodeDataLog {
+        let mut blocks = vec![
+            (Block::Rom, vec![0; rom_size]),
+            (Block::Hram, vec![0; HRAM_SIZE]),
+            (Block::Wram, vec![0; WRAM_SIZE]),
+        ];
+        if cart_ram_size > 0 {
+            blocks.push((Block::CartRam, vec![0; cart_ram_size]));
+        }
+
+        CodeDataLog { blocks }
+    }
+
+    /// Continues an existing log so that coverage adds up across runs, or
+    /// starts a new one if there is no log at the path yet
+    pub fn open(path: &Path, rom_size: usize, cart_ram_size: usize) -> io::Result<CodeDataLog> {
+        let data = match fs::read(path) {
+            Ok(data) => data,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                return Ok(Self::new(rom_size, cart_ram_size))
+            }
+            Err(e) => return Err(e),
+        };
+
+        let mut cdl = Self::new(rom_size, cart_ram_size);
+        cdl.read(&data).map_err(|message| {
+            let message = format!("{}: {}", path.display(), message);
+            io::Error::new(io::ErrorKind::InvalidData, message)
+        })?;
+
+        Ok(cdl)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.to_bytes())
+    }
+
+    /// Adds flags to the byte at an offset into a block. Offsets past the
+    /// end of the block, and blocks that the cartridge doesn't have, are
+    /// ignored.
+    pub fn mark(&mut self, block: Block, offset: usize, flags: u8) {
+        if let Some(byte) = self.block_mut(block).and_then(|b| b.get_mut(offset)) {
+            *byte |= flags;
+        }
+    }
+
+    /// The flags of a block, which are empty if the cartridge doesn't have
+    /// that memory
+    pub fn flags(&self, block: Block) -> &[u8] {
+        self.blocks
+            .iter()
+            .find(|(b, _)| *b == block)
+            .map_or(&[], |(_, flags)| &flags[..])
+    }
+
+    pub fn coverage(&self) -> Coverage {
+        let rom = self.flags(Block::Rom);
+        let count = |mask: u8| rom.iter().filter(|f| *f & mask != 0).count();
+
+        Coverage {
+            code: count(CODE),
+            data: count(DATA),
+            used: count(CODE | DATA),
+            total: rom.len(),
+        }
+    }
+
+    fn block_mut(&mut self, block: Block) -> Option<&mut Vec<u8>> {
+        self.blocks
+            .iter_mut()
+            .find(|(b, _)| *b == block)
+            .map(|(_, flags)| flags)
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        write_string(&mut data, ID);
+        write_string(&mut data, &format!("{:1$}", SUBTYPE, SUBTYPE_LEN));
+        data.extend_from_slice(&(self.blocks.len() as i32).to_le_bytes());
+
+        for (block, flags) in &self.blocks {
+            write_string(&mut data, block.name());
+            data.extend_from_slice(&(flags.len() as i32).to_le_bytes());
+            data.extend_from_slice(flags);
+        }
+
+        data
+    }
+
+    /// Fills in the blocks from a saved log, which must have been logged
+    /// from a cartridge with the same sizes of ROM and RAM
+    fn read(&mut self, data: &[u8]) -> Result<(), String> {
+        let mut reader = Reader { data, position: 0 };
+        if reader.string()? != ID {
+            return Err(String::from("not a BizHawk code/data log"));
+        }
+
+        let subtype = reader.string()?;
+        if subtype.trim_end() != SUBTYPE {
+            return Err(format!("logged by the {} core", subtype.trim_end()));
+        }
+
+        for _ in 0..reader.u32()? {
+            let name = reader.string()?;
+            let length = reader.u32()? as usize;
+            let flags = reader.bytes(length)?;
+
+            let block = Block::ALL.iter().find(|b| b.name() == name);
+            match block.and_then(|&b| self.block_mut(b)) {
+                Some(existing) if existing.len() == length => existing.copy_from_slice(flags),
+                Some(existing) => {
+                    return Err(format!(
+                        "the {} block is {} bytes but {} were expected",
+                        name,
+                        length,
+                        existing.len()
+                    ))
+                }
+                None => return Err(format!("unexpected {} block", name)),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Writes a string the way BinaryWriter does, after its length in UTF-8
+/// bytes, 7 bits at a time with the top bit set on all but the last group
+fn write_string(data: &mut Vec<u8>, string: &str) {
+    let mut length = string.len();
+    while length >= 0x80 {
+        data.push(length as u8 | 0x80);
+        length >>= 7;
+    }
+    data.push(length as u8);
+    data.extend_from_slice(string.as_bytes());
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, length: usize) -> Result<&'a [u8], String> {
+        let end = self.position.saturating_add(length);
+        let bytes = self
+            .data
+            .get(self.position..end)
+            .ok_or_else(|| String::from("the log ends early"))?;
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let mut length = 0;
+        for shift in (0..35).step_by(7) {
+            let byte = self.bytes(1)?[0];
+            length |= ((byte & 0x7F) as usize) << shift;
+            if byte & 0x80 == 0 {
+                let bytes = self.bytes(length)?;
+                return String::from_utf8(bytes.to_vec()).map_err(|e| e.to_string());
+            }
+        }
+        Err(String::from("a string length is too long"))
+    }
+}
+
+impl fmt::Display for Coverage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let percent = |n: usize| n as f64 * 100.0 / self.total.max(1) as f64;
+
+        write!(
+            f,
+            "{} code bytes ({:.2}%), {} data bytes ({:.2}%), {:.2}% of the ROM used",
+            self.code,
+            percent(self.code),
+            self.data,
+            percent(self.data),
+            percent(self.used)
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::asm::Assembler;
+    use crate::asm::Ind;
+    use crate::asm::Target;
+    use crate::asm::R16;
+    use crate::asm::R8;
+    use crate::gameboy::clock::NoClock;
+    use crate::gameboy::display::NoDisplay;
+    use crate::gameboy::GameBoy;
+    use crate::gameboy::Model;
+    use crate::gameboy::Renderer;
+    use std::env;
+
+    #[test]
+    fn mark_combines_flags() {
+        let mut cdl = CodeDataLog::new(4, 0);
+
+        cdl.mark(Block::Rom, 1, OPCODE);
+        cdl.mark(Block::Rom, 1, DATA);
+        cdl.mark(Block::Rom, 2, OPERAND);
+        cdl.mark(Block::Rom, 4, DATA);
+        cdl.mark(Block::Wram, 2, DATA);
+        cdl.mark(Block::CartRam, 0, DATA);
+
+        assert_eq!(&[0x00, 0x05, 0x02, 0x00], cdl.flags(Block::Rom));
+        assert_eq!(DATA, cdl.flags(Block::Wram)[2]);
+        assert!(cdl.flags(Block::CartRam).is_empty());
+    }
+
+    #[test]
+    fn coverage() {
+        let mut cdl = CodeDataLog::new(8, 0);
+
+        cdl.mark(Block::Rom, 0, OPCODE);
+        cdl.mark(Block::Rom, 1, OPERAND | DATA);
+        cdl.mark(Block::Rom, 2, DATA);
+        cdl.mark(Block::Hram, 0, DATA);
+
+        let coverage = cdl.coverage();
+        assert_eq!(
+            Coverage {
+                code: 2,
+                data: 2,
+                used: 3,
+                total: 8
+            },
+            coverage
+        );
+        assert_eq!(
+            "2 code bytes (25.00%), 2 data bytes (25.00%), 37.50% of the ROM used",
+            coverage.to_string()
+        );
+    }
+
+    #[test]
+    fn bizhawk_container() {
+        let mut cdl = CodeDataLog::new(4, 0x800);
+        cdl.mark(Block::Rom, 1, OPCODE);
+        cdl.mark(Block::Hram, 0x7F, DATA);
+        cdl.mark(Block::Wram, 2, OPERAND);
+        cdl.mark(Block::CartRam, 3, DATA);
+
+        let mut expected = vec![0x0D];
+        expected.extend_from_slice(b"BIZHAWK-CDL-2");
+        expected.push(0x0F);
+        expected.extend_from_slice(b"GB             ");
+        expected.extend_from_slice(&[4, 0, 0, 0]);
+
+        expected.push(0x03);
+        expected.extend_from_slice(b"ROM");
+        expected.extend_from_slice(&[4, 0, 0, 0]);
+        expected.extend_from_slice(&[0, OPCODE, 0, 0]);
+
+        let mut hram = vec![0; 0x80];
+        hram[0x7F] = DATA;
+        expected.push(0x04);
+        expected.extend_from_slice(b"HRAM");
+        expected.extend_from_slice(&[0x80, 0, 0, 0]);
+        expected.extend_from_slice(&hram);
+
+        let mut wram = vec![0; 0x2000];
+        wram[2] = OPERAND;
+        expected.push(0x04);
+        expected.extend_from_slice(b"WRAM");
+        expected.extend_from_slice(&[0x00, 0x20, 0, 0]);
+        expected.extend_from_slice(&wram);
+
+        let mut cart_ram = vec![0; 0x800];
+        cart_ram[3] = DATA;
+        expected.push(0x07);
+        expected.extend_from_slice(b"CartRAM");
+        expected.extend_from_slice(&[0x00, 0x08, 0, 0]);
+        expected.extend_from_slice(&cart_ram);
+
+        assert_eq!(expected, cdl.to_bytes());
+
+        // Without cartridge RAM there are only three blocks
+        let data = CodeDataLog::new(4, 0).to_bytes();
+        assert_eq!(&[3, 0, 0, 0], &data[30..34]);
+        assert_eq!(34 + 8 + 4 + 9 + 0x80 + 9 + 0x2000, data.len());
+    }
+
+    #[test]
+    fn long_string_length() {
+        let mut data = Vec::new();
+        write_string(&mut data, &"x".repeat(200));
+        assert_eq!(&[0xC8, 0x01], &data[..2]);
+
+        let mut reader = Reader {
+            data: &data,
+            position: 0,
+        };
+        assert_eq!(200, reader.string().unwrap().len());
+    }
+
+    #[test]
+    fn open_continues_existing_log() {
+        let path = env::temp_dir().join(format!("rustboy-{}.cdl", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mut cdl = CodeDataLog::open(&path, 4, 0x2000).unwrap();
+        assert_eq!(0, cdl.coverage().used);
+
+        cdl.mark(Block::Rom, 3, DATA);
+        cdl.mark(Block::CartRam, 0x1FFF, OPCODE);
+        cdl.save(&path).unwrap();
+
+        let cdl = CodeDataLog::open(&path, 4, 0x2000).unwrap();
+        assert_eq!(DATA, cdl.flags(Block::Rom)[3]);
+        assert_eq!(OPCODE, cdl.flags(Block::CartRam)[0x1FFF]);
+
+        for (rom_size, cart_ram_size) in &[(8, 0x2000), (4, 0x8000), (4, 0)] {
+            let error = CodeDataLog::open(&path, *rom_size, *cart_ram_size);
+            assert_eq!(io::ErrorKind::InvalidData, error.err().unwrap().kind());
+        }
+
+        // A flat log without the container
+        fs::write(&path, [0; 4]).unwrap();
+        let error = CodeDataLog::open(&path, 4, 0).err().unwrap();
+        assert_eq!(io::ErrorKind::InvalidData, error.kind());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn logs_a_running_game() {
+        let mut asm = Assembler::new("CDL");
+        asm.section(0x150)
+            .ld_n(R8::A, 2)
+            .ld_ind_a(Ind::Addr(Target::Address(0x2000)))
+            .ld16(R16::HL, 0x4000)
+            .ld_a_ind(Ind::HLInc)
+            .ld_a_ind(Ind::High(0x80))
+            .ld_a_ind(Ind::Addr(Target::Address(0xE123)))
+            .label("done")
+            .jr("done");
+        asm.bank_section(2, 0x4000).db(&[0xAB]);
+        asm.bank_section(3, 0x7FFF).db(&[0xCD]);
+
+        let rom = asm.build();
+        let mut gameboy = GameBoy::new(
+            &rom,
+            Model::Dmg,
+            Renderer::default(),
+            Box::new(NoClock::new()),
+            Box::new(NoDisplay::new()),
+        );
+        gameboy.enable_cdl(CodeDataLog::new(rom.len(), 0));
+
+        while gameboy.registers().pc != 0x015E {
+            gameboy.step();
+        }
+
+        let cdl = gameboy.cdl().unwrap();
+        let rom = cdl.flags(Block::Rom);
+        // The boot ROM overlays the cartridge, but the header is checked
+        assert_eq!(0, rom[0x0000]);
+        assert_eq!(DATA, rom[0x0104]);
+        assert_eq!(OPCODE, rom[0x0150]);
+        assert_eq!(OPERAND, rom[0x0151]);
+        assert_eq!(OPERAND, rom[0x0154]);
+        assert_eq!(DATA, rom[0x8000]);
+        assert_eq!(0, rom[0x4000]);
+        assert_eq!(0, rom[0xFFFF]);
+
+        assert_eq!(DATA, cdl.flags(Block::Hram)[0x00]);
+        // Echo RAM is logged at the WRAM it mirrors
+        assert_eq!(DATA, cdl.flags(Block::Wram)[0x0123]);
+    }
+}
@@ -0,0 +1,342 @@
+use std::any::Any;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+
+use crate::gameboy::cpu::Registers;
+
+const HEADER: &str = "rustboy crash dump";
+const BYTES_PER_LINE: usize = 32;
+
+/// A copy of one area of memory
+#[derive(Debug, PartialEq)]
+pub struct MemoryRegion {
+    pub name: String,
+    pub start: u16,
+    pub bytes: Vec<u8>,
+}
+
+/// Everything known about the machine when emulation faulted. Dumps are
+/// plain text so they can be attached to bug reports and read as they are,
+/// and can be loaded back for post-mortem inspection.
+#[derive(Debug, PartialEq)]
+pub struct CrashDump {
+    pub message: String,
+    pub registers: Registers,
+    pub ime: bool,
+    pub interrupt_enable: u8,
+    pub interrupt_flags: u8,
+    pub lcd_control: u8,
+    pub lcd_status: u8,
+    pub ly: u8,
+    pub mapper: String,
+    pub ram_init: String,
+    pub backtrace: Vec<String>,
+    pub trace: Vec<String>,
+    pub memory: Vec<MemoryRegion>,
+}
+
+impl CrashDump {
+    pub fn load(path: &Path) -> io::Result<CrashDump> {
+        CrashDump::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut out = io::BufWriter::new(fs::File::create(path)?);
+        self.write(&mut out)?;
+        out.flush()
+    }
+
+    /// Writes the dump without its memory, as shown by post-mortem inspection
+    pub fn write_summary(&self, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(out, "{}", HEADER)?;
+        writeln!(out, "message: {}", self.message.replace('\n', " "))?;
+        writeln!(out, "registers: {}", self.registers)?;
+        writeln!(out, "ime: {}", self.ime as u8)?;
+        writeln!(out, "ie: {:02X}", self.interrupt_enable)?;
+        writeln!(out, "if: {:02X}", self.interrupt_flags)?;
+        writeln!(out, "lcdc: {:02X}", self.lcd_control)?;
+        writeln!(out, "stat: {:02X}", self.lcd_status)?;
+        writeln!(out, "ppu mode: {}", self.lcd_status & 0b11)?;
+        writeln!(out, "ly: {:02X}", self.ly)?;
+        writeln!(out, "mapper: {}", self.mapper)?;
+        writeln!(out, "ram init: {}", self.ram_init)?;
+
+        writeln!(out, "[backtrace]")?;
+        for line in &self.backtrace {
+            writeln!(out, "{}", line)?;
+        }
+
+        writeln!(out, "[trace]")?;
+        for line in &self.trace {
+            writeln!(out, "{}", line)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn write(&self, out: &mut dyn Write) -> io::Result<()> {
+        self.write_summary(out)?;
+
+        for region in &self.memory {
+            writeln!(out, "[memory {} {:04X}]", region.name, region.start)?;
+            write_hex(out, region)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn parse(text: &str) -> io::Result<CrashDump> {
+        let mut lines = text.lines();
+        if lines.next() != Some(HEADER) {
+            return Err(invalid("not a crash dump"));
+        }
+
+        let mut dump = CrashDump {
+            message: String::new(),
+            registers: Registers::default(),
+            ime: false,
+            interrupt_enable: 0,
+            interrupt_flags: 0,
+            lcd_control: 0,
+            lcd_status: 0,
+            ly: 0,
+            mapper: String::new(),
+            ram_init: String::new(),
+            backtrace: Vec::new(),
+            trace: Vec::new(),
+            memory: Vec::new(),
+        };
+
+        let mut section = "";
+        for line in lines {
+            if line.starts_with('[') {
+                section = line.trim_start_matches('[').trim_end_matches(']');
+                if section.starts_with("memory ") {
+                    dump.memory.push(parse_region_header(section)?);
+                }
+                continue;
+            }
+
+            match section {
+                "" => dump.parse_field(line)?,
+                "backtrace" => dump.backtrace.push(line.to_string()),
+                "trace" => dump.trace.push(line.to_string()),
+                _ if section.starts_with("memory ") => {
+                    let region = dump.memory.last_mut().ok_or_else(|| invalid(line))?;
+                    let bytes = line.split(':').nth(1).ok_or_else(|| invalid(line))?;
+                    for byte in bytes.split_whitespace() {
+                        region.bytes.push(parse_hex(byte)? as u8);
+                    }
+                }
+                _ => return Err(invalid(line)),
+            }
+        }
+
+        Ok(dump)
+    }
+
+    pub fn region(&self, name: &str) -> Option<&MemoryRegion> {
+        self.memory.iter().find(|region| region.name == name)
+    }
+
+    fn parse_field(&mut self, line: &str) -> io::Result<()> {
+        let mut parts = line.splitn(2, ": ");
+        let key = parts.next().unwrap();
+        let value = parts.next().ok_or_else(|| invalid(line))?;
+
+        match key {
+            "message" => self.message = value.to_string(),
+            "registers" => self.registers = parse_registers(value)?,
+            "ime" => self.ime = value == "1",
+            "ie" => self.interrupt_enable = parse_hex(value)? as u8,
+            "if" => self.interrupt_flags = parse_hex(value)? as u8,
+            "lcdc" => self.lcd_control = parse_hex(value)? as u8,
+            "stat" => self.lcd_status = parse_hex(value)? as u8,
+            "ly" => self.ly = parse_hex(value)? as u8,
+            "mapper" => self.mapper = value.to_string(),
+            "ram init" => self.ram_init = value.to_string(),
+            // Derived from other fields
+            "ppu mode" => (),
+            _ => return Err(invalid(line)),
+        }
+
+        Ok(())
+    }
+}
+
+/// Writes a region as lines of hex bytes, each starting with its address
+pub fn write_hex(out: &mut dyn Write, region: &MemoryRegion) -> io::Result<()> {
+    for (i, chunk) in region.bytes.chunks(BYTES_PER_LINE).enumerate() {
+        let address = region.start as usize + i * BYTES_PER_LINE;
+        let bytes: Vec<String> = chunk.iter().map(|b| format!("{:02X}", b)).collect();
+        writeln!(out, "{:04X}: {}", address, bytes.join(" "))?;
+    }
+
+    Ok(())
+}
+
+/// Gets the message of a caught panic
+pub fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        String::from("unknown panic")
+    }
+}
+
+fn parse_region_header(section: &str) -> io::Result<MemoryRegion> {
+    let parts: Vec<&str> = section.split_whitespace().collect();
+    match parts.as_slice() {
+        ["memory", name, start] => Ok(MemoryRegion {
+            name: name.to_string(),
+            start: parse_hex(start)? as u16,
+            bytes: Vec::new(),
+        }),
+        _ => Err(invalid(section)),
+    }
+}
+
+fn parse_registers(value: &str) -> io::Result<Registers> {
+    let mut registers = Registers::default();
+
+    for pair in value.split_whitespace() {
+        let mut parts = pair.splitn(2, '=');
+        let name = parts.next().unwrap();
+        let value = parse_hex(parts.next().ok_or_else(|| invalid(pair))?)? as u16;
+
+        match name {
+            "AF" => registers.set_af(value),
+            "BC" => registers.set_bc(value),
+            "DE" => registers.set_de(value),
+            "HL" => registers.set_hl(value),
+            "SP" => registers.sp = value,
+            "PC" => registers.pc = value,
+            _ => return Err(invalid(pair)),
+        }
+    }
+
+    Ok(registers)
+}
+
+fn parse_hex(value: &str) -> io::Result<u32> {
+    u32::from_str_radix(value, 16).map_err(|_| invalid(value))
+}
+
+fn invalid(text: &str) -> io::Error {
+    let message = format!("invalid crash dump: {}", text);
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn dump() -> CrashDump {
+        let mut registers = Registers::default();
+        registers.set_af(0x01B0);
+        registers.set_hl(0xC0DE);
+        registers.sp = 0xDFFC;
+        registers.pc = 0x4002;
+
+        CrashDump {
+            message: String::from("Unknown operation 0xD3"),
+            registers,
+            ime: true,
+            interrupt_enable: 0x01,
+            interrupt_flags: 0xE1,
+            lcd_control: 0x91,
+            lcd_status: 0x83,
+            ly: 0x12,
+            mapper: String::from("MBC1 rom bank 01, ram bank 00, ram disabled, rom banking"),
+            ram_init: String::from("random:0x2A"),
+            backtrace: vec![String::from("backtrace:"), String::from("  #0 01:4001")],
+            trace: vec![String::from(
+                "01:4001 D3 AF=01B0 BC=0000 DE=0000 HL=C0DE SP=DFFC PC=4002",
+            )],
+            memory: vec![
+                MemoryRegion {
+                    name: String::from("hram"),
+                    start: 0xFF80,
+                    bytes: (0..0x7F).collect(),
+                },
+                MemoryRegion {
+                    name: String::from("sram.0"),
+                    start: 0xA000,
+                    bytes: vec![0xAB; 0x2000],
+                },
+                MemoryRegion {
+                    name: String::from("sram.1"),
+                    start: 0xA000,
+                    bytes: vec![0xCD; 0x2000],
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn write_format() {
+        let mut out = Vec::new();
+        dump().write(&mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+
+        let expected = "rustboy crash dump
+message: Unknown operation 0xD3
+registers: AF=01B0 BC=0000 DE=0000 HL=C0DE SP=DFFC PC=4002
+ime: 1
+ie: 01
+if: E1
+lcdc: 91
+stat: 83
+ppu mode: 3
+ly: 12
+mapper: MBC1 rom bank 01, ram bank 00, ram disabled, rom banking
+ram init: random:0x2A
+[backtrace]
+backtrace:
+  #0 01:4001
+[trace]
+01:4001 D3 AF=01B0 BC=0000 DE=0000 HL=C0DE SP=DFFC PC=4002
+[memory hram FF80]
+FF80: 00 01 02 03 04 05 06 07 08 09 0A 0B 0C 0D 0E 0F 10 11 12 13 14 15 16 17 18 19 1A 1B 1C 1D 1E 1F
+";
+        assert!(text.starts_with(expected));
+        assert!(text.contains("\n[memory sram.0 A000]\nA000: AB AB"));
+        assert!(text.contains("\nBFE0: AB AB AB AB AB AB AB AB AB AB AB AB AB AB AB AB AB AB AB AB AB AB AB AB AB AB AB AB AB AB AB AB\n[memory sram.1 A000]\nA000: CD CD"));
+        assert!(text.ends_with("\nBFE0: CD CD CD CD CD CD CD CD CD CD CD CD CD CD CD CD CD CD CD CD CD CD CD CD CD CD CD CD CD CD CD CD\n"));
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut out = Vec::new();
+        dump().write(&mut out).unwrap();
+
+        let loaded = CrashDump::parse(&String::from_utf8(out).unwrap()).unwrap();
+
+        assert_eq!(dump(), loaded);
+        assert_eq!(0x7F, loaded.region("hram").unwrap().bytes.len());
+        assert_eq!(None, loaded.region("vram"));
+    }
+
+    #[test]
+    fn parse_rejects_other_files() {
+        let error = CrashDump::parse("00:0150 Main\n").err().unwrap();
+        assert_eq!(io::ErrorKind::InvalidData, error.kind());
+
+        let error = CrashDump::parse("rustboy crash dump\nie: XY\n")
+            .err()
+            .unwrap();
+        assert_eq!("invalid crash dump: XY", error.to_string());
+    }
+
+    #[test]
+    fn parse_rejects_unknown_sections() {
+        let error = CrashDump::parse("rustboy crash dump\n[foo]\nFF80: 00\n")
+            .err()
+            .unwrap();
+        assert_eq!("invalid crash dump: FF80: 00", error.to_string());
+    }
+}
@@ -0,0 +1,136 @@
+use std::collections::HashSet;
+
+use super::Location;
+
+/// Hardware behaviour that works on a real GameBoy but that games rarely
+/// rely on on purpose, so using it usually points at a bug in the game
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone)]
+pub enum Rule {
+    EchoRam,
+    UnmappedIo,
+    BlockedVram,
+    BlockedOam,
+}
+
+/// What a rule is reported once per
+enum Scope {
+    Run,
+    Address,
+    Instruction,
+}
+
+impl Rule {
+    /// Rules that can be hit by any of thousands of addresses are only
+    /// reported once, instead of once per address. Blocked accesses are
+    /// reported once per instruction, since every routine that races the
+    /// PPU is a separate bug.
+    fn scope(self) -> Scope {
+        match self {
+            Rule::EchoRam => Scope::Run,
+            Rule::UnmappedIo => Scope::Address,
+            Rule::BlockedVram | Rule::BlockedOam => Scope::Instruction,
+        }
+    }
+}
+
+/// Accuracy warnings, reported once each while lint mode is enabled
+pub struct Lint {
+    enabled: bool,
+    seen: HashSet<(Rule, Location)>,
+    warnings: Vec<String>,
+}
+
+impl Lint {
+    pub fn new() -> Lint {
+        Lint {
+            enabled: false,
+            seen: HashSet::new(),
+            warnings: Vec::new(),
+        }
+    }
+
+    pub fn enable(&mut self) {
+        self.enabled = true;
+    }
+
+    /// Reports a rule being hit at an address by the instruction at a
+    /// location. The message is only built if the warning is going to be
+    /// shown.
+    pub fn warn<F: FnOnce() -> String>(
+        &mut self,
+        rule: Rule,
+        address: u16,
+        instruction: Location,
+        message: F,
+    ) {
+        if !self.enabled {
+            return;
+        }
+
+        let key = match rule.scope() {
+            Scope::Run => (rule, Location::new(0, 0)),
+            Scope::Address => (rule, Location::new(0, address)),
+            Scope::Instruction => (rule, instruction),
+        };
+        if self.seen.insert(key) {
+            let warning = message();
+            println!("[lint] {}", warning);
+            self.warnings.push(warning);
+        }
+    }
+
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const PC: Location = Location {
+        bank: 0,
+        address: 0x0150,
+    };
+
+    #[test]
+    fn disabled_by_default() {
+        let mut lint = Lint::new();
+
+        lint.warn(Rule::EchoRam, 0xE000, PC, || String::from("echo"));
+
+        assert!(lint.warnings().is_empty());
+    }
+
+    #[test]
+    fn reports_once() {
+        let mut lint = Lint::new();
+        lint.enable();
+
+        lint.warn(Rule::EchoRam, 0xE000, PC, || String::from("first"));
+        lint.warn(Rule::EchoRam, 0xE001, PC, || String::from("second"));
+
+        assert_eq!(vec![String::from("first")], lint.warnings());
+    }
+
+    #[test]
+    fn reports_blocked_accesses_per_instruction() {
+        let mut lint = Lint::new();
+        lint.enable();
+
+        let other = Location::new(2, 0x4000);
+        lint.warn(Rule::BlockedVram, 0x8000, PC, || String::from("first"));
+        lint.warn(Rule::BlockedVram, 0x8001, PC, || String::from("again"));
+        lint.warn(Rule::BlockedVram, 0x8000, other, || String::from("second"));
+        lint.warn(Rule::BlockedOam, 0xFE00, PC, || String::from("oam"));
+
+        assert_eq!(
+            vec![
+                String::from("first"),
+                String::from("second"),
+                String::from("oam")
+            ],
+            lint.warnings()
+        );
+    }
+}
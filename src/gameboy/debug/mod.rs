@@ -0,0 +1,185 @@
+//! Tools for looking inside a running game. They are fed by `GameBoy` and
+//! the memory bus as the game runs and never affect emulation, other than
+//! the watchdog stopping a game that hangs.
+
+pub mod callstack;
+pub mod cdl;
+pub mod crashdump;
+pub mod lint;
+pub mod profiler;
+pub mod symbols;
+pub mod trace;
+pub mod watchdog;
+
+use std::fmt;
+
+/// A bank-qualified address. Only the switchable ROM region is qualified by
+/// the mapped bank; every other address is reported as bank 0.
+#[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Copy, Clone)]
+pub struct Location {
+    pub bank: u16,
+    pub address: u16,
+}
+
+impl Location {
+    pub fn new(bank: u16, address: u16) -> Location {
+        Location { bank, address }
+    }
+
+    /// Qualifies an address with the ROM bank that is currently mapped
+    pub fn at(address: u16, rom_bank: usize) -> Location {
+        match address {
+            0x4000..=0x7FFF => Location::new(rom_bank as u16, address),
+            _ => Location::new(0, address),
+        }
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:02X}:{:04X}", self.bank, self.address)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::symbols::Symbols;
+    use super::*;
+    use crate::asm::Assembler;
+    use crate::asm::Ind;
+    use crate::asm::Target;
+    use crate::asm::R8;
+    use crate::gameboy::clock::NoClock;
+    use crate::gameboy::debug::crashdump::CrashDump;
+    use crate::gameboy::display::NoDisplay;
+    use crate::gameboy::GameBoy;
+    use crate::gameboy::Model;
+    use crate::gameboy::Renderer;
+    use crate::gameboy::TRACE_LENGTH;
+    use std::env;
+    use std::fs;
+    use std::panic;
+    use std::panic::AssertUnwindSafe;
+    use std::process;
+
+    #[test]
+    fn location_at() {
+        assert_eq!(Location::new(0, 0x3FFF), Location::at(0x3FFF, 5));
+        assert_eq!(Location::new(5, 0x4000), Location::at(0x4000, 5));
+        assert_eq!(Location::new(5, 0x7FFF), Location::at(0x7FFF, 5));
+        assert_eq!(Location::new(0, 0xC000), Location::at(0xC000, 5));
+    }
+
+    #[test]
+    fn location_display() {
+        assert_eq!("00:0150", Location::new(0, 0x150).to_string());
+        assert_eq!("1F:4ABC", Location::new(0x1F, 0x4ABC).to_string());
+    }
+
+    #[test]
+    fn breakpoint_at_label() {
+        let mut asm = Assembler::new("BREAK");
+        asm.section(0x150).nop().nop().label("done").jr("done");
+
+        let rom = asm.build();
+        let mut gameboy = GameBoy::new(
+            &rom,
+            Model::Dmg,
+            Renderer::default(),
+            Box::new(NoClock::new()),
+            Box::new(NoDisplay::new()),
+        );
+        gameboy.set_symbols(Symbols::parse("00:0150 Main\n00:0152 Done\n"));
+
+        let location = gameboy.symbols().resolve("Done", 1).unwrap();
+        gameboy.add_breakpoint(location);
+
+        let mut steps = 0;
+        while !gameboy.at_breakpoint() {
+            gameboy.step();
+            steps += 1;
+            assert!(steps < 10_000_000, "breakpoint was never hit");
+        }
+
+        assert_eq!(0x0152, gameboy.registers().pc);
+    }
+
+    #[test]
+    fn backtrace_after_crash() {
+        let mut asm = Assembler::new("CRASH");
+        asm.section(0x150)
+            .call("update")
+            .label("update")
+            .call(0x4000)
+            .bank_section(1, 0x4000)
+            .nop()
+            .db(&[0xD3]);
+
+        let rom = asm.build();
+        let mut gameboy = GameBoy::new(
+            &rom,
+            Model::Dmg,
+            Renderer::default(),
+            Box::new(NoClock::new()),
+            Box::new(NoDisplay::new()),
+        );
+        gameboy.set_symbols(Symbols::parse("00:0153 Update\n01:4000 Draw\n"));
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| loop {
+            gameboy.step();
+        }));
+        assert!(result.is_err());
+
+        let expected = "backtrace:
+  #0 01:4001 Draw+0x1
+  #1 00:0153 Update
+  #2 00:0150";
+        assert_eq!(expected, gameboy.backtrace());
+    }
+
+    #[test]
+    fn crash_dump_after_crash() {
+        let mut asm = Assembler::new("DUMP");
+        asm.section(0x150)
+            .ld_n(R8::A, 0x5A)
+            .ld_ind_a(Ind::Addr(Target::Address(0xC123)))
+            .ld_ind_a(Ind::High(0x85))
+            .call("crash")
+            .label("crash")
+            .db(&[0xD3]);
+
+        let rom = asm.build();
+        let mut gameboy = GameBoy::new(
+            &rom,
+            Model::Dmg,
+            Renderer::default(),
+            Box::new(NoClock::new()),
+            Box::new(NoDisplay::new()),
+        );
+
+        let path = env::temp_dir().join(format!("rustboy-{}.crash.txt", process::id()));
+        gameboy.set_crash_dump_path(path.clone());
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| loop {
+            gameboy.step();
+        }));
+        assert!(result.is_err());
+
+        let dump = CrashDump::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!("Unknown operation 0xD3", dump.message);
+        assert_eq!(0x015B, dump.registers.pc);
+        assert_eq!(0x5A, dump.registers.a);
+        assert_eq!("none", dump.mapper);
+        assert_eq!(TRACE_LENGTH, dump.trace.len());
+        assert!(dump.trace[TRACE_LENGTH - 2].starts_with("00:0157 CD "));
+        assert!(dump.trace[TRACE_LENGTH - 1].starts_with("00:015A D3 "));
+        assert_eq!("  #1 00:0157", dump.backtrace[2]);
+        assert_eq!(0x5A, dump.region("wram").unwrap().bytes[0x123]);
+        assert_eq!(0x5A, dump.region("hram").unwrap().bytes[0x05]);
+        assert_eq!(0xA0, dump.region("oam").unwrap().bytes.len());
+        assert_eq!(0x2000, dump.region("sram.3").unwrap().bytes.len());
+        assert_eq!(None, dump.region("sram.4"));
+    }
+}
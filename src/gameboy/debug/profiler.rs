@@ -0,0 +1,428 @@
+use std::collections::HashMap;
+use std::io;
+use std::io::Write;
+
+use super::callstack::CallStack;
+use super::symbols::Symbols;
+use super::Location;
+use crate::gameboy::cpu::Flow;
+
+const ROOT: usize = 0;
+
+#[derive(Default, Copy, Clone)]
+struct Counts {
+    instructions: u64,
+    cycles: u64,
+}
+
+/// One distinct call stack; stacks form a tree rooted at the code that runs
+/// outside of any subroutine
+struct Node {
+    parent: usize,
+    location: Option<Location>,
+    cycles: u64,
+}
+
+/// Counts executed instructions and M-cycles per bank:PC and per called
+/// subroutine. Subroutines are followed through the calls, resets, interrupts
+/// and returns reported by the CPU.
+pub struct Profiler {
+    addresses: HashMap<Location, Counts>,
+    calls: HashMap<Location, u64>,
+    nodes: Vec<Node>,
+    children: HashMap<(usize, Location), usize>,
+    stack: CallStack<usize>,
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Profiler {
+            addresses: HashMap::new(),
+            calls: HashMap::new(),
+            nodes: vec![Node {
+                parent: ROOT,
+                location: None,
+                cycles: 0,
+            }],
+            children: HashMap::new(),
+            stack: CallStack::new(),
+        }
+    }
+
+    /// Records a single step of the CPU: the instruction that was executed
+    /// at `address`, the cycles the step took and its control flow changes
+    pub fn record(&mut self, rom_bank: usize, address: u16, cycles: u8, flow: &[Flow]) {
+        // Interrupts are dispatched before the instruction executes, so the
+        // instruction already belongs to the handler
+        let (dispatch, rest) = flow.split_at(
+            flow.iter()
+                .take_while(|f| matches!(f, Flow::Interrupt { .. }))
+                .count(),
+        );
+
+        self.follow(rom_bank, dispatch);
+
+        let cycles = cycles as u64;
+        let counts = self
+            .addresses
+            .entry(Location::at(address, rom_bank))
+            .or_default();
+        counts.instructions += 1;
+        counts.cycles += cycles;
+
+        let node = self.current_node();
+        self.nodes[node].cycles += cycles;
+
+        self.follow(rom_bank, rest);
+    }
+
+    /// Writes the hottest `limit` addresses and subroutines, sorted by cycles
+    pub fn write_report(
+        &self,
+        out: &mut dyn Write,
+        limit: usize,
+        symbols: &Symbols,
+    ) -> io::Result<()> {
+        let total_cycles: u64 = self.addresses.values().map(|c| c.cycles).sum();
+        let total_instructions: u64 = self.addresses.values().map(|c| c.instructions).sum();
+
+        writeln!(
+            out,
+            "Hotspots ({} cycles, {} instructions)",
+            total_cycles, total_instructions
+        )?;
+        writeln!(
+            out,
+            "{:>12} {:>7} {:>12}  location",
+            "cycles", "%", "instrs"
+        )?;
+
+        let mut addresses: Vec<_> = self.addresses.iter().collect();
+        addresses.sort_by(|a, b| b.1.cycles.cmp(&a.1.cycles).then(a.0.cmp(b.0)));
+
+        for (location, counts) in addresses.iter().take(limit) {
+            writeln!(
+                out,
+                "{:>12} {:>6.2}% {:>12}  {}",
+                counts.cycles,
+                percent(counts.cycles, total_cycles),
+                counts.instructions,
+                symbols.describe(**location)
+            )?;
+        }
+
+        writeln!(out)?;
+        writeln!(out, "Subroutines")?;
+        writeln!(
+            out,
+            "{:>10} {:>12} {:>7} {:>12} {:>7}  routine",
+            "calls", "inclusive", "%", "exclusive", "%"
+        )?;
+
+        let mut routines: Vec<_> = self.routine_cycles().into_iter().collect();
+        routines.sort_by(|a, b| (b.1).0.cmp(&(a.1).0).then(a.0.cmp(&b.0)));
+
+        for (location, (inclusive, exclusive)) in routines.iter().take(limit) {
+            writeln!(
+                out,
+                "{:>10} {:>12} {:>6.2}% {:>12} {:>6.2}%  {}",
+                self.calls.get(location).unwrap_or(&0),
+                inclusive,
+                percent(*inclusive, total_cycles),
+                exclusive,
+                percent(*exclusive, total_cycles),
+                symbols.describe(*location)
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes one line per distinct call stack in the collapsed format read
+    /// by flamegraph tools: frames from the root separated by `;`, followed
+    /// by the cycles spent with exactly that stack
+    pub fn write_collapsed(&self, out: &mut dyn Write, symbols: &Symbols) -> io::Result<()> {
+        let mut lines: Vec<String> = self
+            .nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, node)| node.cycles > 0)
+            .map(|(index, node)| {
+                let frames: Vec<String> = self
+                    .path(index)
+                    .iter()
+                    .map(|location| symbols.describe(*location))
+                    .collect();
+
+                let mut line = String::from("root");
+                for frame in frames.iter().rev() {
+                    line.push(';');
+                    line.push_str(frame);
+                }
+                format!("{} {}", line, node.cycles)
+            })
+            .collect();
+
+        lines.sort();
+        for line in lines {
+            writeln!(out, "{}", line)?;
+        }
+
+        Ok(())
+    }
+
+    fn follow(&mut self, rom_bank: usize, flow: &[Flow]) {
+        for flow in flow {
+            match *flow {
+                Flow::Call { target, sp } | Flow::Interrupt { target, sp } => {
+                    let location = Location::at(target, rom_bank);
+                    *self.calls.entry(location).or_insert(0) += 1;
+
+                    // Frames discarded by the new one can't be its parent
+                    self.stack.overwrite(sp);
+                    let node = self.child(self.current_node(), location);
+                    self.stack.enter(sp, node);
+                }
+                Flow::Return { sp } => self.stack.exit(sp),
+            }
+        }
+    }
+
+    fn current_node(&self) -> usize {
+        self.stack.top().copied().unwrap_or(ROOT)
+    }
+
+    fn child(&mut self, parent: usize, location: Location) -> usize {
+        let nodes = &mut self.nodes;
+        *self.children.entry((parent, location)).or_insert_with(|| {
+            nodes.push(Node {
+                parent,
+                location: Some(location),
+                cycles: 0,
+            });
+            nodes.len() - 1
+        })
+    }
+
+    /// Locations on the stack of a node, innermost first
+    fn path(&self, mut index: usize) -> Vec<Location> {
+        let mut path = Vec::new();
+        while let Some(location) = self.nodes[index].location {
+            path.push(location);
+            index = self.nodes[index].parent;
+        }
+        path
+    }
+
+    /// Inclusive and exclusive cycles for every called subroutine. Recursive
+    /// subroutines only count their inclusive cycles once per stack.
+    fn routine_cycles(&self) -> HashMap<Location, (u64, u64)> {
+        let mut routines: HashMap<Location, (u64, u64)> = HashMap::new();
+
+        for (index, node) in self.nodes.iter().enumerate() {
+            let path = self.path(index);
+            for (depth, location) in path.iter().enumerate() {
+                if path[..depth].contains(location) {
+                    continue;
+                }
+                let entry = routines.entry(*location).or_insert((0, 0));
+                entry.0 += node.cycles;
+                if depth == 0 {
+                    entry.1 += node.cycles;
+                }
+            }
+        }
+
+        routines
+    }
+}
+
+fn percent(part: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        part as f64 * 100.0 / total as f64
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::asm::Assembler;
+    use crate::asm::Cond;
+    use crate::asm::R8;
+    use crate::gameboy::clock::NoClock;
+    use crate::gameboy::display::NoDisplay;
+    use crate::gameboy::GameBoy;
+    use crate::gameboy::Model;
+    use crate::gameboy::Renderer;
+
+    fn call(target: u16, sp: u16) -> Flow {
+        Flow::Call { target, sp }
+    }
+
+    fn ret(sp: u16) -> Flow {
+        Flow::Return { sp }
+    }
+
+    fn collapsed(profiler: &Profiler) -> String {
+        let mut out = Vec::new();
+        profiler.write_collapsed(&mut out, &Symbols::new()).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn report(profiler: &Profiler) -> String {
+        let mut out = Vec::new();
+        profiler
+            .write_report(&mut out, 10, &Symbols::new())
+            .unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn counts_per_address() {
+        let mut profiler = Profiler::new();
+
+        profiler.record(1, 0x0150, 1, &[]);
+        profiler.record(1, 0x0150, 1, &[]);
+        profiler.record(2, 0x4000, 4, &[]);
+        profiler.record(3, 0x4000, 2, &[]);
+
+        let report = report(&profiler);
+        let lines: Vec<&str> = report.lines().collect();
+
+        assert_eq!("Hotspots (8 cycles, 4 instructions)", lines[0]);
+        assert_eq!("           4  50.00%            1  02:4000", lines[2]);
+        assert_eq!("           2  25.00%            2  00:0150", lines[3]);
+        assert_eq!("           2  25.00%            1  03:4000", lines[4]);
+    }
+
+    #[test]
+    fn attributes_cycles_to_subroutines() {
+        let mut profiler = Profiler::new();
+
+        // The call instruction belongs to the caller
+        profiler.record(1, 0x0150, 6, &[call(0x0200, 0xFFFC)]);
+        profiler.record(1, 0x0200, 1, &[call(0x0300, 0xFFFA)]);
+        profiler.record(1, 0x0300, 2, &[]);
+        profiler.record(1, 0x0301, 4, &[ret(0xFFFC)]);
+        profiler.record(1, 0x0201, 4, &[ret(0xFFFE)]);
+        profiler.record(1, 0x0153, 1, &[]);
+
+        assert_eq!(
+            "root 7\nroot;00:0200 5\nroot;00:0200;00:0300 6\n",
+            collapsed(&profiler)
+        );
+
+        let report = report(&profiler);
+        assert!(report.contains("         1           11  61.11%            5  27.78%  00:0200"));
+        assert!(report.contains("         1            6  33.33%            6  33.33%  00:0300"));
+    }
+
+    #[test]
+    fn names_locations_with_symbols() {
+        let mut profiler = Profiler::new();
+        let symbols = Symbols::parse("00:0150 Main\n02:4000 PlayerUpdate\n");
+
+        profiler.record(2, 0x0150, 6, &[call(0x4000, 0xFFFC)]);
+        profiler.record(2, 0x4001, 2, &[]);
+
+        let mut out = Vec::new();
+        profiler.write_collapsed(&mut out, &symbols).unwrap();
+        assert_eq!(
+            "root 6\nroot;PlayerUpdate 2\n",
+            String::from_utf8(out).unwrap()
+        );
+
+        let mut out = Vec::new();
+        profiler.write_report(&mut out, 10, &symbols).unwrap();
+        let report = String::from_utf8(out).unwrap();
+        assert!(report.contains("  Main\n"));
+        assert!(report.contains("  PlayerUpdate+0x1\n"));
+        assert!(report.contains("  PlayerUpdate\n"));
+    }
+
+    #[test]
+    fn interrupt_frames_own_the_dispatched_instruction() {
+        let mut profiler = Profiler::new();
+
+        let dispatch = Flow::Interrupt {
+            target: 0x0040,
+            sp: 0xFFFC,
+        };
+        profiler.record(1, 0x0040, 7, &[dispatch]);
+        profiler.record(1, 0x0041, 4, &[ret(0xFFFE)]);
+        profiler.record(1, 0x0150, 1, &[]);
+
+        assert_eq!("root 1\nroot;00:0040 11\n", collapsed(&profiler));
+    }
+
+    #[test]
+    fn unwinds_discarded_frames() {
+        let mut profiler = Profiler::new();
+
+        // A subroutine that pops its own return address and jumps back
+        profiler.record(1, 0x0150, 6, &[call(0x0200, 0xFFFC)]);
+        profiler.record(1, 0x0200, 3, &[]);
+        // A `push hl; ret` jump doesn't leave the subroutine
+        profiler.record(1, 0x0201, 4, &[ret(0xFFFC)]);
+        // Calling again with the same stack pointer replaces the frame
+        profiler.record(1, 0x0300, 6, &[call(0x0400, 0xFFFC)]);
+        profiler.record(1, 0x0400, 1, &[]);
+
+        assert_eq!(
+            "root 6\nroot;00:0200 13\nroot;00:0400 1\n",
+            collapsed(&profiler)
+        );
+    }
+
+    #[test]
+    fn recursion_counts_inclusive_cycles_once() {
+        let mut profiler = Profiler::new();
+
+        profiler.record(1, 0x0150, 6, &[call(0x0200, 0xFFFC)]);
+        profiler.record(1, 0x0200, 6, &[call(0x0200, 0xFFFA)]);
+        profiler.record(1, 0x0200, 3, &[]);
+
+        let routines = profiler.routine_cycles();
+        assert_eq!((9, 9), routines[&Location::new(0, 0x0200)]);
+        assert_eq!(2, profiler.calls[&Location::new(0, 0x0200)]);
+    }
+
+    #[test]
+    fn profiles_a_running_game() {
+        let mut asm = Assembler::new("PROFILE");
+        asm.section(0x150)
+            .label("main")
+            .call("busy")
+            .jr("main")
+            .label("busy")
+            .ld_n(R8::B, 10)
+            .label("loop")
+            .dec(R8::B)
+            .jr_cc(Cond::NZ, "loop")
+            .ret();
+
+        let rom = asm.build();
+        let mut gameboy = GameBoy::new(
+            &rom,
+            Model::Dmg,
+            Renderer::default(),
+            Box::new(NoClock::new()),
+            Box::new(NoDisplay::new()),
+        );
+        gameboy.enable_profiler();
+
+        // Get through the boot ROM and a few rounds of the game
+        while gameboy.registers().pc != 0x0150 {
+            gameboy.step();
+        }
+        for _ in 0..1000 {
+            gameboy.step();
+        }
+
+        let profiler = gameboy.profiler().unwrap();
+        assert!(profiler.calls[&Location::new(0, 0x0155)] > 10);
+        assert!(collapsed(profiler).contains("root;00:0155 "));
+    }
+}
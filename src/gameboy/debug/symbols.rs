@@ -0,0 +1,219 @@
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use super::Location;
+
+/// Labels loaded from the `bank:addr label` symbol files written by RGBDS
+/// and wla-dx. Only ROM in 0x4000-0x7FFF is banked by the emulator, so the
+/// banks of symbols anywhere else are dropped when loading.
+#[derive(Default)]
+pub struct Symbols {
+    by_location: BTreeMap<Location, String>,
+    by_name: HashMap<String, Location>,
+}
+
+impl Symbols {
+    pub fn new() -> Symbols {
+        Symbols::default()
+    }
+
+    pub fn load(path: &Path) -> io::Result<Symbols> {
+        let text = fs::read_to_string(path)?;
+        Ok(Symbols::parse(&text))
+    }
+
+    /// Parses the contents of a symbol file. Lines that aren't labels, such
+    /// as comments and the other sections of wla-dx files, are skipped.
+    pub fn parse(text: &str) -> Symbols {
+        let mut symbols = Symbols::new();
+        let mut in_labels = true;
+
+        for line in text.lines() {
+            let line = line.split(';').next().unwrap().trim();
+
+            if line.starts_with('[') {
+                in_labels = line == "[labels]";
+                continue;
+            }
+
+            if !in_labels {
+                continue;
+            }
+
+            let mut parts = line.split_whitespace();
+            let location = parts.next().and_then(parse_location);
+            let name = parts.next();
+
+            if let (Some(location), Some(name)) = (location, name) {
+                symbols.insert(location, name);
+            }
+        }
+
+        symbols
+    }
+
+    pub fn insert(&mut self, location: Location, name: &str) {
+        let location = Location::at(location.address, location.bank as usize);
+
+        // The first label at an address is the one that gets displayed
+        self.by_location
+            .entry(location)
+            .or_insert_with(|| name.to_string());
+        self.by_name.insert(name.to_string(), location);
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_name.len()
+    }
+
+    /// The label defined exactly at a location
+    pub fn name(&self, location: Location) -> Option<&str> {
+        self.by_location.get(&location).map(|name| name.as_str())
+    }
+
+    /// Finds a location by label, `bank:addr` or a plain address, such as
+    /// when setting a breakpoint. Plain addresses in the switchable region
+    /// use the ROM bank that is currently mapped.
+    pub fn resolve(&self, text: &str, rom_bank: usize) -> Option<Location> {
+        if let Some(location) = self.by_name.get(text) {
+            return Some(*location);
+        }
+
+        parse_location(text).or_else(|| {
+            let address = text.trim_start_matches("0x").trim_start_matches('$');
+            u16::from_str_radix(address, 16)
+                .ok()
+                .map(|address| Location::at(address, rom_bank))
+        })
+    }
+
+    /// Describes a location by the closest label at or before it in the same
+    /// bank and memory region, eg. `PlayerUpdate+0x1A`, or by its bank and
+    /// address if there is no such label
+    pub fn describe(&self, location: Location) -> String {
+        let closest = self
+            .by_location
+            .range(..=location)
+            .next_back()
+            .filter(|(start, _)| {
+                start.bank == location.bank && region(start.address) == region(location.address)
+            });
+
+        if let Some(name) = self.name(location) {
+            return name.to_string();
+        }
+
+        match closest {
+            Some((start, name)) => format!("{}+0x{:X}", name, location.address - start.address),
+            None => location.to_string(),
+        }
+    }
+}
+
+fn parse_location(text: &str) -> Option<Location> {
+    let mut parts = text.splitn(2, ':');
+    let bank = u16::from_str_radix(parts.next()?, 16).ok()?;
+    let address = u16::from_str_radix(parts.next()?, 16).ok()?;
+    Some(Location::at(address, bank as usize))
+}
+
+/// Labels only describe addresses in the same area of the memory map
+fn region(address: u16) -> u8 {
+    match address {
+        0x0000..=0x3FFF => 0,
+        0x4000..=0x7FFF => 1,
+        0x8000..=0x9FFF => 2,
+        0xA000..=0xBFFF => 3,
+        0xC000..=0xFDFF => 4,
+        0xFE00..=0xFF7F => 5,
+        0xFF80..=0xFFFF => 6,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const RGBDS: &str = "; File generated by rgblink
+00:0150 Main
+00:0150 Start
+00:0158 Main.loop
+01:4000 PlayerUpdate
+02:4000 EnemyUpdate
+00:C000 wPlayerX
+01:D000 wEnemies
+00:FF80 hVBlankFlag
+";
+
+    const WLA_DX: &str = "; wla symbolic information file
+[labels]
+0000:0150 main
+0003:4010 sound_play
+[definitions]
+00000010 _sizeof_main
+";
+
+    #[test]
+    fn parse_rgbds() {
+        let symbols = Symbols::parse(RGBDS);
+
+        assert_eq!(8, symbols.len());
+        assert_eq!(Some("Main"), symbols.name(Location::new(0, 0x0150)));
+        assert_eq!(Some("Main.loop"), symbols.name(Location::new(0, 0x0158)));
+        assert_eq!(Some("PlayerUpdate"), symbols.name(Location::new(1, 0x4000)));
+        assert_eq!(Some("EnemyUpdate"), symbols.name(Location::new(2, 0x4000)));
+        // Work RAM isn't banked, so its bank is dropped
+        assert_eq!(Some("wEnemies"), symbols.name(Location::new(0, 0xD000)));
+    }
+
+    #[test]
+    fn parse_wla_dx() {
+        let symbols = Symbols::parse(WLA_DX);
+
+        assert_eq!(2, symbols.len());
+        assert_eq!(Some("main"), symbols.name(Location::new(0, 0x0150)));
+        assert_eq!(Some("sound_play"), symbols.name(Location::new(3, 0x4010)));
+    }
+
+    #[test]
+    fn resolve() {
+        let symbols = Symbols::parse(RGBDS);
+
+        assert_eq!(
+            Some(Location::new(2, 0x4000)),
+            symbols.resolve("EnemyUpdate", 1)
+        );
+        assert_eq!(Some(Location::new(0, 0x0150)), symbols.resolve("Start", 1));
+        assert_eq!(
+            Some(Location::new(5, 0x4100)),
+            symbols.resolve("05:4100", 1)
+        );
+        assert_eq!(Some(Location::new(7, 0x4100)), symbols.resolve("4100", 7));
+        assert_eq!(Some(Location::new(0, 0xC000)), symbols.resolve("$C000", 7));
+        assert_eq!(None, symbols.resolve("Missing", 1));
+    }
+
+    #[test]
+    fn describe_is_bank_aware() {
+        let symbols = Symbols::parse(RGBDS);
+
+        assert_eq!("Main", symbols.describe(Location::new(0, 0x0150)));
+        assert_eq!("Main+0x3", symbols.describe(Location::new(0, 0x0153)));
+        assert_eq!("Main.loop+0x10", symbols.describe(Location::new(0, 0x0168)));
+        assert_eq!(
+            "PlayerUpdate+0x20",
+            symbols.describe(Location::new(1, 0x4020))
+        );
+        assert_eq!(
+            "EnemyUpdate+0x20",
+            symbols.describe(Location::new(2, 0x4020))
+        );
+        assert_eq!("03:4020", symbols.describe(Location::new(3, 0x4020)));
+        assert_eq!("hVBlankFlag", symbols.describe(Location::new(0, 0xFF80)));
+        // Labels don't reach into the next area of memory
+        assert_eq!("00:8000", symbols.describe(Location::new(0, 0x8000)));
+    }
+}
@@ -0,0 +1,95 @@
+use std::collections::VecDeque;
+
+use super::symbols::Symbols;
+use super::Location;
+use crate::gameboy::cpu::Registers;
+
+/// An executed instruction and the registers it left behind
+pub struct TraceEntry {
+    pub location: Location,
+    pub op_code: u8,
+    pub registers: Registers,
+}
+
+impl TraceEntry {
+    pub fn describe(&self, symbols: &Symbols) -> String {
+        let line = format!("{} {:02X} {}", self.location, self.op_code, self.registers);
+        let name = symbols.describe(self.location);
+
+        if name == self.location.to_string() {
+            line
+        } else {
+            format!("{} {}", line, name)
+        }
+    }
+}
+
+/// Keeps the most recently executed instructions, dropping the oldest once
+/// full
+pub struct TraceBuffer {
+    entries: VecDeque<TraceEntry>,
+    capacity: usize,
+}
+
+impl TraceBuffer {
+    pub fn new(capacity: usize) -> TraceBuffer {
+        TraceBuffer {
+            entries: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn push(&mut self, entry: TraceEntry) {
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    /// Entries from oldest to newest
+    pub fn entries(&self) -> impl Iterator<Item = &TraceEntry> {
+        self.entries.iter()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn entry(address: u16) -> TraceEntry {
+        TraceEntry {
+            location: Location::at(address, 1),
+            op_code: 0x00,
+            registers: Registers::default(),
+        }
+    }
+
+    #[test]
+    fn keeps_most_recent_entries() {
+        let mut trace = TraceBuffer::new(3);
+
+        for address in 0..5 {
+            trace.push(entry(address));
+        }
+
+        let addresses: Vec<u16> = trace.entries().map(|e| e.location.address).collect();
+        assert_eq!(vec![2, 3, 4], addresses);
+    }
+
+    #[test]
+    fn describe() {
+        let symbols = Symbols::parse("01:4000 Draw\n");
+        let mut entry = entry(0x4002);
+        entry.op_code = 0xD3;
+        entry.registers.pc = 0x4003;
+
+        assert_eq!(
+            "01:4002 D3 AF=0000 BC=0000 DE=0000 HL=0000 SP=0000 PC=4003 Draw+0x2",
+            entry.describe(&symbols)
+        );
+        assert_eq!(
+            "01:4002 D3 AF=0000 BC=0000 DE=0000 HL=0000 SP=0000 PC=4003",
+            entry.describe(&Symbols::new())
+        );
+    }
+}
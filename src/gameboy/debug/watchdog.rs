@@ -0,0 +1,162 @@
+/*
+ * The watchdog catches games that hang: a jump to itself that nothing takes
+ * the CPU out of. Idle loops that wait for an interrupt are left alone as
+ * long as the interrupt comes, so the limit is in frames rather than in
+ * instructions.
+ */
+
+// Machine cycles from one VBlank to the next
+const CYCLES_PER_FRAME: u64 = 154 * 114;
+
+/// Counts how long the CPU has been running an instruction that jumps to
+/// itself, without an interrupt or anything else taking it elsewhere
+pub struct Watchdog {
+    frames: u32,
+    stuck_at: Option<u16>,
+    stuck_cycles: u64,
+}
+
+impl Watchdog {
+    pub fn new(frames: u32) -> Watchdog {
+        Watchdog {
+            frames,
+            stuck_at: None,
+            stuck_cycles: 0,
+        }
+    }
+
+    /// Follows a step from the instruction at `last_pc` to `pc` that took
+    /// some cycles, and describes the hang once the CPU has been stuck for
+    /// the frame limit
+    pub fn check(&mut self, last_pc: u16, pc: u16, cycles: u64) -> Option<String> {
+        if last_pc != pc {
+            self.stuck_at = None;
+            self.stuck_cycles = 0;
+            return None;
+        }
+
+        if self.stuck_at != Some(pc) {
+            self.stuck_at = Some(pc);
+            self.stuck_cycles = 0;
+        }
+        self.stuck_cycles += cycles;
+
+        if self.stuck_cycles >= self.frames as u64 * CYCLES_PER_FRAME {
+            Some(format!(
+                "watchdog: stuck in a loop at 0x{:04X} for {} frames",
+                pc, self.frames
+            ))
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::asm::Assembler;
+    use crate::asm::Ind;
+    use crate::asm::R8;
+    use crate::gameboy::clock::NoClock;
+    use crate::gameboy::debug::crashdump::CrashDump;
+    use crate::gameboy::display::NoDisplay;
+    use crate::gameboy::GameBoy;
+    use crate::gameboy::Model;
+    use crate::gameboy::Renderer;
+    use std::env;
+    use std::fs;
+    use std::panic;
+    use std::panic::AssertUnwindSafe;
+
+    fn gameboy(rom: &Vec<u8>) -> GameBoy {
+        let mut gameboy = GameBoy::new(
+            rom,
+            Model::Dmg,
+            Renderer::default(),
+            Box::new(NoClock::new()),
+            Box::new(NoDisplay::new()),
+        );
+        gameboy.skip_boot();
+        gameboy
+    }
+
+    #[test]
+    fn resets_when_the_loop_is_left() {
+        let mut watchdog = Watchdog::new(1);
+
+        assert_eq!(None, watchdog.check(0x0150, 0x0150, CYCLES_PER_FRAME - 3));
+        assert_eq!(None, watchdog.check(0x0150, 0x0040, 5));
+        assert_eq!(None, watchdog.check(0x0150, 0x0150, CYCLES_PER_FRAME - 3));
+        assert_eq!(
+            Some(String::from(
+                "watchdog: stuck in a loop at 0x0150 for 1 frames"
+            )),
+            watchdog.check(0x0150, 0x0150, 3)
+        );
+    }
+
+    #[test]
+    fn catches_a_jump_to_itself() {
+        let mut asm = Assembler::new("HANG");
+        asm.section(0x150).di().label("hang").jr("hang");
+        let rom = asm.build();
+
+        let path = env::temp_dir().join(format!("rustboy-watchdog-{}.txt", std::process::id()));
+        let mut gameboy = gameboy(&rom);
+        gameboy.set_crash_dump_path(path.clone());
+        gameboy.enable_watchdog(2);
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            for _ in 0..3 * CYCLES_PER_FRAME {
+                gameboy.step();
+            }
+        }));
+        let message = result.err().unwrap();
+        assert_eq!(
+            Some(&String::from(
+                "watchdog: stuck in a loop at 0x0151 for 2 frames"
+            )),
+            message.downcast_ref::<String>()
+        );
+
+        let dump = CrashDump::load(&path).unwrap();
+        assert_eq!(0x0151, dump.registers.pc);
+        assert!(dump.message.starts_with("watchdog"));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn leaves_idle_loops_alone() {
+        // Waits for VBlank interrupts in a loop, which is how many games idle
+        let mut asm = Assembler::new("IDLE");
+        asm.section(0x40).reti();
+        asm.section(0x150)
+            .ld_n(R8::A, 0x01)
+            .ld_ind_a(Ind::High(0xFF))
+            .ei()
+            .label("idle")
+            .jr("idle");
+        let rom = asm.build();
+
+        let mut gameboy = gameboy(&rom);
+        gameboy.enable_watchdog(2);
+
+        let mut cycles = 0;
+        while cycles < 10 * CYCLES_PER_FRAME {
+            cycles += gameboy.step() as u64;
+        }
+    }
+
+    #[test]
+    fn off_by_default() {
+        let mut asm = Assembler::new("HANG");
+        asm.section(0x150).di().label("hang").jr("hang");
+
+        let mut gameboy = gameboy(&asm.build());
+        let mut cycles = 0;
+        while cycles < 10 * CYCLES_PER_FRAME {
+            cycles += gameboy.step() as u64;
+        }
+    }
+}
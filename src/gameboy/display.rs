@@ -1,24 +1,29 @@
-use crate::gameboy::Color;
+use crate::gameboy::palette::Rgb;
 
+pub const SCREEN_WIDTH: usize = 160;
+pub const SCREEN_HEIGHT: usize = 144;
+
+/// Receives the frames drawn by the GPU. A frame is SCREEN_WIDTH *
+/// SCREEN_HEIGHT colors row by row, in the GPU's color palette.
 pub trait VideoDisplay {
-    fn set_pixel(&mut self, x: u8, y: u8, color: Color);
-    fn vsync(&mut self);
-}
+    /// Shows a frame once it has been drawn completely
+    fn vsync(&mut self, frame: &[Rgb]);
 
-#[cfg(test)]
-pub mod test {
-    use super::*;
+    /// Fills the screen with the white of the background colors, as the LCD
+    /// shows while it is off
+    fn blank(&mut self, white: Rgb);
+}
 
-    pub struct NoDisplay {}
+/// A display that discards everything drawn to it
+pub struct NoDisplay {}
 
-    impl NoDisplay {
-        pub fn new() -> NoDisplay {
-            NoDisplay {}
-        }
+impl NoDisplay {
+    pub fn new() -> NoDisplay {
+        NoDisplay {}
     }
+}
 
-    impl VideoDisplay for NoDisplay {
-        fn set_pixel(&mut self, _x: u8, _y: u8, _color: Color) {}
-        fn vsync(&mut self) {}
-    }
+impl VideoDisplay for NoDisplay {
+    fn vsync(&mut self, _frame: &[Rgb]) {}
+    fn blank(&mut self, _white: Rgb) {}
 }
@@ -0,0 +1,392 @@
+/*
+ * Pixel FIFO renderer. Instead of drawing a whole line at once, the PPU is
+ * advanced one dot at a time during pixel transfer, so writes to SCX, SCY,
+ * the palettes or LCDC in the middle of a line show up where they happen.
+ *
+ * A background fetcher reads a tile number, the low and the high byte of a
+ * row of the tile, taking two dots for each, and then pushes the eight
+ * pixels of the row into the background FIFO once it is empty. A pixel is
+ * shifted out of the FIFO to the LCD every dot, mixed with the sprite FIFO.
+ *
+ * - The first fetch of a line is thrown away, so a line takes at least
+ *   172 dots.
+ * - The first SCX % 8 pixels of a line are discarded for fine scrolling.
+ * - Reaching the window clears the background FIFO and restarts the fetcher
+ *   on the window map, at the row of the window line counter.
+ * - Reaching a sprite stalls shifting until the fetcher has a row ready, and
+ *   then for the six dots it takes to fetch the sprite.
+ */
+
+use std::collections::VecDeque;
+
+use super::Color;
+use super::Layer;
+use super::Sprite;
+use super::GPU;
+use super::V_SCANLINE_MAX;
+
+// Dots taken by the fetcher to read a sprite row
+const SPRITE_FETCH_DOTS: u8 = 6;
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+enum FetchStep {
+    Tile,
+    DataLow,
+    DataHigh,
+    Push,
+}
+
+#[derive(Copy, Clone, Default)]
+struct SpritePixel {
+    color: u8,
+    palette: bool,
+    priority: bool,
+}
+
+pub struct Fifo {
+    /// Pixels shifted out to the LCD on this line
+    x: u8,
+    /// Pixels still to be thrown away before the next one is drawn
+    discard: u8,
+    bg: VecDeque<u8>,
+    sprites: VecDeque<SpritePixel>,
+    step: FetchStep,
+    /// Each step but pushing takes two dots, and happens on the second
+    second_dot: bool,
+    first_fetch: bool,
+    window: bool,
+    /// Tile column of the fetcher, relative to the scroll or window start
+    fetch_x: u8,
+    tile_num: u8,
+    data_low: u8,
+    data_high: u8,
+    /// Sprites selected for the line which haven't been fetched yet
+    line_sprites: Vec<Sprite>,
+    sprite_fetch: Option<(Sprite, u8)>,
+}
+
+impl Fifo {
+    pub fn new() -> Fifo {
+        Fifo {
+            x: 0,
+            discard: 0,
+            bg: VecDeque::with_capacity(16),
+            sprites: VecDeque::with_capacity(8),
+            step: FetchStep::Tile,
+            second_dot: false,
+            first_fetch: true,
+            window: false,
+            fetch_x: 0,
+            tile_num: 0,
+            data_low: 0,
+            data_high: 0,
+            line_sprites: Vec::new(),
+            sprite_fetch: None,
+        }
+    }
+}
+
+impl GPU {
+    /// Sets the FIFO up for the line at the start of pixel transfer
+    pub(super) fn start_fifo(&mut self) {
+        let mut fifo = Fifo::new();
+        fifo.discard = self.scroll_x % 8;
+        fifo.line_sprites = self.line_sprites.clone();
+        self.fifo = fifo;
+    }
+
+    /// Advances pixel transfer by one dot, returning whether the line has
+    /// been drawn
+    pub(super) fn fifo_dot(&mut self) -> bool {
+        if self.fifo.x >= V_SCANLINE_MAX {
+            return true;
+        }
+
+        if let Some((sprite, dots)) = self.fifo.sprite_fetch {
+            if dots > 1 {
+                self.fifo.sprite_fetch = Some((sprite, dots - 1));
+            } else {
+                self.fifo.sprite_fetch = None;
+                self.mix_sprite(&sprite);
+            }
+            return false;
+        }
+
+        self.check_window();
+
+        if let Some(index) = self.pending_sprite() {
+            if self.is_fetcher_idle() {
+                let sprite = self.fifo.line_sprites.remove(index);
+                self.fifo.sprite_fetch = Some((sprite, SPRITE_FETCH_DOTS));
+            } else {
+                self.fetch_tick();
+            }
+            return false;
+        }
+
+        self.fetch_tick();
+        self.shift_pixel();
+        self.fifo.x >= V_SCANLINE_MAX
+    }
+
+    /// Restarts the fetcher on the window once it is reached on the line
+    fn check_window(&mut self) {
+        let fifo = &self.fifo;
+        if fifo.window || fifo.discard > 0 || !self.control.window_on {
+            return;
+        }
+
+        if !self.window_y_latched || fifo.x + 7 < self.window_x {
+            return;
+        }
+
+        self.window_drawn = true;
+        let fifo = &mut self.fifo;
+        fifo.window = true;
+        fifo.bg.clear();
+        fifo.step = FetchStep::Tile;
+        fifo.second_dot = false;
+        fifo.fetch_x = 0;
+
+        // A window left of the screen edge starts with its leftmost pixels
+        // already shifted out
+        fifo.discard = 7u8.saturating_sub(self.window_x);
+    }
+
+    /// The first sprite on the line which starts at the current pixel
+    fn pending_sprite(&self) -> Option<usize> {
+        if !self.control.obj_on || self.fifo.discard > 0 {
+            return None;
+        }
+
+        let x = self.fifo.x + 8;
+        self.fifo
+            .line_sprites
+            .iter()
+            .position(|sprite| sprite.x != 0 && sprite.x <= x)
+    }
+
+    /// Sprites are fetched once the background FIFO has pixels and the
+    /// fetcher is between rows
+    fn is_fetcher_idle(&self) -> bool {
+        let fifo = &self.fifo;
+        !fifo.bg.is_empty()
+            && (fifo.step == FetchStep::Push || (fifo.step == FetchStep::Tile && !fifo.second_dot))
+    }
+
+    fn fetch_tick(&mut self) {
+        if self.fifo.step == FetchStep::Push {
+            self.push_row();
+            return;
+        }
+
+        if !self.fifo.second_dot {
+            self.fifo.second_dot = true;
+            return;
+        }
+        self.fifo.second_dot = false;
+
+        match self.fifo.step {
+            FetchStep::Tile => {
+                self.fifo.tile_num = self.fetch_tile_num();
+                self.fifo.step = FetchStep::DataLow;
+            }
+            FetchStep::DataLow => {
+                self.fifo.data_low = self.fetch_tile_data(0);
+                self.fifo.step = FetchStep::DataHigh;
+            }
+            FetchStep::DataHigh => {
+                self.fifo.data_high = self.fetch_tile_data(1);
+                self.fifo.step = if self.fifo.first_fetch {
+                    self.fifo.first_fetch = false;
+                    FetchStep::Tile
+                } else {
+                    FetchStep::Push
+                };
+            }
+            FetchStep::Push => unreachable!(),
+        }
+    }
+
+    /// Pushes the fetched row once the background FIFO is empty
+    fn push_row(&mut self) {
+        let fifo = &mut self.fifo;
+        if !fifo.bg.is_empty() {
+            return;
+        }
+
+        fifo.step = FetchStep::Tile;
+        for bit in (0..8).rev() {
+            let color = ((fifo.data_high >> bit) & 1) << 1 | ((fifo.data_low >> bit) & 1);
+            fifo.bg.push_back(color);
+        }
+        fifo.fetch_x = fifo.fetch_x.wrapping_add(1);
+    }
+
+    fn fetch_tile_num(&self) -> u8 {
+        let (map_select_bit, x, y) = if self.fifo.window {
+            (self.control.window_map, self.fifo.fetch_x, self.window_line)
+        } else {
+            let x = (self.scroll_x / 8).wrapping_add(self.fifo.fetch_x);
+            let y = self.current_line.wrapping_add(self.scroll_y);
+            (self.control.bg_map, x, y)
+        };
+
+        let tile_map = if map_select_bit {
+            &self.tile_map_1
+        } else {
+            &self.tile_map_0
+        };
+        tile_map[(y as usize / 8) * 32 + (x as usize % 32)]
+    }
+
+    fn fetch_tile_data(&self, plane: usize) -> u8 {
+        let y = if self.fifo.window {
+            self.window_line
+        } else {
+            self.current_line.wrapping_add(self.scroll_y)
+        };
+
+        let tile = self.get_tile_data(self.fifo.tile_num);
+        tile.bytes[(y as usize % 8) * 2 + plane]
+    }
+
+    /// Mixes a fetched sprite row into the sprite FIFO. Pixels already in
+    /// the FIFO belong to sprites with priority, so only transparent ones
+    /// are replaced.
+    fn mix_sprite(&mut self, sprite: &Sprite) {
+        let (tile, y_pos) = self.get_sprite_row(sprite);
+
+        let fifo = &mut self.fifo;
+        while fifo.sprites.len() < 8 {
+            fifo.sprites.push_back(SpritePixel::default());
+        }
+
+        // Sprites partly left of the screen, or of the pixel they were
+        // reached at, have their first pixels cut off
+        let skip = (fifo.x + 8).saturating_sub(sprite.x);
+        for x_offset in skip..8 {
+            let x_pos = if sprite.flags.flip_x {
+                7 - x_offset
+            } else {
+                x_offset
+            };
+
+            let slot = &mut fifo.sprites[(x_offset - skip) as usize];
+            if slot.color != 0 {
+                continue;
+            }
+
+            *slot = SpritePixel {
+                color: u8::from(&tile.get_color(y_pos, x_pos)),
+                palette: sprite.flags.palette,
+                priority: sprite.flags.priority,
+            };
+        }
+    }
+
+    fn shift_pixel(&mut self) {
+        let bg = match self.fifo.bg.pop_front() {
+            Some(color) => color,
+            None => return,
+        };
+
+        if self.fifo.discard > 0 {
+            self.fifo.discard -= 1;
+            return;
+        }
+
+        let sprite = self.fifo.sprites.pop_front().unwrap_or_default();
+        let bg = if self.control.bg_on { bg } else { 0 };
+
+        let sprite_visible =
+            self.control.obj_on && sprite.color != 0 && !(sprite.priority && bg != 0);
+
+        let (color, layer) = if sprite_visible {
+            if sprite.palette {
+                (
+                    self.obj_palette_1.map(Color::from(sprite.color)),
+                    Layer::Obj1,
+                )
+            } else {
+                (
+                    self.obj_palette_0.map(Color::from(sprite.color)),
+                    Layer::Obj0,
+                )
+            }
+        } else {
+            (self.bg_palette.map(Color::from(bg)), Layer::Background)
+        };
+
+        self.set_pixel(self.fifo.x, self.current_line, layer, color);
+        self.fifo.x += 1;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::test::drawn_line;
+    use super::super::test::finish_transfer;
+    use super::super::test::line_gpu;
+    use super::super::test::transfer;
+    use super::super::Mode;
+    use super::super::Renderer;
+    use super::*;
+    use crate::gameboy::irq::IRQ;
+
+    #[test]
+    fn pixel_transfer_length() {
+        let mut gpu = line_gpu(Renderer::Fifo);
+        assert_eq!(transfer(&mut gpu), 43);
+        assert_eq!(gpu.remaining_cycles, Mode::HBlank.cycles());
+
+        // Fine scrolling discards a dot per pixel, rounded up to the cycle
+        let mut gpu = line_gpu(Renderer::Fifo);
+        gpu.set_scroll_x(5);
+        assert_eq!(transfer(&mut gpu), 45);
+        assert_eq!(gpu.remaining_cycles, Mode::HBlank.cycles() - 2);
+
+        // Sprites stall the FIFO while they are fetched
+        let mut gpu = line_gpu(Renderer::Fifo);
+        gpu.write_oam(0, 16);
+        gpu.write_oam(1, 8 + 80);
+        assert!(transfer(&mut gpu) > 43);
+    }
+
+    #[test]
+    fn mid_line_palette_change() {
+        let mut gpu = line_gpu(Renderer::Fifo);
+        gpu.tile_map_0.iter_mut().for_each(|tile| *tile = 1);
+
+        let mut irq = IRQ::enabled();
+        gpu.switch_mode(Mode::PixelTransfer, &mut irq);
+        for _ in 0..20 {
+            gpu.emulate(&mut irq);
+        }
+        gpu.set_bg_palette(0b00_00_00_00);
+        finish_transfer(&mut gpu);
+
+        let line = drawn_line(&gpu);
+        assert_eq!(line[0], Color::Black);
+        assert_eq!(line[159], Color::White);
+
+        // The change lands where the FIFO was: 80 dots in, after the 12
+        // dots it takes to fill it
+        let changed = line.iter().position(|&color| color == Color::White);
+        assert_eq!(changed, Some(80 - 12));
+    }
+
+    #[test]
+    fn window() {
+        let mut gpu = line_gpu(Renderer::Fifo);
+        gpu.tile_map_1.iter_mut().for_each(|tile| *tile = 1);
+        gpu.set_window_x(7 + 100);
+        gpu.set_control(0b1111_0011);
+        transfer(&mut gpu);
+
+        let line = drawn_line(&gpu);
+        assert_eq!(line[99], Color::White);
+        assert_eq!(line[100], Color::Black);
+        assert_eq!(line[159], Color::Black);
+    }
+}
@@ -1,7 +1,18 @@
+mod fifo;
+
+use std::fmt;
+use std::str::FromStr;
+
+use self::fifo::Fifo;
 use crate::bits;
 use crate::gameboy::display::VideoDisplay;
+use crate::gameboy::display::SCREEN_HEIGHT;
+use crate::gameboy::display::SCREEN_WIDTH;
 use crate::gameboy::irq::Interrupt;
 use crate::gameboy::irq::IRQ;
+use crate::gameboy::palette::ColorPalette;
+use crate::gameboy::palette::Layer;
+use crate::gameboy::palette::Rgb;
 use crate::gameboy::Color;
 
 // LCDC CPU cycle lengths
@@ -18,6 +29,8 @@ const MODE_FLAG_PIXEL_TRANSFER: u8 = 0b11;
 const STAT_UNUSED: u8 = 0b1000_0000;
 
 const V_SCANLINE_MAX: u8 = 160;
+// The window isn't reached on a line past WX 166
+const WINDOW_X_MAX: u8 = 166;
 const H_SCANLINE_MAX: u8 = 144;
 const H_SCANLINE_VBLANK_MAX: u8 = 153;
 
@@ -27,6 +40,42 @@ const BYTES_PER_TILE: usize = 16;
 const TILE_MAP_SIZE: usize = 0x400;
 
 const NUM_SPRITES: usize = 40;
+const MAX_SPRITES_PER_LINE: usize = 10;
+
+// Dots in each CPU cycle
+const DOTS_PER_CYCLE: u8 = 4;
+
+/// How lines are drawn. The pixel FIFO follows the PPU dot by dot, so
+/// registers changed in the middle of a line take effect there; drawing
+/// whole scanlines is faster, but only sees the registers at the end of
+/// pixel transfer.
+#[derive(Debug, Default, PartialEq, Eq, Copy, Clone)]
+pub enum Renderer {
+    Scanline,
+    #[default]
+    Fifo,
+}
+
+impl fmt::Display for Renderer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Renderer::Scanline => write!(f, "scanline"),
+            Renderer::Fifo => write!(f, "fifo"),
+        }
+    }
+}
+
+impl FromStr for Renderer {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Renderer, String> {
+        match text {
+            "scanline" => Ok(Renderer::Scanline),
+            "fifo" => Ok(Renderer::Fifo),
+            _ => Err(format!("unknown renderer {}", text)),
+        }
+    }
+}
 
 impl From<u8> for Color {
     fn from(byte: u8) -> Color {
@@ -317,20 +366,37 @@ pub struct GPU {
     scroll_y: u8,
     window_x: u8,
     window_y: u8,
+    window_y_latched: bool,
+    window_line: u8,
+    window_drawn: bool,
     control: Control,
     stat: Stat,
+    stat_line: bool,
+    lcd_starting: bool,
+    skip_frame: bool,
     bg_palette: Palette,
     obj_palette_0: Palette,
     obj_palette_1: Palette,
+    colors: ColorPalette,
     tile_map_0: [u8; TILE_MAP_SIZE],
     tile_map_1: [u8; TILE_MAP_SIZE],
     tile_data: [Tile; NUM_TILES],
     sprites: [Sprite; NUM_SPRITES],
+    line_sprites: Vec<Sprite>,
+    renderer: Renderer,
+    fifo: Fifo,
+    /// The frame being drawn
+    drawing: Vec<Rgb>,
+    /// The last frame drawn completely
+    frame: Vec<Rgb>,
     display: Box<dyn VideoDisplay>,
 }
 
 impl GPU {
-    pub fn new(display: Box<dyn VideoDisplay>) -> GPU {
+    pub fn new(renderer: Renderer, display: Box<dyn VideoDisplay>) -> GPU {
+        let colors = ColorPalette::default();
+        let white = colors.rgb(Layer::Background, Color::White);
+
         GPU {
             current_line: 0,
             compare_line: 0,
@@ -340,15 +406,27 @@ impl GPU {
             scroll_y: 0,
             window_x: 0,
             window_y: 0,
+            window_y_latched: false,
+            window_line: 0,
+            window_drawn: false,
             control: Control::new(),
             stat: Stat::new(),
+            stat_line: false,
+            lcd_starting: false,
+            skip_frame: false,
             bg_palette: Palette::new(),
             obj_palette_0: Palette::new(),
             obj_palette_1: Palette::new(),
+            colors,
             tile_map_0: [0; TILE_MAP_SIZE],
             tile_map_1: [0; TILE_MAP_SIZE],
             tile_data: [Tile::new(); NUM_TILES],
             sprites: [Sprite::new(); NUM_SPRITES],
+            line_sprites: Vec::with_capacity(MAX_SPRITES_PER_LINE),
+            renderer,
+            fifo: Fifo::new(),
+            drawing: vec![white; SCREEN_WIDTH * SCREEN_HEIGHT],
+            frame: vec![white; SCREEN_WIDTH * SCREEN_HEIGHT],
             display: display,
         }
     }
@@ -358,19 +436,102 @@ impl GPU {
     }
 
     pub fn set_control(&mut self, value: u8) {
-        self.control = Control::from(value)
+        let control = Control::from(value);
+        if self.control.lcd_on && !control.lcd_on {
+            self.turn_lcd_off();
+        } else if !self.control.lcd_on && control.lcd_on {
+            self.turn_lcd_on();
+        }
+        self.control = control
+    }
+
+    /// While the LCD is off, LY reads 0, STAT reads mode 0 and the screen
+    /// is blank
+    fn turn_lcd_off(&mut self) {
+        self.current_line = 0;
+        self.current_mode = Mode::HBlank;
+        self.stat.mode_flag = MODE_FLAG_HBLANK;
+        self.stat_line = false;
+        self.window_y_latched = false;
+        self.window_line = 0;
+        self.window_drawn = false;
+        let white = self.colors.rgb(Layer::Background, Color::White);
+        self.drawing.fill(white);
+        self.frame.fill(white);
+        self.display.blank(white);
+    }
+
+    /// The LCD starts on line 0 without an OAM scan, reading mode 0 for a
+    /// cycle less than the scan would take. The first frame isn't shown.
+    fn turn_lcd_on(&mut self) {
+        self.current_line = 0;
+        self.current_mode = Mode::HBlank;
+        self.stat.mode_flag = MODE_FLAG_HBLANK;
+        self.remaining_cycles = OAM_CYCLES - 1;
+        self.lcd_starting = true;
+        self.skip_frame = true;
+        self.check_compare_line();
+    }
+
+    /// The last frame drawn completely, in the format given to the display
+    pub fn framebuffer(&self) -> &[Rgb] {
+        &self.frame
+    }
+
+    /// Frames drawn from now on use the colors of the palette
+    pub fn set_color_palette(&mut self, palette: ColorPalette) {
+        self.colors = palette;
+    }
+
+    fn set_pixel(&mut self, x: u8, y: u8, layer: Layer, color: Color) {
+        self.drawing[y as usize * SCREEN_WIDTH + x as usize] = self.colors.rgb(layer, color);
+    }
+
+    /// VRAM is read by the PPU while it draws a line
+    pub fn is_vram_busy(&self) -> bool {
+        self.control.lcd_on && self.current_mode == Mode::PixelTransfer
+    }
+
+    /// OAM is read by the PPU while it searches for sprites and draws a line
+    pub fn is_oam_busy(&self) -> bool {
+        self.control.lcd_on
+            && (self.current_mode == Mode::OAM || self.current_mode == Mode::PixelTransfer)
+    }
+
+    /// Writing STAT on DMG era hardware briefly enables every source but
+    /// OAM, which raises the STAT line during HBlank or VBlank, or while LY
+    /// matches LYC.
+    /// Like any other source, it doesn't request an interrupt if the line
+    /// is already high.
+    pub fn trigger_stat_write_bug(&mut self, irq: &mut IRQ) {
+        let all_sources = Stat {
+            access_oam_interrupt: false,
+            ..Stat::from(0xFF)
+        };
+        let hit = self.control.lcd_on && self.is_stat_source_active(&all_sources);
+
+        if hit && !self.stat_line {
+            irq.set_interrupt(&Interrupt::LCDC);
+        }
+        self.stat_line |= hit;
     }
 
     pub fn get_stat(&self) -> u8 {
         if self.control.lcd_on {
             u8::from(&self.stat)
         } else {
-            STAT_UNUSED
+            u8::from(&self.stat) & !0b11
         }
     }
 
+    /// Only the interrupt selection can be written, the coincidence and mode
+    /// flags are kept
     pub fn set_stat(&mut self, value: u8) {
-        self.stat = Stat::from(value)
+        self.stat = Stat {
+            line_compare: self.stat.line_compare,
+            mode_flag: self.stat.mode_flag,
+            ..Stat::from(value)
+        }
     }
 
     pub fn get_current_line(&self) -> u8 {
@@ -385,8 +546,12 @@ impl GPU {
         self.compare_line
     }
 
+    /// LY is compared with the new value straight away
     pub fn set_compare_line(&mut self, value: u8) {
-        self.compare_line = value
+        self.compare_line = value;
+        if self.control.lcd_on {
+            self.check_compare_line();
+        }
     }
 
     pub fn get_scroll_x(&self) -> u8 {
@@ -496,7 +661,24 @@ impl GPU {
             return;
         }
 
+        self.advance(irq);
+        self.update_stat_line(irq);
+    }
+
+    fn advance(&mut self, irq: &mut IRQ) {
         self.remaining_cycles -= 1;
+        if self.current_mode == Mode::PixelTransfer && self.renderer == Renderer::Fifo {
+            self.transfer_pixels(irq);
+            return;
+        }
+
+        // LY only reads 153 for the first cycle of the last line, and then
+        // already reads 0
+        if self.current_line == H_SCANLINE_VBLANK_MAX {
+            self.current_line = 0;
+            self.check_compare_line();
+        }
+
         if self.remaining_cycles > 0 {
             return;
         }
@@ -507,141 +689,196 @@ impl GPU {
                 self.draw_scanline();
                 self.switch_mode(Mode::HBlank, irq);
             }
+            Mode::HBlank if self.lcd_starting => {
+                self.lcd_starting = false;
+                self.switch_mode(Mode::PixelTransfer, irq);
+            }
             Mode::HBlank => {
                 self.current_line += 1;
-                self.check_compare_line(irq);
+                self.check_compare_line();
 
                 if self.current_line < H_SCANLINE_MAX {
                     self.switch_mode(Mode::OAM, irq);
                 } else {
-                    self.display.vsync();
+                    if self.skip_frame {
+                        self.skip_frame = false;
+                    } else {
+                        self.frame.copy_from_slice(&self.drawing);
+                        self.display.vsync(&self.frame);
+                    }
                     self.switch_mode(Mode::VBlank, irq);
                 }
             }
             Mode::VBlank => {
-                self.current_line += 1;
-                self.check_compare_line(irq);
-
-                if self.current_line < H_SCANLINE_VBLANK_MAX {
+                if self.current_line == 0 {
+                    // The end of the last line, which LY has read 0 on
+                    self.switch_mode(Mode::OAM, irq);
+                } else {
                     // Reset cycles to be able to continue incrementing scanline
                     // but do not actually switch mode (no interrupts)
+                    self.current_line += 1;
+                    self.check_compare_line();
                     self.remaining_cycles = Mode::VBlank.cycles();
-                } else {
-                    self.current_line = 0;
-                    self.switch_mode(Mode::OAM, irq);
                 }
             }
         }
     }
 
+    /// The STAT interrupt sources are ORed into a single line, and the
+    /// interrupt is only requested when the line rises. A source becoming
+    /// active while another one already is doesn't request it again.
+    fn update_stat_line(&mut self, irq: &mut IRQ) {
+        let stat_line = self.is_stat_source_active(&self.stat);
+        if stat_line && !self.stat_line {
+            irq.set_interrupt(&Interrupt::LCDC);
+        }
+        self.stat_line = stat_line;
+    }
+
+    fn is_stat_source_active(&self, stat: &Stat) -> bool {
+        let mode = match self.current_mode {
+            Mode::OAM => stat.access_oam_interrupt,
+            Mode::PixelTransfer => false,
+            Mode::HBlank => stat.hblank_interrupt && !self.lcd_starting,
+            // The OAM source is also active for the first cycle of VBlank
+            Mode::VBlank => {
+                stat.vblank_interrupt
+                    || (stat.access_oam_interrupt
+                        && self.current_line == H_SCANLINE_MAX
+                        && self.remaining_cycles == VBLANK_CYCLES)
+            }
+        };
+
+        mode || (stat.line_compare_interrupt && stat.line_compare)
+    }
+
+    /// Runs the pixel FIFO for a cycle. Pixel transfer lasts as long as the
+    /// line takes to draw, and HBlank makes up the rest of the line.
+    fn transfer_pixels(&mut self, irq: &mut IRQ) {
+        let mut done = false;
+        for _ in 0..DOTS_PER_CYCLE {
+            done = self.fifo_dot();
+        }
+
+        if done {
+            let remaining_cycles = self.remaining_cycles;
+            self.switch_mode(Mode::HBlank, irq);
+            self.remaining_cycles += remaining_cycles;
+        }
+    }
+
     fn switch_mode(&mut self, mode: Mode, irq: &mut IRQ) {
         self.stat.mode_flag = mode.flag_bits();
         self.remaining_cycles = mode.cycles();
         self.current_mode = mode;
 
         match self.current_mode {
-            Mode::OAM => {
-                if self.stat.access_oam_interrupt {
-                    irq.set_interrupt(&Interrupt::LCDC);
-                }
-            }
+            Mode::OAM => {}
             Mode::HBlank => {
-                if self.stat.hblank_interrupt {
-                    irq.set_interrupt(&Interrupt::LCDC);
+                if self.window_drawn {
+                    self.window_drawn = false;
+                    self.window_line = self.window_line.wrapping_add(1);
                 }
             }
             Mode::VBlank => {
+                self.window_y_latched = false;
+                self.window_line = 0;
                 irq.set_interrupt(&Interrupt::VBlank);
-                if self.stat.vblank_interrupt {
-                    irq.set_interrupt(&Interrupt::LCDC);
+            }
+            Mode::PixelTransfer => {
+                self.oam_scan();
+                self.window_y_latched |= self.current_line == self.window_y;
+                if self.renderer == Renderer::Fifo {
+                    self.start_fifo();
                 }
             }
-            _ => {}
         };
     }
 
-    fn check_compare_line(&mut self, irq: &mut IRQ) {
+    fn check_compare_line(&mut self) {
         self.stat.line_compare = self.current_line == self.compare_line;
-        if self.stat.line_compare && self.stat.line_compare_interrupt {
-            irq.set_interrupt(&Interrupt::LCDC);
-        }
     }
 
     fn draw_scanline(&mut self) {
+        // Color indexes before the palette, which sprites are drawn against
+        let mut bg_line = [0; V_SCANLINE_MAX as usize];
+
         if self.control.bg_on {
-            self.draw_bg();
+            self.draw_bg(&mut bg_line);
+        } else {
+            self.draw_blank_line();
         }
 
         if self.control.window_on {
-            self.draw_window();
+            self.draw_window(&mut bg_line);
         }
 
         if self.control.obj_on {
-            self.draw_sprites();
+            self.draw_sprites(&bg_line);
         }
     }
 
-    fn draw_window(&mut self) {
-        let window_y = self.window_y;
-        let window_x = self.window_x.wrapping_sub(7);
-
-        if self.current_line < window_y {
+    /// The window is drawn from the line WY matched LY on in the frame, and
+    /// its rows are counted separately, so that lines it isn't drawn on
+    /// don't skip any
+    fn draw_window(&mut self, bg_line: &mut [u8]) {
+        if !self.window_y_latched || self.window_x > WINDOW_X_MAX {
             return;
         }
 
-        let y_pos = self.current_line.wrapping_sub(window_y);
+        // Below WX 7 the window starts at the left edge, with its first
+        // pixels cut off
+        let start = self.window_x.saturating_sub(7);
+        let y_pos = self.window_line;
+        self.window_drawn = true;
 
-        for col in 0..V_SCANLINE_MAX {
-            if col < window_x {
-                continue;
-            }
+        // With LCDC.0 clear the window is blank as well, but its rows are
+        // still counted
+        if !self.control.bg_on {
+            return;
+        }
 
-            let x_pos = col.wrapping_sub(window_x);
+        for col in start..V_SCANLINE_MAX {
+            let x_pos = col + 7 - self.window_x;
             let color = self.get_window_pixel(y_pos, x_pos);
-            self.display.set_pixel(col, self.current_line, color);
+            bg_line[col as usize] = u8::from(&color);
+            let color = self.bg_palette.map(color);
+            self.set_pixel(col, self.current_line, Layer::Background, color);
         }
     }
 
-    fn draw_bg(&mut self) {
+    /// With LCDC.0 clear, the background and window are drawn in color 0
+    fn draw_blank_line(&mut self) {
+        let color = self.bg_palette.map(Color::White);
+        for col in 0..V_SCANLINE_MAX {
+            self.set_pixel(col, self.current_line, Layer::Background, color);
+        }
+    }
+
+    fn draw_bg(&mut self, bg_line: &mut [u8]) {
         let y_pos = self.current_line.wrapping_add(self.scroll_y);
 
         for col in 0..V_SCANLINE_MAX {
             let x_pos = col.wrapping_add(self.scroll_x);
             let color = self.get_bg_pixel(y_pos, x_pos);
-            self.display.set_pixel(col, self.current_line, color);
+            bg_line[col as usize] = u8::from(&color);
+            let color = self.bg_palette.map(color);
+            self.set_pixel(col, self.current_line, Layer::Background, color);
         }
     }
 
-    fn draw_sprites(&mut self) {
-        let y_size: u8 = if self.control.obj_size { 16 } else { 8 };
-
-        for &sprite in self.sprites.iter() {
-            if !self.should_draw_sprite(&sprite, y_size) {
-                continue;
-            }
-
-            let palette = if sprite.flags.palette {
-                &self.obj_palette_1
-            } else {
-                &self.obj_palette_0
-            };
-
-            let sprite_y = self.current_line.wrapping_sub(sprite.get_y());
-            let mut y_pos = if sprite.flags.flip_y {
-                y_size - sprite_y - 1
-            } else {
-                sprite_y
-            };
+    /// Each pixel is taken by the first sprite with a visible pixel there,
+    /// in order of X and then of OAM. A sprite with the priority flag set
+    /// is hidden behind background colors other than 0.
+    fn draw_sprites(&mut self, bg_line: &[u8]) {
+        let mut sprites = self.line_sprites.clone();
+        sprites.sort_by_key(|sprite| sprite.x);
 
-            let mut tile_num = sprite.tile_num;
-            if y_pos >= 8 {
-                // Go to the next tile, and normalize the y pos
-                tile_num += 1;
-                y_pos -= 8;
-            }
+        let mut pixels: [Option<(Sprite, Color)>; V_SCANLINE_MAX as usize] =
+            [None; V_SCANLINE_MAX as usize];
 
-            // Sprite tiles start at 0x8000, which is where tile_data is based
-            let tile = self.tile_data[tile_num as usize];
+        for sprite in sprites {
+            let (tile, y_pos) = self.get_sprite_row(&sprite);
             for x_offset in 0..8 {
                 let x_pos = if sprite.flags.flip_x {
                     7 - x_offset
@@ -650,8 +887,8 @@ impl GPU {
                 };
 
                 let color = tile.get_color(y_pos, x_pos);
-                if color == Color::White {
-                    // White is transparent for sprite drawing
+                if u8::from(&color) == 0 {
+                    // Color 0 is transparent for sprites
                     continue;
                 }
 
@@ -661,17 +898,71 @@ impl GPU {
                     continue;
                 }
 
-                let color = palette.map(color);
-                self.display.set_pixel(col, self.current_line, color);
+                let pixel = &mut pixels[col as usize];
+                if pixel.is_none() {
+                    *pixel = Some((sprite, color));
+                }
             }
         }
+
+        for (col, pixel) in pixels.iter().enumerate() {
+            let (sprite, color) = match pixel {
+                Some(pixel) => *pixel,
+                None => continue,
+            };
+
+            if sprite.flags.priority && bg_line[col] != 0 {
+                continue;
+            }
+
+            let (palette, layer) = if sprite.flags.palette {
+                (&self.obj_palette_1, Layer::Obj1)
+            } else {
+                (&self.obj_palette_0, Layer::Obj0)
+            };
+            let color = palette.map(color);
+            self.set_pixel(col as u8, self.current_line, layer, color);
+        }
     }
 
-    /// Sprites should only be drawn when they intersect with the scanline
-    fn should_draw_sprite(&self, sprite: &Sprite, y_size: u8) -> bool {
-        let y_pos = sprite.get_y();
-        let line = self.current_line;
-        return y_pos <= line && line < (y_pos + y_size);
+    /// The tile and row within it of a sprite on the scanline. The tile of
+    /// the top half of 8x16 sprites has bit 0 ignored.
+    fn get_sprite_row(&self, sprite: &Sprite) -> (Tile, u8) {
+        let y_size: u8 = if self.control.obj_size { 16 } else { 8 };
+        let sprite_y = self.current_line.wrapping_sub(sprite.get_y());
+        let y_pos = if sprite.flags.flip_y {
+            y_size - sprite_y - 1
+        } else {
+            sprite_y
+        };
+
+        let tile_num = if y_size == 16 {
+            (sprite.tile_num & 0xFE) + y_pos / 8
+        } else {
+            sprite.tile_num
+        };
+
+        // Sprite tiles start at 0x8000, which is where tile_data is based
+        (self.tile_data[tile_num as usize], y_pos % 8)
+    }
+
+    /// Selects the first ten sprites in OAM which intersect with the
+    /// scanline, whether or not they are on screen horizontally
+    fn oam_scan(&mut self) {
+        let y_size: u16 = if self.control.obj_size { 16 } else { 8 };
+        let line = self.current_line as u16 + 16;
+
+        self.line_sprites.clear();
+        for &sprite in self.sprites.iter() {
+            if line.wrapping_sub(sprite.y as u16) >= y_size {
+                continue;
+            }
+
+            self.line_sprites.push(sprite);
+            if self.line_sprites.len() == MAX_SPRITES_PER_LINE {
+                break;
+            }
+        }
     }
 
     fn get_bg_pixel(&self, y_pos: u8, x_pos: u8) -> Color {
@@ -682,6 +973,7 @@ impl GPU {
         self.get_pixel(y_pos, x_pos, self.control.window_map)
     }
 
+    /// Returns the color of a background or window pixel, before the palette
     fn get_pixel(&self, y_pos: u8, x_pos: u8, map_select_bit: bool) -> Color {
         let tile_row = y_pos / 8;
         let tile_col = x_pos / 8;
@@ -689,9 +981,7 @@ impl GPU {
 
         let tile_y = y_pos % 8;
         let tile_x = x_pos % 8;
-        let raw_color = tile.get_color(tile_y, tile_x);
-
-        self.bg_palette.map(raw_color)
+        tile.get_color(tile_y, tile_x)
     }
 
     /// Given a tile row and col, and tile map, returns the tile via the proper semantics
@@ -707,7 +997,12 @@ impl GPU {
         let offset = (row as usize) * 32 + (col as usize);
         let tile_num = tile_map[offset];
 
-        // Next, use the tile number to find the corresponding data
+        self.get_tile_data(tile_num)
+    }
+
+    /// Finds the data of a background or window tile, which is addressed
+    /// from 0x8000 or signed from 0x9000 depending on LCDC
+    fn get_tile_data(&self, tile_num: u8) -> &Tile {
         if self.control.bg_data {
             &self.tile_data[tile_num as usize]
         } else {
@@ -723,15 +1018,67 @@ impl GPU {
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::gameboy::display::test::NoDisplay;
+    use crate::gameboy::display::NoDisplay;
+    use std::cell::RefCell;
+    use std::rc::Rc;
 
     impl GPU {
         fn test() -> GPU {
             let display = NoDisplay::new();
-            GPU::new(Box::new(display))
+            GPU::new(Renderer::Scanline, Box::new(display))
         }
     }
 
+    /// A GPU on the first line, with tile 1 solid black and tile 2 in the
+    /// lightest color. The window shows tile 3 from the map at 0x9C00.
+    pub(super) fn line_gpu(renderer: Renderer) -> GPU {
+        let mut gpu = GPU::new(renderer, Box::new(NoDisplay::new()));
+
+        for row in 0..16 {
+            gpu.set_tile_row(0x10 + row, 0xFF);
+            gpu.set_tile_row(0x20 + row, if row % 2 == 0 { 0xFF } else { 0x00 });
+        }
+        gpu.set_bg_palette(0b11_10_01_00);
+        gpu.set_obj_palette_0(0b11_10_01_00);
+        gpu.set_control(0b1001_0011);
+
+        // Row 1 of tile 3 is black, and the last column the lightest color
+        gpu.set_tile_row(0x32, 0xFF);
+        gpu.set_tile_row(0x33, 0xFF);
+        gpu.set_tile_row(0x30, 0x01);
+        gpu.tile_map_1.iter_mut().for_each(|tile| *tile = 3);
+
+        gpu
+    }
+
+    /// The pixels drawn on the current line of the frame being drawn, in
+    /// the default palette, which has the same colors for every layer
+    pub(super) fn drawn_line(gpu: &GPU) -> Vec<Color> {
+        let shades = ColorPalette::default().bg;
+        let start = gpu.current_line as usize * SCREEN_WIDTH;
+        gpu.drawing[start..start + SCREEN_WIDTH]
+            .iter()
+            .map(|rgb| Color::from(shades.iter().position(|shade| shade == rgb).unwrap() as u8))
+            .collect()
+    }
+
+    /// Starts pixel transfer and runs it to the end, returning how many
+    /// cycles it took
+    pub(super) fn transfer(gpu: &mut GPU) -> i32 {
+        gpu.switch_mode(Mode::PixelTransfer, &mut IRQ::enabled());
+        finish_transfer(gpu)
+    }
+
+    pub(super) fn finish_transfer(gpu: &mut GPU) -> i32 {
+        let mut irq = IRQ::enabled();
+        let mut cycles = 0;
+        while gpu.current_mode == Mode::PixelTransfer {
+            gpu.emulate(&mut irq);
+            cycles += 1;
+        }
+        cycles
+    }
+
     #[test]
     fn palette_from_u8() {
         let palette = Palette::from(0b11_10_01_00);
@@ -865,10 +1212,31 @@ mod test {
         gpu.set_stat(0xFF);
 
         gpu.control.lcd_on = false;
-        assert_eq!(gpu.get_stat(), 0x80);
+        assert_eq!(gpu.get_stat(), 0xF8);
 
         gpu.control.lcd_on = true;
-        assert_eq!(gpu.get_stat(), 0xFF);
+        assert_eq!(gpu.get_stat(), 0xF8 | MODE_FLAG_ACCESS_OAM);
+    }
+
+    #[test]
+    fn gpu_busy_memory() {
+        let mut gpu = GPU::test();
+
+        gpu.current_mode = Mode::PixelTransfer;
+        assert!(!gpu.is_vram_busy());
+        assert!(!gpu.is_oam_busy());
+
+        gpu.control.lcd_on = true;
+        assert!(gpu.is_vram_busy());
+        assert!(gpu.is_oam_busy());
+
+        gpu.current_mode = Mode::OAM;
+        assert!(!gpu.is_vram_busy());
+        assert!(gpu.is_oam_busy());
+
+        gpu.current_mode = Mode::HBlank;
+        assert!(!gpu.is_vram_busy());
+        assert!(!gpu.is_oam_busy());
     }
 
     #[test]
@@ -940,9 +1308,7 @@ mod test {
         let mut irq = IRQ::enabled();
 
         gpu.control.lcd_on = true;
-        gpu.stat.access_oam_interrupt = true;
         gpu.stat.hblank_interrupt = true;
-        gpu.stat.vblank_interrupt = true;
 
         gpu.remaining_cycles = 1;
         gpu.emulate(&mut irq);
@@ -956,6 +1322,11 @@ mod test {
         assert_eq!(gpu.current_mode, Mode::HBlank);
         assert_eq!(irq.ack_interrupt(), Some(Interrupt::LCDC.get_addr()));
 
+        // Each source is turned off and the line left to fall before the
+        // next, or the next would be blocked
+        gpu.stat.hblank_interrupt = false;
+        gpu.emulate(&mut irq);
+        gpu.stat.vblank_interrupt = true;
         gpu.current_line = H_SCANLINE_MAX - 1;
         gpu.remaining_cycles = 1;
         gpu.emulate(&mut irq);
@@ -964,14 +1335,106 @@ mod test {
         assert_eq!(irq.ack_interrupt(), Some(Interrupt::VBlank.get_addr()));
         assert_eq!(irq.ack_interrupt(), Some(Interrupt::LCDC.get_addr()));
 
-        gpu.current_line = V_SCANLINE_MAX - 1;
+        gpu.stat.vblank_interrupt = false;
+        gpu.emulate(&mut irq);
+        gpu.stat.access_oam_interrupt = true;
+        gpu.current_line = H_SCANLINE_VBLANK_MAX;
+        gpu.remaining_cycles = 1;
+        gpu.emulate(&mut irq);
+
+        assert_eq!(gpu.current_mode, Mode::OAM);
+        assert_eq!(irq.ack_interrupt(), Some(Interrupt::LCDC.get_addr()));
+    }
+
+    #[test]
+    fn gpu_stat_blocking() {
+        let mut gpu = GPU::test();
+        let mut irq = IRQ::enabled();
+
+        gpu.control.lcd_on = true;
+        gpu.stat.hblank_interrupt = true;
+        gpu.stat.access_oam_interrupt = true;
+        gpu.switch_mode(Mode::PixelTransfer, &mut irq);
         gpu.remaining_cycles = 1;
         gpu.emulate(&mut irq);
+        assert_eq!(irq.ack_interrupt(), Some(Interrupt::LCDC.get_addr()));
 
+        // The line stays high from HBlank into OAM, so OAM is blocked
+        gpu.remaining_cycles = 1;
+        gpu.emulate(&mut irq);
         assert_eq!(gpu.current_mode, Mode::OAM);
+        assert_eq!(irq.ack_interrupt(), None);
+
+        // LYC matching while the line is high is blocked too
+        gpu.stat.line_compare_interrupt = true;
+        gpu.set_compare_line(gpu.current_line);
+        gpu.emulate(&mut irq);
+        assert_eq!(irq.ack_interrupt(), None);
+
+        // Until the line falls again
+        gpu.stat.hblank_interrupt = false;
+        gpu.stat.access_oam_interrupt = false;
+        gpu.set_compare_line(0xFF);
+        gpu.emulate(&mut irq);
+        gpu.set_compare_line(gpu.current_line);
+        gpu.emulate(&mut irq);
         assert_eq!(irq.ack_interrupt(), Some(Interrupt::LCDC.get_addr()));
     }
 
+    #[test]
+    fn gpu_stat_oam_at_vblank() {
+        let mut gpu = GPU::test();
+        let mut irq = IRQ::enabled();
+
+        // The OAM source fires once more when VBlank starts
+        gpu.control.lcd_on = true;
+        gpu.stat.access_oam_interrupt = true;
+        gpu.switch_mode(Mode::HBlank, &mut irq);
+        gpu.current_line = H_SCANLINE_MAX - 1;
+        gpu.remaining_cycles = 1;
+        gpu.emulate(&mut irq);
+
+        assert_eq!(irq.ack_interrupt(), Some(Interrupt::VBlank.get_addr()));
+        assert_eq!(irq.ack_interrupt(), Some(Interrupt::LCDC.get_addr()));
+        assert!(gpu.stat_line);
+
+        gpu.emulate(&mut irq);
+        assert!(!gpu.stat_line);
+    }
+
+    #[test]
+    fn gpu_last_line() {
+        let mut gpu = GPU::test();
+        let mut irq = IRQ::enabled();
+
+        gpu.control.lcd_on = true;
+        gpu.stat.line_compare_interrupt = true;
+        gpu.set_compare_line(0);
+        gpu.switch_mode(Mode::VBlank, &mut irq);
+        irq.ack_interrupt();
+
+        gpu.current_line = H_SCANLINE_VBLANK_MAX - 1;
+        gpu.remaining_cycles = 1;
+        gpu.emulate(&mut irq);
+        assert_eq!(gpu.get_current_line(), H_SCANLINE_VBLANK_MAX);
+
+        // LY reads 0, and matches LYC, from the second cycle of line 153
+        gpu.emulate(&mut irq);
+        assert_eq!(gpu.get_current_line(), 0);
+        assert_eq!(gpu.current_mode, Mode::VBlank);
+        assert_eq!(irq.ack_interrupt(), Some(Interrupt::LCDC.get_addr()));
+
+        // Line 0 starts at the end of line 153, without matching again
+        let mut cycles = 1;
+        while gpu.current_mode == Mode::VBlank {
+            gpu.emulate(&mut irq);
+            cycles += 1;
+        }
+        assert_eq!(cycles, VBLANK_CYCLES);
+        assert_eq!(gpu.get_current_line(), 0);
+        assert_eq!(irq.ack_interrupt(), None);
+    }
+
     #[test]
     fn gpu_emulate_vblank() {
         let mut gpu = GPU::test();
@@ -995,7 +1458,7 @@ mod test {
         assert_eq!(gpu.current_line, H_SCANLINE_MAX + 1);
         assert_eq!(irq.ack_interrupt(), None);
 
-        gpu.current_line = V_SCANLINE_MAX - 1;
+        gpu.current_line = H_SCANLINE_VBLANK_MAX;
         gpu.remaining_cycles = 1;
         gpu.emulate(&mut irq);
 
@@ -1029,4 +1492,332 @@ mod test {
         assert_eq!(gpu.stat.line_compare, false);
         assert_eq!(irq.ack_interrupt(), None);
     }
+
+    /// Sprites put in front of background color 0 by OAM order: tile 1 is
+    /// solid black and tile 2 the lightest color
+    fn sprite_line(renderer: Renderer, sprites: &[(u8, u8, u8)]) -> Vec<Color> {
+        let mut gpu = line_gpu(renderer);
+        for (i, &(x, tile_num, flags)) in sprites.iter().enumerate() {
+            let address = i as u8 * 4;
+            gpu.write_oam(address, 16);
+            gpu.write_oam(address + 1, x);
+            gpu.write_oam(address + 2, tile_num);
+            gpu.write_oam(address + 3, flags);
+        }
+
+        transfer(&mut gpu);
+        drawn_line(&gpu)
+    }
+
+    #[test]
+    fn gpu_oam_scan() {
+        let mut gpu = GPU::test();
+        for i in 0..12 {
+            gpu.write_oam(i * 4, 16);
+            gpu.write_oam(i * 4 + 1, i + 1);
+        }
+
+        // Sprites off screen horizontally still count
+        gpu.write_oam(4 * 4 + 1, 0);
+        gpu.oam_scan();
+        let selected: Vec<u8> = gpu.line_sprites.iter().map(|sprite| sprite.x).collect();
+        assert_eq!(selected, vec![1, 2, 3, 4, 0, 6, 7, 8, 9, 10]);
+
+        gpu.current_line = 8;
+        gpu.oam_scan();
+        assert!(gpu.line_sprites.is_empty());
+
+        // Sprites partly above the screen, and 8x16 sprites
+        gpu.write_oam(0, 10);
+        gpu.write_oam(4, 2);
+        gpu.control.obj_size = true;
+        gpu.oam_scan();
+        let selected: Vec<u8> = gpu.line_sprites.iter().map(|sprite| sprite.y).collect();
+        assert_eq!(&selected[..2], &[10, 16]);
+
+        gpu.current_line = 1;
+        gpu.oam_scan();
+        let selected: Vec<u8> = gpu.line_sprites.iter().map(|sprite| sprite.y).collect();
+        assert_eq!(&selected[..3], &[10, 2, 16]);
+    }
+
+    #[test]
+    fn gpu_sprite_limit() {
+        for &renderer in &[Renderer::Scanline, Renderer::Fifo] {
+            let sprites: Vec<(u8, u8, u8)> = (0..11).map(|i| (8 + i * 10, 1, 0)).collect();
+            let line = sprite_line(renderer, &sprites);
+
+            assert_eq!(line[90], Color::Black, "{}", renderer);
+            assert_eq!(line[100], Color::White, "{}", renderer);
+        }
+    }
+
+    #[test]
+    fn gpu_sprite_x_priority() {
+        for &renderer in &[Renderer::Scanline, Renderer::Fifo] {
+            // A lower X wins, even from later in OAM
+            let line = sprite_line(renderer, &[(8 + 20, 2, 0), (8 + 16, 1, 0)]);
+            assert_eq!(&line[16..24], &[Color::Black; 8], "{}", renderer);
+            assert_eq!(&line[24..28], &[Color::Light; 4], "{}", renderer);
+
+            // With the same X, the first in OAM wins
+            let line = sprite_line(renderer, &[(8 + 20, 2, 0), (8 + 20, 1, 0)]);
+            assert_eq!(&line[20..28], &[Color::Light; 8], "{}", renderer);
+        }
+    }
+
+    #[test]
+    fn gpu_sprite_bg_priority() {
+        for &renderer in &[Renderer::Scanline, Renderer::Fifo] {
+            let mut gpu = line_gpu(renderer);
+            for tile in gpu.tile_map_0.iter_mut().step_by(2) {
+                *tile = 2;
+            }
+
+            // Sprites in front of and behind the colored background tiles
+            gpu.write_oam(0, 16);
+            gpu.write_oam(1, 8 + 4);
+            gpu.write_oam(2, 1);
+            gpu.write_oam(4, 16);
+            gpu.write_oam(5, 8 + 20);
+            gpu.write_oam(6, 1);
+            gpu.write_oam(7, 0x80);
+            transfer(&mut gpu);
+
+            let line = drawn_line(&gpu);
+            assert_eq!(&line[4..12], &[Color::Black; 8], "{}", renderer);
+            assert_eq!(&line[20..24], &[Color::Light; 4], "{}", renderer);
+            assert_eq!(&line[24..28], &[Color::Black; 4], "{}", renderer);
+        }
+    }
+
+    #[test]
+    fn gpu_sprite_transparency() {
+        for &renderer in &[Renderer::Scanline, Renderer::Fifo] {
+            // Color 0 is transparent even when the palette makes it black,
+            // and other colors aren't when the palette makes them white
+            let mut gpu = line_gpu(renderer);
+            gpu.set_obj_palette_0(0b00_00_00_11);
+            gpu.write_oam(0, 16);
+            gpu.write_oam(1, 8);
+            gpu.write_oam(2, 0);
+            gpu.write_oam(4, 16);
+            gpu.write_oam(5, 8 + 4);
+            gpu.write_oam(6, 1);
+            gpu.set_bg_palette(0b11_11_11_01);
+            transfer(&mut gpu);
+
+            let line = drawn_line(&gpu);
+            assert_eq!(&line[0..4], &[Color::Light; 4], "{}", renderer);
+            assert_eq!(&line[4..12], &[Color::White; 8], "{}", renderer);
+        }
+    }
+
+    /// Draws a line with the window on or off, and returns the first pixel
+    /// of the window on it
+    fn draw_window_line(gpu: &mut GPU, window_on: bool) -> Color {
+        gpu.control.window_on = window_on;
+        transfer(gpu);
+        let color = drawn_line(gpu)[10];
+        gpu.current_line += 1;
+        color
+    }
+
+    #[test]
+    fn gpu_window_line_counter() {
+        for &renderer in &[Renderer::Scanline, Renderer::Fifo] {
+            let mut gpu = line_gpu(renderer);
+            gpu.set_control(0b1111_0011);
+            gpu.set_window_x(7);
+            gpu.set_window_y(1);
+
+            assert_eq!(draw_window_line(&mut gpu, true), Color::White);
+            assert_eq!(draw_window_line(&mut gpu, true), Color::White);
+            assert_eq!(draw_window_line(&mut gpu, false), Color::White);
+            assert_eq!(gpu.window_line, 1);
+
+            // The row after the last drawn, not the line below WY
+            assert_eq!(
+                draw_window_line(&mut gpu, true),
+                Color::Black,
+                "{}",
+                renderer
+            );
+            assert_eq!(gpu.window_line, 2);
+
+            // Off screen to the right, the window isn't drawn or counted
+            gpu.set_window_x(167);
+            draw_window_line(&mut gpu, true);
+            assert_eq!(gpu.window_line, 2);
+
+            // Counting restarts with the frame
+            gpu.switch_mode(Mode::VBlank, &mut IRQ::enabled());
+            assert_eq!(gpu.window_line, 0);
+        }
+    }
+
+    #[test]
+    fn gpu_window_y_latch() {
+        for &renderer in &[Renderer::Scanline, Renderer::Fifo] {
+            let mut gpu = line_gpu(renderer);
+            gpu.set_control(0b1111_0011);
+            gpu.set_window_x(7);
+            gpu.set_window_y(2);
+
+            // Moving WY above the line doesn't start the window
+            draw_window_line(&mut gpu, true);
+            gpu.set_window_y(0);
+            draw_window_line(&mut gpu, true);
+            assert_eq!(gpu.window_line, 0, "{}", renderer);
+
+            // Once WY has matched, moving it keeps the window
+            gpu.set_window_y(2);
+            draw_window_line(&mut gpu, true);
+            gpu.set_window_y(100);
+            assert_eq!(draw_window_line(&mut gpu, true), Color::Black);
+            assert_eq!(gpu.window_line, 2, "{}", renderer);
+        }
+    }
+
+    #[test]
+    fn gpu_window_x_edges() {
+        for &renderer in &[Renderer::Scanline, Renderer::Fifo] {
+            let mut gpu = line_gpu(renderer);
+            gpu.set_control(0b1111_0011);
+
+            // WX 0 cuts off the first seven pixels of the window
+            gpu.set_window_x(0);
+            transfer(&mut gpu);
+            assert_eq!(drawn_line(&gpu)[0], Color::Light, "{}", renderer);
+            assert_eq!(drawn_line(&gpu)[1], Color::White, "{}", renderer);
+            assert_eq!(drawn_line(&gpu)[8], Color::Light, "{}", renderer);
+
+            // WX 166 only shows the first pixel of the window
+            let mut gpu = line_gpu(renderer);
+            gpu.set_control(0b1111_0011);
+            gpu.tile_map_0.iter_mut().for_each(|tile| *tile = 1);
+            gpu.set_window_x(166);
+            transfer(&mut gpu);
+            assert_eq!(drawn_line(&gpu)[158], Color::Black, "{}", renderer);
+            assert_eq!(drawn_line(&gpu)[159], Color::White, "{}", renderer);
+        }
+    }
+
+    #[test]
+    fn gpu_framebuffer() {
+        for &renderer in &[Renderer::Scanline, Renderer::Fifo] {
+            let mut gpu = line_gpu(renderer);
+            let mut irq = IRQ::enabled();
+            gpu.set_color_palette(ColorPalette::uniform([0xFFFFFF, 0xAAAAAA, 0x555555, 0]));
+            gpu.tile_map_0.iter_mut().for_each(|tile| *tile = 1);
+
+            // Frames are only published once drawn, and the first one after
+            // the LCD is turned on isn't
+            for _ in 0..154 * 114 {
+                gpu.emulate(&mut irq);
+            }
+            assert!(gpu.framebuffer().iter().all(|&rgb| rgb != 0));
+
+            for _ in 0..154 * 114 {
+                gpu.emulate(&mut irq);
+            }
+            assert_eq!(gpu.framebuffer().len(), SCREEN_WIDTH * SCREEN_HEIGHT);
+            assert!(gpu.framebuffer().iter().all(|&rgb| rgb == 0));
+
+            gpu.set_control(0x00);
+            assert!(gpu.framebuffer().iter().all(|&rgb| rgb == 0xFFFFFF));
+        }
+    }
+
+    #[test]
+    fn gpu_layer_colors() {
+        for &renderer in &[Renderer::Scanline, Renderer::Fifo] {
+            let mut gpu = line_gpu(renderer);
+            gpu.set_color_palette(ColorPalette {
+                bg: [0x000010, 0x000011, 0x000012, 0x000013],
+                obj0: [0x000020, 0x000021, 0x000022, 0x000023],
+                obj1: [0x000030, 0x000031, 0x000032, 0x000033],
+            });
+            gpu.set_obj_palette_1(0b11_10_01_00);
+            gpu.write_oam(0, 16);
+            gpu.write_oam(1, 8);
+            gpu.write_oam(2, 1);
+            gpu.write_oam(4, 16);
+            gpu.write_oam(5, 8 + 8);
+            gpu.write_oam(6, 1);
+            gpu.write_oam(7, 0x10);
+            transfer(&mut gpu);
+
+            // The colors come after the DMG palettes have picked the shade
+            let line = &gpu.drawing[..SCREEN_WIDTH];
+            assert_eq!(line[0], 0x000023, "{}", renderer);
+            assert_eq!(line[8], 0x000033, "{}", renderer);
+            assert_eq!(line[16], 0x000010, "{}", renderer);
+        }
+    }
+
+    /// Counts the frames shown and blanked
+    struct FrameCounter {
+        frames: Rc<RefCell<(u32, u32)>>,
+    }
+
+    impl VideoDisplay for FrameCounter {
+        fn vsync(&mut self, _frame: &[Rgb]) {
+            self.frames.borrow_mut().0 += 1;
+        }
+
+        fn blank(&mut self, _white: Rgb) {
+            self.frames.borrow_mut().1 += 1;
+        }
+    }
+
+    #[test]
+    fn gpu_lcd_off() {
+        let mut gpu = line_gpu(Renderer::Scanline);
+        let mut irq = IRQ::enabled();
+        gpu.tile_map_0.iter_mut().for_each(|tile| *tile = 1);
+        transfer(&mut gpu);
+        assert_eq!(drawn_line(&gpu)[0], Color::Black);
+        gpu.current_line = 100;
+
+        gpu.set_control(0x00);
+        assert_eq!(gpu.get_current_line(), 0);
+        assert_eq!(gpu.get_stat() & 0b11, MODE_FLAG_HBLANK);
+        assert_eq!(drawn_line(&gpu)[0], Color::White);
+
+        for _ in 0..1000 {
+            gpu.emulate(&mut irq);
+        }
+        assert_eq!(gpu.get_current_line(), 0);
+        assert_eq!(irq.ack_interrupt(), None);
+    }
+
+    #[test]
+    fn gpu_lcd_on() {
+        let frames = Rc::new(RefCell::new((0, 0)));
+        let display = FrameCounter {
+            frames: frames.clone(),
+        };
+        let mut gpu = GPU::new(Renderer::Scanline, Box::new(display));
+        let mut irq = IRQ::enabled();
+
+        // Line 0 reads mode 0 instead of the OAM scan, and is a cycle short
+        gpu.stat.hblank_interrupt = true;
+        gpu.set_control(0x80);
+        for _ in 0..OAM_CYCLES - 1 {
+            assert_eq!(gpu.get_stat() & 0b11, MODE_FLAG_HBLANK);
+            gpu.emulate(&mut irq);
+        }
+        assert_eq!(gpu.get_stat() & 0b11, MODE_FLAG_PIXEL_TRANSFER);
+        assert_eq!(irq.ack_interrupt(), None);
+
+        // The first frame isn't shown
+        for _ in 0..2 * 154 * 114 {
+            gpu.emulate(&mut irq);
+        }
+        assert_eq!(*frames.borrow(), (1, 0));
+
+        gpu.set_control(0x00);
+        assert_eq!(*frames.borrow(), (1, 1));
+    }
 }
@@ -0,0 +1,5 @@
+/// A serial sink sits on the other end of the link cable and receives every
+/// byte the GameBoy shifts out using its internal clock.
+pub trait SerialSink {
+    fn receive(&mut self, byte: u8);
+}
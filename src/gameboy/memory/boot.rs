@@ -1,3 +1,5 @@
+use crate::gameboy::Model;
+
 pub const DMG_BIN: [u8; 0x100] = [
     0x31, 0xFE, 0xFF, 0xAF, 0x21, 0xFF, 0x9F, 0x32, 0xCB, 0x7C, 0x20, 0xFB, 0x21, 0x26, 0xFF, 0x0E,
     0x11, 0x3E, 0x80, 0x32, 0xE2, 0x0C, 0x3E, 0xF3, 0xE2, 0x32, 0x3E, 0x77, 0x77, 0x3E, 0xFC, 0xE0,
@@ -16,3 +18,111 @@ pub const DMG_BIN: [u8; 0x100] = [
     0x21, 0x04, 0x01, 0x11, 0xA8, 0x00, 0x1A, 0x13, 0xBE, 0x00, 0x00, 0x23, 0x7D, 0xFE, 0x34, 0x20,
     0xF5, 0x06, 0x19, 0x78, 0x86, 0x23, 0x05, 0x20, 0xFB, 0x86, 0x00, 0x00, 0x3E, 0x01, 0xE0, 0x50,
 ];
+
+/// I/O registers as the DMG boot ROM leaves them. DIV isn't set by writing,
+/// and writing 0xFF50 unmaps the boot ROM. IF comes last, as writing the
+/// others can request interrupts, such as through the STAT write bug.
+pub const DMG_IO: [(u16, u8); 38] = [
+    (0xFF00, 0xCF),
+    (0xFF01, 0x00),
+    (0xFF02, 0x7E),
+    (0xFF05, 0x00),
+    (0xFF06, 0x00),
+    (0xFF07, 0xF8),
+    (0xFF10, 0x80),
+    (0xFF11, 0xBF),
+    (0xFF12, 0xF3),
+    (0xFF13, 0xFF),
+    (0xFF14, 0xBF),
+    (0xFF16, 0x3F),
+    (0xFF17, 0x00),
+    (0xFF18, 0xFF),
+    (0xFF19, 0xBF),
+    (0xFF1A, 0x7F),
+    (0xFF1B, 0xFF),
+    (0xFF1C, 0x9F),
+    (0xFF1D, 0xFF),
+    (0xFF1E, 0xBF),
+    (0xFF20, 0xFF),
+    (0xFF21, 0x00),
+    (0xFF22, 0x00),
+    (0xFF23, 0xBF),
+    (0xFF24, 0x77),
+    (0xFF25, 0xF3),
+    (0xFF26, 0xF1),
+    (0xFF40, 0x91),
+    (0xFF41, 0x85),
+    (0xFF42, 0x00),
+    (0xFF43, 0x00),
+    (0xFF45, 0x00),
+    (0xFF47, 0xFC),
+    (0xFF4A, 0x00),
+    (0xFF4B, 0x00),
+    (0xFF50, 0x01),
+    (0xFFFF, 0x00),
+    (0xFF0F, 0xE1),
+];
+
+/// I/O registers as the boot ROM of a model leaves them, which only differ
+/// from the DMG's in a few registers
+pub fn boot_io(model: Model) -> Vec<(u16, u8)> {
+    let changes: &[(u16, u8)] = match model {
+        Model::Dmg0 | Model::Dmg | Model::Mgb => &[],
+        // The SGB boot ROM plays no sound, so channel 1 is left off
+        Model::Sgb => &[(0xFF26, 0xF0)],
+        // The CGB boot ROM leaves both clock bits of SC set, and has a few
+        // more registers
+        Model::Cgb | Model::Agb => &[
+            (0xFF02, 0x7F),
+            (0xFF72, 0x00),
+            (0xFF73, 0x00),
+            (0xFF75, 0x8F),
+        ],
+    };
+
+    let mut io = DMG_IO.to_vec();
+    for &(address, byte) in changes {
+        match io.iter_mut().find(|(a, _)| *a == address) {
+            Some(register) => register.1 = byte,
+            // Before IF, which has to be written last
+            None => io.insert(io.len() - 1, (address, byte)),
+        }
+    }
+    io
+}
+
+/// The writes with which the boot ROM draws the logo from the cartridge
+/// header, followed by the ® from the boot ROM itself, to VRAM. Each nibble
+/// of the logo becomes two rows of a tile, with every pixel doubled.
+pub fn logo_vram(logo: &[u8]) -> Vec<(u16, u8)> {
+    let double = |nibble: u8| {
+        (0..4)
+            .filter(|bit| nibble & (1 << bit) != 0)
+            .fold(0, |byte, bit| byte | (0b11 << (bit * 2)))
+    };
+
+    let mut rows = Vec::new();
+    for byte in logo {
+        for nibble in &[byte >> 4, byte & 0x0F] {
+            rows.push(double(*nibble));
+            rows.push(double(*nibble));
+        }
+    }
+    rows.extend_from_slice(&DMG_BIN[0xD8..0xE0]);
+
+    // Only the low bit plane of each row is drawn
+    let mut writes: Vec<(u16, u8)> = rows
+        .iter()
+        .enumerate()
+        .map(|(i, row)| (0x8010 + i as u16 * 2, *row))
+        .collect();
+
+    // Two rows of twelve tiles, with the ® after the first row
+    writes.push((0x9910, 0x19));
+    for tile in 0..12 {
+        writes.push((0x9904 + tile, tile as u8 + 0x01));
+        writes.push((0x9924 + tile, tile as u8 + 0x0D));
+    }
+
+    writes
+}
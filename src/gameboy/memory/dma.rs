@@ -1,32 +1,45 @@
 const DMA_CYCLES: u8 = 160;
+/// M-cycles between writing the DMA register and the first byte transferred
+const STARTUP_CYCLES: u8 = 1;
 
+/// OAM DMA, which copies 160 bytes from `source << 8` to OAM one byte per
+/// M-cycle. Writing the register while a transfer is running restarts it
+/// once the new transfer has started up.
 pub struct DMA {
     source: u8,
     offset: u8,
     running: bool,
+    starting: Option<(u8, u8)>,
 }
 
 impl DMA {
     pub fn new() -> DMA {
         DMA {
-            source: 0x0,
+            source: 0xFF,
             offset: 0,
             running: false,
+            starting: None,
         }
     }
 
     pub fn initialize(&mut self, addr_high: u8) {
-        if self.running {
-            println!("DMA already running (0x{:x})", addr_high);
-            return;
-        }
-
-        self.source = addr_high;
-        self.offset = 0;
-        self.running = true;
+        self.starting = Some((addr_high, STARTUP_CYCLES));
     }
 
+    /// Advances by an M-cycle, giving the address of the byte to copy if a
+    /// transfer is running
     pub fn emulate(&mut self) -> Option<u16> {
+        match self.starting {
+            Some((source, 0)) => {
+                self.starting = None;
+                self.source = source;
+                self.offset = 0;
+                self.running = true;
+            }
+            Some((source, delay)) => self.starting = Some((source, delay - 1)),
+            None => (),
+        }
+
         if self.running {
             Some(self.get_next_address())
         } else {
@@ -35,7 +48,7 @@ impl DMA {
     }
 
     pub fn get_source(&self) -> u8 {
-        self.source
+        self.starting.map_or(self.source, |(source, _)| source)
     }
 
     fn get_next_address(&mut self) -> u16 {
@@ -46,7 +59,12 @@ impl DMA {
             self.running = false;
         }
 
-        address
+        // Sources from 0xE000 up read work RAM instead of OAM and I/O
+        if address >= 0xE000 {
+            address - 0x2000
+        } else {
+            address
+        }
     }
 
     fn is_complete(&self) -> bool {
@@ -58,26 +76,32 @@ impl DMA {
 mod test {
     use super::*;
 
+    fn start(dma: &mut DMA, source: u8) {
+        dma.initialize(source);
+        for _ in 0..STARTUP_CYCLES {
+            assert_eq!(dma.emulate(), None);
+        }
+    }
+
     #[test]
     fn initialize() {
         let mut dma = DMA::new();
 
         dma.initialize(0xC1);
 
-        assert_eq!(dma.source, 0xC1);
-        assert_eq!(dma.offset, 0);
-        assert_eq!(dma.running, true);
-
-        dma.initialize(0xA1);
+        assert_eq!(dma.get_source(), 0xC1);
+        assert!(!dma.running);
 
-        assert_eq!(dma.source, 0xC1);
+        assert_eq!(dma.emulate(), None);
+        assert_eq!(dma.emulate(), Some(0xC100));
+        assert!(dma.running);
     }
 
     #[test]
     fn emulate() {
         let mut dma = DMA::new();
 
-        dma.initialize(0xC1);
+        start(&mut dma, 0xC1);
 
         let address = dma.emulate();
 
@@ -94,7 +118,7 @@ mod test {
     fn emulate_stop_after_complete() {
         let mut dma = DMA::new();
 
-        dma.initialize(0xC1);
+        start(&mut dma, 0xC1);
 
         for _ in 0..DMA_CYCLES {
             let address = dma.emulate();
@@ -107,4 +131,27 @@ mod test {
         assert_eq!(address, None);
         assert_eq!(dma.running, false);
     }
+
+    #[test]
+    fn restart() {
+        let mut dma = DMA::new();
+
+        start(&mut dma, 0xC1);
+        dma.emulate();
+        dma.initialize(0xD2);
+
+        // The old transfer carries on while the new one starts up
+        assert_eq!(dma.emulate(), Some(0xC101));
+        assert_eq!(dma.emulate(), Some(0xD200));
+        assert_eq!(dma.get_source(), 0xD2);
+    }
+
+    #[test]
+    fn echo_sources() {
+        let mut dma = DMA::new();
+
+        start(&mut dma, 0xFE);
+
+        assert_eq!(dma.emulate(), Some(0xDE00));
+    }
 }
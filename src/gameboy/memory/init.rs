@@ -0,0 +1,125 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::gameboy::Model;
+
+/// What RAM holds at power on. Real hardware comes up with mostly random
+/// contents, so filling RAM with something other than zeros brings out
+/// games that read memory they never wrote.
+#[derive(Debug, Default, PartialEq, Copy, Clone)]
+pub enum RamInit {
+    #[default]
+    Zero,
+    Ones,
+    /// The regular patterns RAM of each model tends to power on with,
+    /// without the random bits that are flipped on real hardware
+    Pattern,
+    /// Random contents from a seed, so that runs can be reproduced
+    Random(u64),
+}
+
+impl RamInit {
+    /// The power-on contents of an area of memory. Each area is identified
+    /// by a number so that random areas don't repeat each other.
+    pub fn contents(self, model: Model, area: u64, len: usize) -> Vec<u8> {
+        match self {
+            RamInit::Zero => vec![0x00; len],
+            RamInit::Ones => vec![0xFF; len],
+            RamInit::Pattern => (0..len).map(|offset| pattern(model, offset)).collect(),
+            RamInit::Random(seed) => {
+                let mut state = seed ^ area.wrapping_mul(0xA076_1D64_78BD_642F);
+                (0..len).map(|_| splitmix64(&mut state) as u8).collect()
+            }
+        }
+    }
+}
+
+impl fmt::Display for RamInit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RamInit::Zero => write!(f, "zero"),
+            RamInit::Ones => write!(f, "ones"),
+            RamInit::Pattern => write!(f, "pattern"),
+            RamInit::Random(seed) => write!(f, "random:0x{:X}", seed),
+        }
+    }
+}
+
+impl FromStr for RamInit {
+    type Err = String;
+
+    /// Parses `zero`, `ones`, `pattern` or `random:seed`, with the seed in
+    /// decimal or in hex starting with `0x`
+    fn from_str(text: &str) -> Result<RamInit, String> {
+        let invalid = || format!("unknown RAM init {}", text);
+
+        match text {
+            "zero" => Ok(RamInit::Zero),
+            "ones" => Ok(RamInit::Ones),
+            "pattern" => Ok(RamInit::Pattern),
+            _ if text.starts_with("random:") => {
+                let seed = &text["random:".len()..];
+                let seed = match seed.strip_prefix("0x") {
+                    Some(hex) => u64::from_str_radix(hex, 16),
+                    None => seed.parse(),
+                };
+                seed.map(RamInit::Random).map_err(|_| invalid())
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// Work RAM on the CGB tends to alternate between runs of 0x00 and 0xFF
+/// every 8 bytes, and on older models every 16 bytes
+fn pattern(model: Model, offset: usize) -> u8 {
+    let run = if model.is_cgb() { 8 } else { 16 };
+    if (offset / run) & 1 == 0 {
+        0x00
+    } else {
+        0xFF
+    }
+}
+
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_and_display() {
+        for text in &["zero", "ones", "pattern", "random:0x1F2E"] {
+            assert_eq!(*text, text.parse::<RamInit>().unwrap().to_string());
+        }
+        assert_eq!(Ok(RamInit::Random(42)), "random:42".parse());
+        assert!("random".parse::<RamInit>().is_err());
+        assert!("random:0xZZ".parse::<RamInit>().is_err());
+    }
+
+    #[test]
+    fn random_is_reproducible() {
+        let first = RamInit::Random(1).contents(Model::Dmg, 0, 0x100);
+
+        assert_eq!(first, RamInit::Random(1).contents(Model::Dmg, 0, 0x100));
+        assert_ne!(first, RamInit::Random(2).contents(Model::Dmg, 0, 0x100));
+        assert_ne!(first, RamInit::Random(1).contents(Model::Dmg, 1, 0x100));
+    }
+
+    #[test]
+    fn patterns() {
+        let dmg = RamInit::Pattern.contents(Model::Dmg, 0, 0x20);
+        assert_eq!(&[0x00; 16][..], &dmg[..16]);
+        assert_eq!(&[0xFF; 16][..], &dmg[16..]);
+
+        let cgb = RamInit::Pattern.contents(Model::Cgb, 0, 0x10);
+        assert_eq!(&[0x00; 8][..], &cgb[..8]);
+        assert_eq!(&[0xFF; 8][..], &cgb[8..]);
+    }
+}
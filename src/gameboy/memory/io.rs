@@ -0,0 +1,119 @@
+use crate::gameboy::Model;
+
+/// Unmapped I/O addresses and unused register bits read back as 1 on a DMG
+pub const OPEN_BUS: u8 = 0xFF;
+
+/// How the bits of a DMG I/O register behave. Bits in the read mask always
+/// read as 1, because they are unused or write only, and writes only change
+/// the bits in the write mask.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct Register {
+    pub read_mask: u8,
+    pub write_mask: u8,
+}
+
+/// Looks up the I/O register at an address in 0xFF00-0xFF7F or at 0xFFFF.
+/// Returns `None` for addresses where nothing is mapped. The CGB locks its
+/// own registers in DMG compatibility mode, so they read as unmapped, except
+/// for a few undocumented ones.
+pub fn register(address: u16, model: Model) -> Option<Register> {
+    let (read_mask, write_mask) = match address {
+        // The CGB adds a clock speed bit
+        0xFF02 if model.is_cgb() => (0x7C, 0x83), // SC
+        0xFF00 => (0xC0, 0x30),                   // P1
+        0xFF01 => (0x00, 0xFF),                   // SB
+        0xFF02 => (0x7E, 0x81),                   // SC
+        0xFF04 => (0x00, 0xFF),                   // DIV, any write resets it
+        0xFF05 => (0x00, 0xFF),                   // TIMA
+        0xFF06 => (0x00, 0xFF),                   // TMA
+        0xFF07 => (0xF8, 0x07),                   // TAC
+        0xFF0F => (0xE0, 0x1F),                   // IF
+        0xFF10 => (0x80, 0x7F),                   // NR10
+        0xFF11 => (0x3F, 0xFF),                   // NR11
+        0xFF12 => (0x00, 0xFF),                   // NR12
+        0xFF13 => (0xFF, 0xFF),                   // NR13
+        0xFF14 => (0xBF, 0xC7),                   // NR14
+        0xFF16 => (0x3F, 0xFF),                   // NR21
+        0xFF17 => (0x00, 0xFF),                   // NR22
+        0xFF18 => (0xFF, 0xFF),                   // NR23
+        0xFF19 => (0xBF, 0xC7),                   // NR24
+        0xFF1A => (0x7F, 0x80),                   // NR30
+        0xFF1B => (0xFF, 0xFF),                   // NR31
+        0xFF1C => (0x9F, 0x60),                   // NR32
+        0xFF1D => (0xFF, 0xFF),                   // NR33
+        0xFF1E => (0xBF, 0xC7),                   // NR34
+        0xFF20 => (0xFF, 0x3F),                   // NR41
+        0xFF21 => (0x00, 0xFF),                   // NR42
+        0xFF22 => (0x00, 0xFF),                   // NR43
+        0xFF23 => (0xBF, 0xC0),                   // NR44
+        0xFF24 => (0x00, 0xFF),                   // NR50
+        0xFF25 => (0x00, 0xFF),                   // NR51
+        0xFF26 => (0x70, 0x80),                   // NR52, the channel flags are read only
+        0xFF30..=0xFF3F => (0x00, 0xFF),          // Wave RAM
+        0xFF40 => (0x00, 0xFF),                   // LCDC
+        0xFF41 => (0x80, 0x78),                   // STAT
+        0xFF42 => (0x00, 0xFF),                   // SCY
+        0xFF43 => (0x00, 0xFF),                   // SCX
+        0xFF44 => (0x00, 0xFF),                   // LY
+        0xFF45 => (0x00, 0xFF),                   // LYC
+        0xFF46 => (0x00, 0xFF),                   // DMA
+        0xFF47 => (0x00, 0xFF),                   // BGP
+        0xFF48 => (0x00, 0xFF),                   // OBP0
+        0xFF49 => (0x00, 0xFF),                   // OBP1
+        0xFF4A => (0x00, 0xFF),                   // WY
+        0xFF4B => (0x00, 0xFF),                   // WX
+        0xFF50 => (0xFF, 0xFF),                   // Boot ROM disable, write only
+        0xFF72 if model.is_cgb() => (0x00, 0xFF), // Undocumented
+        0xFF73 if model.is_cgb() => (0x00, 0xFF), // Undocumented
+        0xFF75 if model.is_cgb() => (0x8F, 0x70), // Undocumented
+        0xFFFF => (0x00, 0xFF),                   // IE
+        _ => return None,
+    };
+
+    Some(Register {
+        read_mask,
+        write_mask,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn unmapped_addresses() {
+        for address in &[
+            0xFF03, 0xFF08, 0xFF0E, 0xFF15, 0xFF1F, 0xFF27, 0xFF4D, 0xFF7F,
+        ] {
+            assert_eq!(None, register(*address, Model::Dmg), "0x{:04X}", address);
+        }
+    }
+
+    #[test]
+    fn masks() {
+        let stat = register(0xFF41, Model::Dmg).unwrap();
+        assert_eq!(0x80, stat.read_mask);
+        assert_eq!(0x78, stat.write_mask);
+
+        let interrupt_flags = register(0xFF0F, Model::Dmg).unwrap();
+        assert_eq!(0xE0, interrupt_flags.read_mask);
+        assert_eq!(0x1F, interrupt_flags.write_mask);
+
+        assert_eq!(0x7E, register(0xFF02, Model::Dmg).unwrap().read_mask);
+        assert_eq!(0x7C, register(0xFF02, Model::Cgb).unwrap().read_mask);
+    }
+
+    #[test]
+    fn cgb_registers() {
+        for address in &[0xFF72, 0xFF73, 0xFF75] {
+            assert_ne!(None, register(*address, Model::Cgb), "0x{:04X}", address);
+            assert_ne!(None, register(*address, Model::Agb), "0x{:04X}", address);
+            assert_eq!(None, register(*address, Model::Dmg), "0x{:04X}", address);
+        }
+
+        // Locked in DMG compatibility mode
+        for address in &[0xFF4D, 0xFF4F, 0xFF55, 0xFF68, 0xFF70, 0xFF74] {
+            assert_eq!(None, register(*address, Model::Cgb), "0x{:04X}", address);
+        }
+    }
+}
@@ -1,29 +1,53 @@
 mod boot;
 mod dma;
+mod init;
+mod io;
+
+use std::io::Error;
+use std::io::ErrorKind;
 
 use self::boot::DMG_BIN;
 use self::dma::DMA;
+pub use self::init::RamInit;
+use self::io::OPEN_BUS;
 use crate::gameboy::cartridge::Cartridge;
+use crate::gameboy::cartridge::Header;
+use crate::gameboy::cpu::Fetch;
 use crate::gameboy::cpu::MemoryBus;
+use crate::gameboy::debug::cdl;
+use crate::gameboy::debug::cdl::Block;
+use crate::gameboy::debug::cdl::CodeDataLog;
+use crate::gameboy::debug::lint::Lint;
+use crate::gameboy::debug::lint::Rule;
+use crate::gameboy::debug::Location;
 use crate::gameboy::display::VideoDisplay;
+use crate::gameboy::gpu::Renderer;
 use crate::gameboy::gpu::GPU;
 use crate::gameboy::irq::IRQ;
 use crate::gameboy::joypad::Joypad;
+use crate::gameboy::link::SerialSink;
+use crate::gameboy::palette::ColorPalette;
+use crate::gameboy::palette::Rgb;
 use crate::gameboy::serial::Serial;
 use crate::gameboy::timer::Timer;
 use crate::gameboy::Button;
+use crate::gameboy::Model;
 
-const BOOT_ROM_SIZE: usize = 0x100;
 const INTERNAL_RAM_SIZE: usize = 0x2000;
 const HIRAM_SIZE: usize = 0x7F;
+const SOUND_SIZE: usize = 0x30;
 const EMPTY_READ: u8 = 0xFF;
 
 pub struct MMU {
+    model: Model,
     elapsed_cycles: u8,
     is_checking_boot_rom: bool,
-    boot_rom: [u8; BOOT_ROM_SIZE],
+    boot_rom: Vec<u8>,
     internal_ram: [u8; INTERNAL_RAM_SIZE],
     hiram: [u8; HIRAM_SIZE],
+    sound: [u8; SOUND_SIZE],
+    // CGB registers at 0xFF72-0xFF75 that do nothing but keep their value
+    undocumented: [u8; 4],
     cartridge: Cartridge,
     gpu: GPU,
     irq: IRQ,
@@ -31,23 +55,48 @@ pub struct MMU {
     joypad: Joypad,
     serial: Serial,
     dma: DMA,
+    dma_value: Option<u8>,
+    cdl: Option<CodeDataLog>,
+    lint: Lint,
+    // The instruction being executed, for lint warnings
+    instruction: Location,
+    access_blocking: bool,
 }
 
 impl MMU {
-    pub fn new(rom: Vec<u8>, display: Box<dyn VideoDisplay>) -> MMU {
+    pub fn new(
+        rom: Vec<u8>,
+        model: Model,
+        renderer: Renderer,
+        display: Box<dyn VideoDisplay>,
+    ) -> MMU {
+        let boot_rom = if model.has_built_in_boot_rom() {
+            DMG_BIN.to_vec()
+        } else {
+            Vec::new()
+        };
+
         MMU {
+            model,
             elapsed_cycles: 0,
             is_checking_boot_rom: true,
-            boot_rom: DMG_BIN,
+            boot_rom,
             internal_ram: [0; INTERNAL_RAM_SIZE],
             hiram: [0; HIRAM_SIZE],
+            sound: [0; SOUND_SIZE],
+            undocumented: [0; 4],
             cartridge: Cartridge::new(rom),
-            gpu: GPU::new(display),
+            gpu: GPU::new(renderer, display),
             irq: IRQ::new(),
             timer: Timer::new(),
             joypad: Joypad::new(),
             serial: Serial::new(),
             dma: DMA::new(),
+            dma_value: None,
+            cdl: None,
+            lint: Lint::new(),
+            instruction: Location::new(0, 0),
+            access_blocking: true,
         }
     }
 
@@ -59,12 +108,129 @@ impl MMU {
         self.joypad.button_up(btn);
     }
 
+    pub fn connect_serial(&mut self, sink: Box<dyn SerialSink>) {
+        self.serial.connect(sink);
+    }
+
+    pub fn enable_lint(&mut self) {
+        self.lint.enable();
+    }
+
+    pub fn lint_warnings(&self) -> &[String] {
+        self.lint.warnings()
+    }
+
+    /// Lets the CPU use VRAM and OAM while the PPU is reading them, as the
+    /// hardware doesn't. Lint mode still reports those accesses.
+    pub fn set_access_blocking(&mut self, enabled: bool) {
+        self.access_blocking = enabled;
+    }
+
+    pub fn enable_cdl(&mut self, cdl: CodeDataLog) {
+        self.cdl = Some(cdl);
+    }
+
+    pub fn cdl(&self) -> Option<&CodeDataLog> {
+        self.cdl.as_ref()
+    }
+
+    /// Replaces the built in boot ROM with one dumped from hardware
+    pub fn set_boot_rom(&mut self, data: Vec<u8>) -> std::io::Result<()> {
+        MMU::check_boot_rom(self.model, &data)?;
+        self.boot_rom = data;
+        Ok(())
+    }
+
+    pub fn has_boot_rom(&self) -> bool {
+        !self.boot_rom.is_empty()
+    }
+
+    /// Checks that a boot ROM has the size of the ones for a model
+    pub fn check_boot_rom(model: Model, data: &[u8]) -> std::io::Result<()> {
+        if data.len() == model.boot_rom_size() {
+            return Ok(());
+        }
+
+        let message = format!(
+            "boot ROM is {} bytes, but {} boot ROMs are {} bytes",
+            data.len(),
+            model,
+            model.boot_rom_size()
+        );
+        Err(Error::new(ErrorKind::InvalidData, message))
+    }
+
+    /// Fills work RAM, HRAM, VRAM, OAM and cartridge RAM with their power-on
+    /// contents
+    pub fn init_ram(&mut self, init: RamInit) {
+        let model = self.model;
+        let contents = |area: u64, len: usize| init.contents(model, area, len);
+
+        self.internal_ram
+            .copy_from_slice(&contents(0, INTERNAL_RAM_SIZE));
+        self.hiram.copy_from_slice(&contents(1, HIRAM_SIZE));
+        for (offset, byte) in contents(2, 0x2000).into_iter().enumerate() {
+            self.set_byte_internal(0x8000 + offset as u16, byte);
+        }
+        for (offset, byte) in contents(3, 0xA0).into_iter().enumerate() {
+            self.gpu.write_oam(offset as u8, byte);
+        }
+        let ram = contents(4, self.cartridge.ram().len());
+        self.cartridge.fill_ram(&ram);
+    }
+
+    /// Puts VRAM and I/O in the state the boot ROM leaves them in, and
+    /// unmaps the boot ROM without running it. The logo is drawn the way
+    /// the DMG boot ROM draws it.
+    pub fn skip_boot(&mut self) {
+        for address in 0x8000..=0x9FFF {
+            self.set_byte_internal(address, 0);
+        }
+
+        let logo: Vec<u8> = (0x104..0x134)
+            .map(|address| self.cartridge.read_rom_bank0(address))
+            .collect();
+        for (address, byte) in boot::logo_vram(&logo) {
+            self.set_byte_internal(address, byte);
+        }
+
+        for (address, byte) in boot::boot_io(self.model) {
+            self.write_io(address, byte);
+        }
+        self.timer.set_counter(self.model.boot_div_counter());
+    }
+
     pub fn get_and_reset_cycles(&mut self) -> u8 {
         let cycles = self.elapsed_cycles;
         self.elapsed_cycles = 0;
         cycles
     }
 
+    /// Reads memory without letting any time pass, for debugging tools
+    pub fn peek(&self, address: u16) -> u8 {
+        self.get_byte_internal(address)
+    }
+
+    pub fn describe_mapper(&self) -> String {
+        self.cartridge.describe_mapper()
+    }
+
+    pub fn cartridge_ram(&self) -> &[u8] {
+        self.cartridge.ram()
+    }
+
+    pub fn cartridge_header(&self) -> &Header {
+        self.cartridge.header()
+    }
+
+    pub fn framebuffer(&self) -> &[Rgb] {
+        self.gpu.framebuffer()
+    }
+
+    pub fn set_color_palette(&mut self, palette: ColorPalette) {
+        self.gpu.set_color_palette(palette);
+    }
+
     fn tick_cycle(&mut self) {
         self.elapsed_cycles = self.elapsed_cycles.wrapping_add(1);
     }
@@ -74,19 +240,103 @@ impl MMU {
         self.emulate_oam_dma();
         self.gpu.emulate(&mut self.irq);
         self.timer.emulate(&mut self.irq);
+        self.serial.emulate(&mut self.irq);
     }
 
     fn emulate_oam_dma(&mut self) {
-        self.dma.emulate().map(|address| {
+        self.dma_value = self.dma.emulate().map(|address| {
+            self.log_access(address, cdl::DATA);
             let value = self.get_byte_internal(address);
             self.gpu.write_oam(address as u8, value);
+            value
+        });
+    }
+
+    /// The value the CPU sees at an address while OAM DMA has the bus. Only
+    /// HRAM and I/O can be used, OAM reads 0xFF and any other read gets the
+    /// byte being copied.
+    fn dma_conflict(&self, address: u16) -> Option<u8> {
+        let value = self.dma_value?;
+        match address {
+            0xFE00..=0xFEFF => Some(EMPTY_READ),
+            0xFF00..=0xFFFF => None,
+            _ => Some(value),
+        }
+    }
+
+    fn check_access(&mut self, address: u16) {
+        match address {
+            0xE000..=0xFDFF => self
+                .lint
+                .warn(Rule::EchoRam, address, self.instruction, || {
+                    format!(
+                        "echo RAM accessed at 0x{:04X}, which mirrors 0x{:04X}",
+                        address,
+                        address - 0x2000
+                    )
+                }),
+            0xFF00..=0xFF7F if io::register(address, self.model).is_none() => {
+                self.lint
+                    .warn(Rule::UnmappedIo, address, self.instruction, || {
+                        format!("unmapped I/O register accessed at 0x{:04X}", address)
+                    })
+            }
+            _ => (),
+        }
+    }
+
+    /// Whether the PPU is using the memory at an address, which hides it from
+    /// the CPU: reads return 0xFF and writes are dropped
+    fn is_blocked(&mut self, address: u16) -> bool {
+        let (rule, area) = match address {
+            0x8000..=0x9FFF if self.gpu.is_vram_busy() => (Rule::BlockedVram, "VRAM"),
+            0xFE00..=0xFE9F if self.gpu.is_oam_busy() => (Rule::BlockedOam, "OAM"),
+            _ => return false,
+        };
+
+        let mode = self.gpu.get_stat() & 0b11;
+        let instruction = self.instruction;
+        self.lint.warn(rule, address, instruction, || {
+            format!(
+                "{} accessed at 0x{:04X} by {} during PPU mode {}",
+                area, address, instruction, mode
+            )
         });
+
+        self.access_blocking
+    }
+
+    /// Whether the boot ROM is mapped over the cartridge at an address
+    fn is_boot_rom(&self, address: u16) -> bool {
+        self.is_checking_boot_rom
+            && self.has_boot_rom()
+            && match address >> 8 {
+                0x00 => true,
+                0x02..=0x08 => self.model.is_cgb(),
+                _ => false,
+            }
+    }
+
+    fn log_access(&mut self, address: u16, flags: u8) {
+        let (block, offset) = match address {
+            _ if self.cdl.is_none() => return,
+            _ if self.is_boot_rom(address) => return,
+            0x0000..=0x7FFF => (Block::Rom, self.cartridge.rom_offset(address)),
+            0xA000..=0xBFFF => (Block::CartRam, self.cartridge.ram_offset(address)),
+            0xC000..=0xFDFF => (Block::Wram, (address as usize - 0xC000) % 0x2000),
+            0xFF80..=0xFFFE => (Block::Hram, (address - 0xFF80) as usize),
+            _ => return,
+        };
+
+        if let Some(cdl) = &mut self.cdl {
+            cdl.mark(block, offset, flags);
+        }
     }
 
     fn get_byte_internal(&self, address: u16) -> u8 {
         let index = address as usize;
         match address >> 8 {
-            0x00 if self.is_checking_boot_rom => self.boot_rom[index],
+            _ if self.is_boot_rom(address) => self.boot_rom[index],
             0x00...0x3F => self.cartridge.read_rom_bank0(address),
             0x40...0x7F => self.cartridge.read_rom_bank1(address),
             0x80...0x97 => self.gpu.get_tile_row(address - 0x8000),
@@ -94,38 +344,14 @@ impl MMU {
             0x9C...0x9F => self.gpu.get_tile_map_1(address - 0x9C00),
             0xA0...0xBF => self.cartridge.read_ram(address),
             0xC0...0xDF => self.internal_ram[index - 0xC000],
+            0xE0..=0xFD => self.internal_ram[index - 0xE000],
             0xFE => match address & 0xFF {
                 0x00...0x9F => self.gpu.read_oam(address as u8),
                 _ => EMPTY_READ,
             },
             0xFF => match address & 0xFF {
-                0x00 => self.joypad.get_data(),
-                0x01 => self.serial.get_data(),
-                0x02 => self.serial.get_control(),
-                0x04 => self.timer.get_div(),
-                0x05 => self.timer.get_tima(),
-                0x06 => self.timer.get_tma(),
-                0x07 => self.timer.get_tac(),
-                0x0F => self.irq.get_interrupt_bits(),
-                0x10...0x14 => self.read_sound_byte(address),
-                0x16...0x2F => self.read_sound_byte(address),
-                0x30...0x3F => self.read_sound_byte(address),
-                0x40 => self.gpu.get_control(),
-                0x41 => self.gpu.get_stat(),
-                0x42 => self.gpu.get_scroll_y(),
-                0x43 => self.gpu.get_scroll_x(),
-                0x44 => self.gpu.get_current_line(),
-                0x45 => self.gpu.get_compare_line(),
-                0x46 => self.dma.get_source(),
-                0x47 => self.gpu.get_bg_palette(),
-                0x48 => self.gpu.get_obj_palette_0(),
-                0x49 => self.gpu.get_obj_palette_1(),
-                0x4A => self.gpu.get_window_y(),
-                0x4B => self.gpu.get_window_x(),
-                0x4C...0x7F => EMPTY_READ,
-                0x80...0xFE => self.hiram[index - 0xFF80],
-                0xFF => self.irq.get_enabled_bits(),
-                _ => panic!("unsupported read 0x{:X}", address),
+                0x80..=0xFE => self.hiram[index - 0xFF80],
+                _ => self.read_io(address),
             },
             _ => unreachable!(),
         }
@@ -140,54 +366,95 @@ impl MMU {
             0x9C...0x9F => self.gpu.set_tile_map_1(address - 0x9C00, byte),
             0xA0...0xBF => self.cartridge.write_ram(address, byte),
             0xC0...0xDF => self.internal_ram[index - 0xC000] = byte,
+            0xE0..=0xFD => self.internal_ram[index - 0xE000] = byte,
             0xFE => match address & 0xFF {
                 0x00...0x9F => self.gpu.write_oam(address as u8, byte),
                 _ => (),
             },
             0xFF => match address & 0xFF {
-                0x00 => self.joypad.set_data(byte),
-                0x01 => self.serial.set_data(byte),
-                0x02 => self.serial.set_control(byte),
-                0x04 => self.timer.reset_div(),
-                0x05 => self.timer.set_tima(byte),
-                0x06 => self.timer.set_tma(byte),
-                0x07 => self.timer.set_tac(byte),
-                0x0F => self.irq.set_interrupt_bits(byte),
-                0x10...0x14 => self.write_sound_byte(address, byte),
-                0x16...0x2F => self.write_sound_byte(address, byte),
-                0x30...0x3F => self.write_sound_byte(address, byte),
-                0x40 => self.gpu.set_control(byte),
-                0x41 => self.gpu.set_stat(byte),
-                0x42 => self.gpu.set_scroll_y(byte),
-                0x43 => self.gpu.set_scroll_x(byte),
-                0x44 => self.gpu.reset_current_line(),
-                0x45 => self.gpu.set_compare_line(byte),
-                0x46 => self.dma.initialize(byte),
-                0x47 => self.gpu.set_bg_palette(byte),
-                0x48 => self.gpu.set_obj_palette_0(byte),
-                0x49 => self.gpu.set_obj_palette_1(byte),
-                0x50 => self.is_checking_boot_rom = false,
-                0x4A => self.gpu.set_window_y(byte),
-                0x4B => self.gpu.set_window_x(byte),
-                0x4C...0x7F => (), // Empty
-                0x80...0xFE => self.hiram[index - 0xFF80] = byte,
-                0xFF => self.irq.set_enabled_bits(byte),
-                _ => panic!("unsupported write 0x{:X} = {:X}", address, byte),
+                0x80..=0xFE => self.hiram[index - 0xFF80] = byte,
+                _ => self.write_io(address, byte),
             },
             _ => unreachable!(),
         }
     }
 
-    fn read_sound_byte(&self, address: u16) -> u8 {
-        println!("[sound] not implemented; read (0x{:x})", address);
-        0xFF
+    fn read_io(&self, address: u16) -> u8 {
+        let register = match io::register(address, self.model) {
+            Some(register) => register,
+            None => return OPEN_BUS,
+        };
+
+        let value = match address & 0xFF {
+            0x00 => self.joypad.get_data(),
+            0x01 => self.serial.get_data(),
+            0x02 => self.serial.get_control(),
+            0x04 => self.timer.get_div(),
+            0x05 => self.timer.get_tima(),
+            0x06 => self.timer.get_tma(),
+            0x07 => self.timer.get_tac(),
+            0x0F => self.irq.get_interrupt_bits(),
+            // Sound isn't emulated, its registers only keep what was written
+            0x10..=0x3F => self.sound[address as usize - 0xFF10],
+            0x40 => self.gpu.get_control(),
+            0x41 => self.gpu.get_stat(),
+            0x42 => self.gpu.get_scroll_y(),
+            0x43 => self.gpu.get_scroll_x(),
+            0x44 => self.gpu.get_current_line(),
+            0x45 => self.gpu.get_compare_line(),
+            0x46 => self.dma.get_source(),
+            0x47 => self.gpu.get_bg_palette(),
+            0x48 => self.gpu.get_obj_palette_0(),
+            0x49 => self.gpu.get_obj_palette_1(),
+            0x4A => self.gpu.get_window_y(),
+            0x4B => self.gpu.get_window_x(),
+            0x50 => OPEN_BUS,
+            0x72..=0x75 => self.undocumented[address as usize - 0xFF72],
+            0xFF => self.irq.get_enabled_bits(),
+            _ => unreachable!("no read for I/O register 0x{:04X}", address),
+        };
+
+        value | register.read_mask
     }
 
-    fn write_sound_byte(&mut self, address: u16, byte: u8) {
-        println!(
-            "[sound] not implemented; write (0x{:x}) = 0x{:x}",
-            address, byte
-        );
+    fn write_io(&mut self, address: u16, byte: u8) {
+        let byte = match io::register(address, self.model) {
+            Some(register) => byte & register.write_mask,
+            None => return,
+        };
+
+        match address & 0xFF {
+            0x00 => self.joypad.set_data(byte),
+            0x01 => self.serial.set_data(byte),
+            0x02 => self.serial.set_control(byte),
+            0x04 => self.timer.reset_div(),
+            0x05 => self.timer.set_tima(byte),
+            0x06 => self.timer.set_tma(byte),
+            0x07 => self.timer.set_tac(byte),
+            0x0F => self.irq.set_interrupt_bits(byte),
+            0x10..=0x3F => self.sound[address as usize - 0xFF10] = byte,
+            0x40 => self.gpu.set_control(byte),
+            0x41 => {
+                if self.model.has_stat_write_bug() {
+                    self.gpu.trigger_stat_write_bug(&mut self.irq);
+                }
+                self.gpu.set_stat(byte)
+            }
+            0x42 => self.gpu.set_scroll_y(byte),
+            0x43 => self.gpu.set_scroll_x(byte),
+            0x44 => self.gpu.reset_current_line(),
+            0x45 => self.gpu.set_compare_line(byte),
+            0x46 => self.dma.initialize(byte),
+            0x47 => self.gpu.set_bg_palette(byte),
+            0x48 => self.gpu.set_obj_palette_0(byte),
+            0x49 => self.gpu.set_obj_palette_1(byte),
+            0x4A => self.gpu.set_window_y(byte),
+            0x4B => self.gpu.set_window_x(byte),
+            0x50 => self.is_checking_boot_rom = false,
+            0x72..=0x75 => self.undocumented[address as usize - 0xFF72] = byte,
+            0xFF => self.irq.set_enabled_bits(byte),
+            _ => unreachable!("no write for I/O register 0x{:04X}", address),
+        }
     }
 }
 
@@ -198,11 +465,391 @@ impl MemoryBus for MMU {
 
     fn get_byte(&mut self, address: u16) -> u8 {
         self.emulate();
+        self.check_access(address);
+        if let Some(value) = self.dma_conflict(address) {
+            return value;
+        }
+        if self.is_blocked(address) {
+            return EMPTY_READ;
+        }
+        self.log_access(address, cdl::DATA);
+        self.get_byte_internal(address)
+    }
+
+    fn rom_bank(&self) -> usize {
+        self.cartridge.rom_bank()
+    }
+
+    fn fetch_byte(&mut self, address: u16, fetch: Fetch) -> u8 {
+        self.emulate();
+        let flags = match fetch {
+            Fetch::Opcode => {
+                self.instruction = Location::at(address, self.cartridge.rom_bank());
+                cdl::OPCODE
+            }
+            Fetch::Operand => cdl::OPERAND,
+        };
+        self.check_access(address);
+        if let Some(value) = self.dma_conflict(address) {
+            return value;
+        }
+        if self.is_blocked(address) {
+            return EMPTY_READ;
+        }
+        self.log_access(address, flags);
         self.get_byte_internal(address)
     }
 
     fn set_byte(&mut self, address: u16, byte: u8) {
         self.emulate();
+        self.check_access(address);
+        if self.dma_conflict(address).is_some() || self.is_blocked(address) {
+            return;
+        }
         self.set_byte_internal(address, byte)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::gameboy::display::NoDisplay;
+
+    fn mmu() -> MMU {
+        MMU::new(
+            vec![0; 0x8000],
+            Model::Dmg,
+            Renderer::default(),
+            Box::new(NoDisplay::new()),
+        )
+    }
+
+    #[test]
+    fn echo_ram_mirrors_internal_ram() {
+        let mut mmu = mmu();
+
+        mmu.set_byte(0xC123, 0x12);
+        assert_eq!(0x12, mmu.get_byte(0xE123));
+
+        mmu.set_byte(0xFDFF, 0x34);
+        assert_eq!(0x34, mmu.get_byte(0xDDFF));
+
+        assert!(mmu.lint_warnings().is_empty());
+    }
+
+    #[test]
+    fn echo_ram_lint() {
+        let mut mmu = mmu();
+        mmu.enable_lint();
+
+        mmu.get_byte(0xC000);
+        mmu.set_byte(0xE010, 0x01);
+        mmu.get_byte(0xF000);
+
+        assert_eq!(
+            vec![String::from(
+                "echo RAM accessed at 0xE010, which mirrors 0xC010"
+            )],
+            mmu.lint_warnings()
+        );
+    }
+
+    #[test]
+    fn unmapped_io_is_open_bus() {
+        let mut mmu = mmu();
+
+        for address in &[0xFF03, 0xFF08, 0xFF0E, 0xFF15, 0xFF27, 0xFF4C, 0xFF7F] {
+            mmu.set_byte(*address, 0x00);
+            assert_eq!(0xFF, mmu.get_byte(*address), "0x{:04X}", address);
+        }
+
+        assert!(mmu.lint_warnings().is_empty());
+    }
+
+    #[test]
+    fn unused_io_bits_read_as_one() {
+        let mut mmu = mmu();
+
+        mmu.set_byte(0xFF0F, 0x00);
+        assert_eq!(0xE0, mmu.get_byte(0xFF0F));
+        mmu.set_byte(0xFF0F, 0xFF);
+        assert_eq!(0xFF, mmu.get_byte(0xFF0F));
+        assert_eq!(0x1F, mmu.irq.get_interrupt_bits());
+
+        mmu.set_byte(0xFF07, 0x00);
+        assert_eq!(0xF8, mmu.get_byte(0xFF07));
+        mmu.set_byte(0xFF02, 0x00);
+        assert_eq!(0x7E, mmu.get_byte(0xFF02));
+        assert_eq!(0xFF, mmu.get_byte(0xFF50));
+    }
+
+    #[test]
+    fn sound_registers_keep_written_bits() {
+        let mut mmu = mmu();
+
+        mmu.set_byte(0xFF11, 0x80);
+        assert_eq!(0xBF, mmu.get_byte(0xFF11));
+        mmu.set_byte(0xFF24, 0x77);
+        assert_eq!(0x77, mmu.get_byte(0xFF24));
+        mmu.set_byte(0xFF26, 0x8F);
+        assert_eq!(0xF0, mmu.get_byte(0xFF26));
+        mmu.set_byte(0xFF30, 0x12);
+        assert_eq!(0x12, mmu.get_byte(0xFF30));
+    }
+
+    #[test]
+    fn unmapped_io_lint() {
+        let mut mmu = mmu();
+        mmu.enable_lint();
+
+        mmu.get_byte(0xFF03);
+        mmu.set_byte(0xFF03, 0x01);
+        mmu.get_byte(0xFF08);
+        mmu.get_byte(0xFF0F);
+
+        assert_eq!(
+            vec![
+                String::from("unmapped I/O register accessed at 0xFF03"),
+                String::from("unmapped I/O register accessed at 0xFF08"),
+            ],
+            mmu.lint_warnings()
+        );
+    }
+
+    fn wait_for_mode(mmu: &mut MMU, mode: u8) {
+        while mmu.get_byte(0xFF41) & 0b11 != mode {}
+    }
+
+    #[test]
+    fn vram_and_oam_blocked_by_ppu() {
+        let mut mmu = mmu();
+        mmu.enable_lint();
+        mmu.set_byte(0xFF40, 0x80);
+
+        wait_for_mode(&mut mmu, 2);
+        mmu.set_byte(0xFE00, 0x12);
+        mmu.set_byte(0x8000, 0x34);
+        assert_eq!(0x34, mmu.get_byte(0x8000));
+
+        wait_for_mode(&mut mmu, 3);
+        mmu.set_byte(0x8001, 0x56);
+        assert_eq!(0xFF, mmu.get_byte(0x8000));
+        assert_eq!(0xFF, mmu.get_byte(0xFE00));
+
+        wait_for_mode(&mut mmu, 0);
+        assert_eq!(0x00, mmu.get_byte(0xFE00));
+        assert_eq!(0x00, mmu.get_byte(0x8001));
+
+        assert_eq!(
+            vec![
+                String::from("OAM accessed at 0xFE00 by 00:0000 during PPU mode 2"),
+                String::from("VRAM accessed at 0x8001 by 00:0000 during PPU mode 3"),
+            ],
+            mmu.lint_warnings()
+        );
+    }
+
+    #[test]
+    fn blocked_access_lint_per_instruction() {
+        let mut mmu = mmu();
+        mmu.enable_lint();
+        mmu.set_byte(0xFF40, 0x80);
+        wait_for_mode(&mut mmu, 3);
+
+        // Two routines that both race the PPU, the first one twice
+        for &pc in &[0x0150, 0x0200, 0x0150] {
+            mmu.fetch_byte(pc, Fetch::Opcode);
+            mmu.set_byte(0x8000, 0x34);
+        }
+
+        assert_eq!(
+            vec![
+                String::from("VRAM accessed at 0x8000 by 00:0150 during PPU mode 3"),
+                String::from("VRAM accessed at 0x8000 by 00:0200 during PPU mode 3"),
+            ],
+            mmu.lint_warnings()
+        );
+    }
+
+    #[test]
+    fn access_blocking_disabled() {
+        let mut mmu = mmu();
+        mmu.set_access_blocking(false);
+        mmu.set_byte(0xFF40, 0x80);
+
+        wait_for_mode(&mut mmu, 3);
+        mmu.set_byte(0x8000, 0x34);
+        mmu.set_byte(0xFE00, 0x12);
+        assert_eq!(0x34, mmu.get_byte(0x8000));
+        assert_eq!(0x12, mmu.get_byte(0xFE00));
+    }
+
+    // The OAM DMA tests below check what Mooneye's oam_dma ROMs check, one
+    // M-cycle per bus access, with HRAM reads to let cycles pass
+
+    fn wait_cycles(mmu: &mut MMU, cycles: usize) {
+        for _ in 0..cycles {
+            mmu.get_byte(0xFF80);
+        }
+    }
+
+    fn dma_byte(index: u16) -> u8 {
+        let byte = index as u8 ^ 0x5A;
+        // OAM only keeps the DMG bits of sprite attributes
+        if index % 4 == 3 {
+            byte & 0xF0
+        } else {
+            byte
+        }
+    }
+
+    fn fill_dma_source(mmu: &mut MMU, start: u16) {
+        for i in 0..0xA0 {
+            mmu.set_byte(start + i, dma_byte(i));
+        }
+    }
+
+    #[test]
+    fn oam_dma_basic() {
+        let mut mmu = mmu();
+        fill_dma_source(&mut mmu, 0xC000);
+
+        mmu.set_byte(0xFF46, 0xC0);
+        wait_cycles(&mut mmu, 161);
+
+        for i in 0..0xA0 {
+            assert_eq!(dma_byte(i), mmu.get_byte(0xFE00 + i));
+        }
+    }
+
+    #[test]
+    fn oam_dma_reg_read() {
+        let mut mmu = mmu();
+
+        mmu.set_byte(0xFF46, 0xC1);
+        assert_eq!(0xC1, mmu.get_byte(0xFF46));
+        wait_cycles(&mut mmu, 161);
+        assert_eq!(0xC1, mmu.get_byte(0xFF46));
+    }
+
+    #[test]
+    fn oam_dma_sources() {
+        let mut mmu = mmu();
+        fill_dma_source(&mut mmu, 0xC000);
+        fill_dma_source(&mut mmu, 0xDE00);
+        fill_dma_source(&mut mmu, 0xDF00);
+
+        for source in &[0xE0, 0xFE, 0xFF] {
+            mmu.set_byte(0xFF46, *source);
+            wait_cycles(&mut mmu, 161);
+
+            assert_eq!(dma_byte(0x00), mmu.get_byte(0xFE00), "0x{:02X}", source);
+            assert_eq!(dma_byte(0x9F), mmu.get_byte(0xFE9F), "0x{:02X}", source);
+        }
+    }
+
+    #[test]
+    fn oam_dma_start() {
+        let mut mmu = mmu();
+        fill_dma_source(&mut mmu, 0xC000);
+
+        mmu.set_byte(0xFF46, 0xC0);
+        // The bus is still free while the transfer starts up
+        assert_eq!(dma_byte(0x10), mmu.get_byte(0xC010));
+        // Then reads get the byte being copied, except for OAM, HRAM and I/O
+        assert_eq!(dma_byte(0x00), mmu.get_byte(0xC010));
+        assert_eq!(0xFF, mmu.get_byte(0xFE00));
+        assert_eq!(dma_byte(0x02), mmu.get_byte(0x0150));
+        mmu.set_byte(0xFF80, 0x12);
+        assert_eq!(0x12, mmu.get_byte(0xFF80));
+        assert_eq!(0xC0, mmu.get_byte(0xFF46));
+
+        // Writes outside HRAM and I/O are dropped
+        mmu.set_byte(0xC010, 0x00);
+        wait_cycles(&mut mmu, 160);
+        assert_eq!(dma_byte(0x10), mmu.get_byte(0xC010));
+    }
+
+    #[test]
+    fn oam_dma_timing() {
+        let mut mmu = mmu();
+        fill_dma_source(&mut mmu, 0xC000);
+
+        mmu.set_byte(0xFF46, 0xC0);
+        wait_cycles(&mut mmu, 160);
+        assert_eq!(dma_byte(0x9F), mmu.get_byte(0xC010));
+        assert_eq!(dma_byte(0x10), mmu.get_byte(0xC010));
+    }
+
+    #[test]
+    fn oam_dma_restart() {
+        let mut mmu = mmu();
+        fill_dma_source(&mut mmu, 0xC000);
+        mmu.set_byte(0xD000, 0xAB);
+
+        mmu.set_byte(0xFF46, 0xC0);
+        wait_cycles(&mut mmu, 10);
+        mmu.set_byte(0xFF46, 0xD0);
+
+        // The first transfer keeps the bus while the second starts up
+        assert_eq!(dma_byte(0x0A), mmu.get_byte(0xC010));
+        assert_eq!(0xAB, mmu.get_byte(0xC010));
+        wait_cycles(&mut mmu, 159);
+        assert_eq!(dma_byte(0x10), mmu.get_byte(0xC010));
+
+        assert_eq!(0xAB, mmu.get_byte(0xFE00));
+    }
+
+    #[test]
+    fn stat_write_bug() {
+        for (model, requested) in &[(Model::Dmg, true), (Model::Cgb, false)] {
+            let mut mmu = MMU::new(
+                vec![0; 0x8000],
+                *model,
+                Renderer::default(),
+                Box::new(NoDisplay::new()),
+            );
+            mmu.set_byte(0xFF40, 0x80);
+
+            wait_for_mode(&mut mmu, 0);
+            mmu.set_byte(0xFF0F, 0x00);
+            mmu.set_byte(0xFF41, 0x00);
+
+            assert_eq!(*requested, mmu.get_byte(0xFF0F) & 0x02 != 0, "{}", model);
+
+            // Nothing is requested while the STAT line is already high
+            mmu.set_byte(0xFF41, 0x08);
+            wait_for_mode(&mut mmu, 2);
+            mmu.set_byte(0xFF41, 0x48);
+            let line = mmu.get_byte(0xFF44);
+            mmu.set_byte(0xFF45, line);
+            mmu.set_byte(0xFF0F, 0x00);
+            mmu.set_byte(0xFF41, 0x48);
+            assert_eq!(0, mmu.get_byte(0xFF0F) & 0x02, "{}", model);
+        }
+    }
+
+    #[test]
+    fn init_ram() {
+        let mut ones = mmu();
+        ones.init_ram(RamInit::Ones);
+
+        for address in &[
+            0x8000, 0x9FFF, 0xC000, 0xDFFF, 0xFE00, 0xFE9E, 0xFF80, 0xFFFE,
+        ] {
+            assert_eq!(0xFF, ones.get_byte(*address), "0x{:04X}", address);
+        }
+        assert!(ones.cartridge_ram().iter().all(|byte| *byte == 0xFF));
+
+        let mut first = mmu();
+        first.init_ram(RamInit::Random(7));
+        let mut second = mmu();
+        second.init_ram(RamInit::Random(7));
+        for address in (0x8000..=0x9FFF)
+            .chain(0xC000..=0xDFFF)
+            .chain(0xFF80..=0xFFFE)
+        {
+            assert_eq!(first.peek(address), second.peek(address));
+        }
+    }
+}
@@ -1,5 +1,8 @@
 pub mod clock;
+pub mod debug;
 pub mod display;
+pub mod link;
+pub mod palette;
 
 mod cartridge;
 mod cpu;
@@ -7,13 +10,39 @@ mod gpu;
 mod irq;
 mod joypad;
 mod memory;
+mod model;
 mod serial;
 mod timer;
 
+use std::any::Any;
+use std::io;
+use std::panic;
+use std::panic::AssertUnwindSafe;
+use std::path::PathBuf;
+
 use self::clock::Clock;
+use self::cpu::MemoryBus;
+use self::cpu::Registers;
 use self::cpu::CPU;
+use self::debug::callstack;
+use self::debug::cdl::CodeDataLog;
+use self::debug::crashdump;
+use self::debug::crashdump::CrashDump;
+use self::debug::crashdump::MemoryRegion;
+use self::debug::profiler::Profiler;
+use self::debug::symbols::Symbols;
+use self::debug::trace::TraceBuffer;
+use self::debug::trace::TraceEntry;
+use self::debug::watchdog::Watchdog;
+use self::debug::Location;
 use self::display::VideoDisplay;
+pub use self::gpu::Renderer;
+use self::link::SerialSink;
+pub use self::memory::RamInit;
 use self::memory::MMU;
+pub use self::model::Model;
+use self::palette::ColorPalette;
+use self::palette::Rgb;
 
 #[derive(Hash, Eq, PartialEq)]
 pub enum Button {
@@ -36,14 +65,30 @@ pub enum Color {
 }
 
 pub struct GameBoy {
+    model: Model,
+    ram_init: RamInit,
+    started: bool,
     cpu: CPU,
     mmu: MMU,
     clock: Box<dyn Clock>,
+    profiler: Option<Profiler>,
+    symbols: Symbols,
+    breakpoints: Vec<Location>,
+    trace: TraceBuffer,
+    crash_dump_path: Option<PathBuf>,
+    watchdog: Option<Watchdog>,
 }
 
+// Enough history to see how a crash was reached
+const TRACE_LENGTH: usize = 256;
+
+const SRAM_BANK_SIZE: usize = 0x2000;
+
 impl GameBoy {
     pub fn new(
         cartridge: &Vec<u8>,
+        model: Model,
+        renderer: Renderer,
         clock: Box<dyn Clock>,
         display: Box<dyn VideoDisplay>,
     ) -> GameBoy {
@@ -51,19 +96,286 @@ impl GameBoy {
         let rom = cartridge.to_owned();
 
         GameBoy {
+            model,
+            ram_init: RamInit::Zero,
+            started: false,
             cpu: CPU::default(),
-            mmu: MMU::new(rom, display),
+            mmu: MMU::new(rom, model, renderer, display),
             clock,
+            profiler: None,
+            symbols: Symbols::new(),
+            breakpoints: Vec::new(),
+            trace: TraceBuffer::new(TRACE_LENGTH),
+            crash_dump_path: None,
+            watchdog: None,
         }
     }
 
+    /// Fills RAM with its power-on contents, which are zeros unless set.
+    /// The policy is recorded in crash dumps to reproduce runs.
+    pub fn init_ram(&mut self, init: RamInit) {
+        self.ram_init = init;
+        self.mmu.init_ram(init);
+    }
+
+    /// Replaces the built in boot ROM with one dumped from hardware, which
+    /// must be the size of the boot ROMs for the model. Models without a
+    /// built in boot ROM skip the boot sequence unless one is set.
+    pub fn set_boot_rom(&mut self, data: Vec<u8>) -> io::Result<()> {
+        self.mmu.set_boot_rom(data)
+    }
+
+    pub fn check_boot_rom(model: Model, data: &[u8]) -> io::Result<()> {
+        MMU::check_boot_rom(model, data)
+    }
+
+    /// Starts at 0x0100 in the state the boot ROM of the model leaves the
+    /// machine in, without running it
+    pub fn skip_boot(&mut self) {
+        self.started = true;
+        self.mmu.skip_boot();
+
+        let registers = self.model.boot_registers(self.mmu.cartridge_header());
+        self.cpu.set_registers(registers);
+    }
+
     pub fn step(&mut self) -> u8 {
-        self.cpu.step(&mut self.mmu);
+        if !self.started && !self.mmu.has_boot_rom() {
+            self.skip_boot();
+        }
+        self.started = true;
+
+        let rom_bank = self.mmu.rom_bank();
+
+        let cpu = &mut self.cpu;
+        let mmu = &mut self.mmu;
+        if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(|| cpu.step(mmu))) {
+            self.record_trace(rom_bank);
+            self.report_crash(&*payload);
+            panic::resume_unwind(payload);
+        }
+        self.record_trace(rom_bank);
+
         let cycles = self.mmu.get_and_reset_cycles();
         self.clock.tick(cycles);
+
+        if let Some(watchdog) = &mut self.watchdog {
+            let last_pc = self.cpu.get_last_pc();
+            let pc = self.cpu.get_registers().pc;
+            if let Some(message) = watchdog.check(last_pc, pc, cycles as u64) {
+                self.report_crash(&message);
+                panic!("{}", message);
+            }
+        }
+
+        if let Some(profiler) = &mut self.profiler {
+            let address = self.cpu.get_last_pc();
+            profiler.record(rom_bank, address, cycles, self.cpu.get_flow());
+        }
+
         cycles
     }
 
+    /// Writes a crash dump to the path whenever emulation faults
+    pub fn set_crash_dump_path(&mut self, path: PathBuf) {
+        self.crash_dump_path = Some(path);
+    }
+
+    /// Reports a crash once the CPU has spent a number of frames in a jump
+    /// to itself that no interrupt took it out of
+    pub fn enable_watchdog(&mut self, frames: u32) {
+        self.watchdog = Some(Watchdog::new(frames));
+    }
+
+    /// Captures the state of the whole machine
+    pub fn crash_dump(&self, message: String) -> CrashDump {
+        let region = |name: &str, start: u16, len: u16| MemoryRegion {
+            name: name.to_string(),
+            start,
+            bytes: (start..start + len).map(|a| self.mmu.peek(a)).collect(),
+        };
+
+        // Cartridge RAM is switched in at 0xA000 one bank at a time
+        let sram = self
+            .mmu
+            .cartridge_ram()
+            .chunks(SRAM_BANK_SIZE)
+            .enumerate()
+            .map(|(bank, bytes)| MemoryRegion {
+                name: format!("sram.{}", bank),
+                start: 0xA000,
+                bytes: bytes.to_vec(),
+            });
+
+        let mut memory = vec![
+            region("wram", 0xC000, 0x2000),
+            region("vram", 0x8000, 0x2000),
+            region("oam", 0xFE00, 0xA0),
+            region("hram", 0xFF80, 0x7F),
+        ];
+        memory.extend(sram);
+
+        CrashDump {
+            message,
+            registers: self.registers().clone(),
+            ime: self.cpu.get_ime(),
+            interrupt_enable: self.mmu.peek(0xFFFF),
+            interrupt_flags: self.mmu.peek(0xFF0F),
+            lcd_control: self.mmu.peek(0xFF40),
+            lcd_status: self.mmu.peek(0xFF41),
+            ly: self.mmu.peek(0xFF44),
+            mapper: self.mmu.describe_mapper(),
+            ram_init: self.ram_init.to_string(),
+            backtrace: self.backtrace().lines().map(String::from).collect(),
+            trace: self
+                .trace
+                .entries()
+                .map(|entry| entry.describe(&self.symbols))
+                .collect(),
+            memory,
+        }
+    }
+
+    pub fn set_symbols(&mut self, symbols: Symbols) {
+        self.symbols = symbols;
+    }
+
+    pub fn symbols(&self) -> &Symbols {
+        &self.symbols
+    }
+
+    /// Qualifies an address with the ROM bank that is currently mapped
+    pub fn location(&self, address: u16) -> Location {
+        Location::at(address, self.mmu.rom_bank())
+    }
+
+    pub fn add_breakpoint(&mut self, location: Location) {
+        self.breakpoints.push(location);
+    }
+
+    /// Whether the next instruction to execute is at a breakpoint
+    pub fn at_breakpoint(&self) -> bool {
+        if self.breakpoints.is_empty() {
+            return false;
+        }
+
+        let location = self.location(self.cpu.get_registers().pc);
+        self.breakpoints.contains(&location)
+    }
+
+    /// Starts counting instructions and cycles for every step from now on
+    pub fn enable_profiler(&mut self) {
+        self.profiler = Some(Profiler::new());
+    }
+
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
+    /// Reports accesses that only work because of hardware quirks
+    pub fn enable_lint(&mut self) {
+        self.mmu.enable_lint();
+    }
+
+    /// Every lint warning reported so far
+    pub fn lint_warnings(&self) -> &[String] {
+        self.mmu.lint_warnings()
+    }
+
+    /// Blocks CPU access to VRAM and OAM while the PPU uses them, which is
+    /// on by default
+    pub fn set_access_blocking(&mut self, enabled: bool) {
+        self.mmu.set_access_blocking(enabled);
+    }
+
+    /// Starts logging how each byte of ROM and RAM is used on top of an
+    /// existing log
+    pub fn enable_cdl(&mut self, cdl: CodeDataLog) {
+        self.mmu.enable_cdl(cdl);
+    }
+
+    pub fn cdl(&self) -> Option<&CodeDataLog> {
+        self.mmu.cdl()
+    }
+
+    pub fn connect_serial(&mut self, sink: Box<dyn SerialSink>) {
+        self.mmu.connect_serial(sink);
+    }
+
+    /// The last frame drawn completely, row by row in the color palette.
+    /// It's blank while the LCD is off. Only headless frontends read it;
+    /// windowed ones are handed frames at vsync.
+    pub fn framebuffer(&self) -> &[Rgb] {
+        self.mmu.framebuffer()
+    }
+
+    /// The size of the cartridge RAM that the header declares
+    pub fn cartridge_ram_size(&self) -> usize {
+        self.mmu.cartridge_header().ram_bytes()
+    }
+
+    pub fn title(&self) -> String {
+        self.mmu.cartridge_header().title_text()
+    }
+
+    /// The colors the CGB boot ROM gives a DMG game: those it has for the
+    /// title of a game by Nintendo, or else its default ones. Games made for
+    /// the CGB pick their own colors, so they get none.
+    pub fn cgb_colorization(&self) -> Option<ColorPalette> {
+        let header = self.mmu.cartridge_header();
+        if header.supports_cgb() {
+            return None;
+        }
+
+        let by_title = if header.is_nintendo() {
+            ColorPalette::for_cgb_title(&header.title)
+        } else {
+            None
+        };
+        Some(by_title.unwrap_or_else(ColorPalette::cgb_default))
+    }
+
+    /// Switches the colors the four shades of each layer are shown in
+    pub fn set_color_palette(&mut self, palette: ColorPalette) {
+        self.mmu.set_color_palette(palette);
+    }
+
+    pub fn registers(&self) -> &Registers {
+        self.cpu.get_registers()
+    }
+
+    pub fn last_op_code(&self) -> u8 {
+        self.cpu.get_last_op_code()
+    }
+
+    /// Describes the most recently executed instruction and the call site of
+    /// every active subroutine, using symbols if any were loaded
+    pub fn backtrace(&self) -> String {
+        let current = self.location(self.cpu.get_last_pc());
+        callstack::backtrace(current, self.cpu.get_call_stack(), &self.symbols)
+    }
+
+    fn record_trace(&mut self, rom_bank: usize) {
+        self.trace.push(TraceEntry {
+            location: Location::at(self.cpu.get_last_pc(), rom_bank),
+            op_code: self.cpu.get_last_op_code(),
+            registers: self.registers().clone(),
+        });
+    }
+
+    fn report_crash(&self, payload: &(dyn Any + Send)) {
+        println!("{}", self.backtrace());
+        println!("registers: {}", self.registers());
+
+        if let Some(path) = &self.crash_dump_path {
+            let dump = self.crash_dump(crashdump::panic_message(payload));
+            match dump.save(path) {
+                Ok(()) => println!("[crash] dump written to {}", path.display()),
+                Err(e) => println!("[crash] could not write {}: {}", path.display(), e),
+            }
+        }
+    }
+
     pub fn button_down(&mut self, btn: Button) {
         self.mmu.button_down(btn);
     }
@@ -72,3 +384,166 @@ impl GameBoy {
         self.mmu.button_up(btn);
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::asm::Assembler;
+    use crate::gameboy::clock::NoClock;
+    use crate::gameboy::display::NoDisplay;
+
+    fn gameboy(model: Model) -> GameBoy {
+        let mut asm = Assembler::new("BOOT");
+        asm.section(0x150).nop();
+        GameBoy::new(
+            &asm.build(),
+            model,
+            Renderer::default(),
+            Box::new(NoClock::new()),
+            Box::new(NoDisplay::new()),
+        )
+    }
+
+    #[test]
+    fn skip_boot_matches_boot_rom() {
+        let mut booted = gameboy(Model::Dmg);
+        while booted.registers().pc != 0x0100 {
+            booted.step();
+        }
+
+        let mut skipped = gameboy(Model::Dmg);
+        skipped.skip_boot();
+
+        assert_eq!(booted.registers(), skipped.registers());
+        for address in 0x8000..=0x9FFF {
+            assert_eq!(
+                booted.mmu.peek(address),
+                skipped.mmu.peek(address),
+                "0x{:04X}",
+                address
+            );
+        }
+        // DIV and the PPU are at the documented point of the frame instead
+        // of wherever the built in boot ROM reaches it
+        for address in (0xFF00..=0xFF7F).chain(0xFFFF..=0xFFFF) {
+            if let 0xFF04 | 0xFF41 | 0xFF44 = address {
+                continue;
+            }
+            assert_eq!(
+                booted.mmu.peek(address),
+                skipped.mmu.peek(address),
+                "0x{:04X}",
+                address
+            );
+        }
+        assert_eq!(0xAB, skipped.mmu.peek(0xFF04));
+        assert_eq!(0x00, skipped.mmu.peek(0x0000));
+
+        const CGB_IO: [(u16, u8); 4] = [
+            (0xFF02, 0x7F),
+            (0xFF72, 0x00),
+            (0xFF73, 0x00),
+            (0xFF75, 0x8F),
+        ];
+
+        // Other boot ROMs leave a few registers differently. DIV is checked
+        // by the model tests, and the SGB's NR52 only differs in channel
+        // flags, which read as off without sound emulation.
+        for &(model, differences) in &[
+            (Model::Dmg0, &[][..]),
+            (Model::Mgb, &[][..]),
+            (Model::Sgb, &[][..]),
+            (Model::Cgb, &CGB_IO[..]),
+            (Model::Agb, &CGB_IO[..]),
+        ] {
+            let mut other = gameboy(model);
+            other.skip_boot();
+            for address in (0xFF00..=0xFF7F).chain(0xFFFF..=0xFFFF) {
+                if address == 0xFF04 {
+                    continue;
+                }
+                let expected = differences
+                    .iter()
+                    .find(|(a, _)| *a == address)
+                    .map_or(skipped.mmu.peek(address), |(_, byte)| *byte);
+                assert_eq!(
+                    expected,
+                    other.mmu.peek(address),
+                    "{} 0x{:04X}",
+                    model,
+                    address
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn boot_rom_size() {
+        let mut dmg = gameboy(Model::Dmg);
+        let error = dmg.set_boot_rom(vec![0; 0x900]).err().unwrap();
+        assert_eq!(
+            "boot ROM is 2304 bytes, but DMG boot ROMs are 256 bytes",
+            error.to_string()
+        );
+
+        let mut cgb = gameboy(Model::Cgb);
+        cgb.set_boot_rom(vec![0x3C; 0x900]).unwrap();
+        assert_eq!(0x3C, cgb.mmu.peek(0x0000));
+        assert_eq!(0xCE, cgb.mmu.peek(0x0104));
+        assert_eq!(0x3C, cgb.mmu.peek(0x0200));
+        assert_eq!(0x3C, cgb.mmu.peek(0x08FF));
+        assert_eq!(0x00, cgb.mmu.peek(0x0900));
+    }
+
+    #[test]
+    fn models_without_a_boot_rom_skip_boot() {
+        let mut mgb = gameboy(Model::Mgb);
+        assert_eq!(0x00, mgb.mmu.peek(0x0000));
+
+        mgb.step();
+        assert_eq!(0xFF, mgb.registers().a);
+        assert_eq!(0x0101, mgb.registers().pc);
+
+        let mut sgb = gameboy(Model::Sgb);
+        sgb.set_boot_rom(vec![0x00; 0x100]).unwrap();
+        sgb.step();
+        assert_eq!(0x0001, sgb.registers().pc);
+    }
+
+    #[test]
+    fn cgb_colorization() {
+        let colorized = |title: &str, cgb_flag: u8, licensee: u8| {
+            let mut asm = Assembler::new(title);
+            asm.section(0x150).nop();
+            let mut rom = asm.build();
+            rom[0x143] = cgb_flag;
+            rom[0x14B] = licensee;
+
+            let display = Box::new(NoDisplay::new());
+            let gameboy = GameBoy::new(
+                &rom,
+                Model::Dmg,
+                Renderer::default(),
+                Box::new(NoClock::new()),
+                display,
+            );
+            gameboy.cgb_colorization()
+        };
+
+        let tetris = [0xFFFFFF, 0xFFFF00, 0xFF0000, 0x000000];
+        assert_eq!(
+            colorized("TETRIS", 0x00, 0x01),
+            Some(ColorPalette::uniform(tetris))
+        );
+
+        // Other DMG games get the default colors, and CGB games none
+        let default = ColorPalette {
+            bg: [0xFFFFFF, 0x7BFF31, 0x0063C6, 0x000000],
+            obj0: [0xFFFFFF, 0xFF8484, 0x943939, 0x000000],
+            obj1: [0xFFFFFF, 0xFF8484, 0x943939, 0x000000],
+        };
+        assert_eq!(colorized("TETRIS", 0x00, 0x08), Some(default));
+        assert_eq!(colorized("RUSTBOY", 0x00, 0x01), Some(default));
+        assert_eq!(colorized("TETRIS", 0x80, 0x01), None);
+    }
+}
@@ -0,0 +1,195 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::gameboy::cartridge::Header;
+use crate::gameboy::cpu::Registers;
+
+/// A Game Boy hardware revision. The model decides the boot ROM, the state
+/// the boot ROM hands over in and the quirks of the hardware. CGB mode isn't
+/// emulated, so the CGB and AGB run games in DMG compatibility mode, where
+/// only a few of their extra registers are left.
+#[derive(Debug, Default, PartialEq, Eq, Copy, Clone)]
+pub enum Model {
+    Dmg0,
+    #[default]
+    Dmg,
+    Mgb,
+    Sgb,
+    Cgb,
+    Agb,
+}
+
+impl Model {
+    /// Only the DMG boot ROM is built in; the others have to be loaded, or
+    /// their boot is skipped
+    pub fn has_built_in_boot_rom(self) -> bool {
+        self == Model::Dmg
+    }
+
+    pub fn boot_rom_size(self) -> usize {
+        if self.is_cgb() {
+            0x900
+        } else {
+            0x100
+        }
+    }
+
+    pub fn is_cgb(self) -> bool {
+        self == Model::Cgb || self == Model::Agb
+    }
+
+    /// Writing STAT on DMG era hardware briefly enables every STAT interrupt
+    /// source, which some games rely on
+    pub fn has_stat_write_bug(self) -> bool {
+        !self.is_cgb()
+    }
+
+    /// CPU registers as the boot ROM leaves them. Games tell models apart by
+    /// A, and the AGB by bit 0 of B. The CGB and AGB values are those of the
+    /// DMG compatibility mode that games are run in.
+    pub fn boot_registers(self, header: &Header) -> Registers {
+        use self::Model::*;
+
+        let (af, bc, de, hl): (u16, u16, u16, u16) = match self {
+            Dmg0 => (0x0100, 0xFF13, 0x00C1, 0x8403),
+            Dmg => (0x01B0, 0x0013, 0x00D8, 0x014D),
+            Mgb => (0xFFB0, 0x0013, 0x00D8, 0x014D),
+            Sgb => (0x0100, 0x0014, 0x0000, 0xC060),
+            Cgb => (0x1180, 0x0000, 0x0008, 0x007C),
+            Agb => (0x1100, 0x0100, 0x0008, 0x007C),
+        };
+
+        // The DMG and MGB header checks leave H and C set unless the
+        // checksum in the header is zero
+        let af = match self {
+            Dmg | Mgb if header.header_checksum == 0 => af & 0xFF80,
+            _ => af,
+        };
+
+        // The CGB boot ROM leaves the title checksum of Nintendo's games in
+        // B, and points HL into the logo for the two it treats specially
+        let (bc, hl) = match self {
+            Cgb | Agb if header.is_nintendo() => {
+                let checksum = header.title_checksum();
+                let hl = match checksum {
+                    0x43 | 0x58 => 0x991A,
+                    _ => hl,
+                };
+                (bc.wrapping_add((checksum as u16) << 8), hl)
+            }
+            _ => (bc, hl),
+        };
+
+        let mut registers = Registers::default();
+        registers.set_af(af);
+        registers.set_bc(bc);
+        registers.set_de(de);
+        registers.set_hl(hl);
+        registers.sp = 0xFFFE;
+        registers.pc = 0x0100;
+        registers
+    }
+
+    /// The system counter that DIV is the upper byte of, in T-cycles, when
+    /// the boot ROM hands over. It isn't documented for the SGB, CGB and
+    /// AGB, so they start at zero.
+    pub fn boot_div_counter(self) -> u16 {
+        use self::Model::*;
+
+        match self {
+            Dmg0 => 0x1800,
+            Dmg | Mgb => 0xABCC,
+            Sgb | Cgb | Agb => 0,
+        }
+    }
+}
+
+impl fmt::Display for Model {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Model::Dmg0 => "DMG0",
+            Model::Dmg => "DMG",
+            Model::Mgb => "MGB",
+            Model::Sgb => "SGB",
+            Model::Cgb => "CGB",
+            Model::Agb => "AGB",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Model {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Model, String> {
+        match text.to_ascii_lowercase().as_str() {
+            "dmg0" => Ok(Model::Dmg0),
+            "dmg" => Ok(Model::Dmg),
+            "mgb" => Ok(Model::Mgb),
+            "sgb" => Ok(Model::Sgb),
+            "cgb" => Ok(Model::Cgb),
+            "agb" => Ok(Model::Agb),
+            _ => Err(format!("unknown model {}", text)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_and_display() {
+        for model in &["DMG0", "DMG", "MGB", "SGB", "CGB", "AGB"] {
+            assert_eq!(*model, model.parse::<Model>().unwrap().to_string());
+        }
+        assert_eq!(Ok(Model::Mgb), "mgb".parse());
+        assert!("gba".parse::<Model>().is_err());
+    }
+
+    #[test]
+    fn boot_registers() {
+        let header = |checksum: u8, title: &[u8], licensee: u8| {
+            let mut rom = vec![0; 0x150];
+            rom[0x134..0x134 + title.len()].copy_from_slice(title);
+            rom[0x14B] = licensee;
+            rom[0x14D] = checksum;
+            Header::parse(&rom)
+        };
+        let game = header(0x66, b"RUSTBOY", 0x00);
+
+        assert_eq!(0x01B0, Model::Dmg.boot_registers(&game).get_af());
+        assert_eq!(
+            0x0180,
+            Model::Dmg.boot_registers(&header(0x00, b"", 0)).get_af()
+        );
+        assert_eq!(0xFF, Model::Mgb.boot_registers(&game).a);
+        assert_eq!(0x11, Model::Cgb.boot_registers(&game).a);
+        assert_eq!(0x01, Model::Agb.boot_registers(&game).b & 1);
+        assert_eq!(0x0100, Model::Sgb.boot_registers(&game).pc);
+
+        for &(model, de, hl) in &[
+            (Model::Dmg0, 0x00C1, 0x8403),
+            (Model::Dmg, 0x00D8, 0x014D),
+            (Model::Mgb, 0x00D8, 0x014D),
+            (Model::Sgb, 0x0000, 0xC060),
+            (Model::Cgb, 0x0008, 0x007C),
+            (Model::Agb, 0x0008, 0x007C),
+        ] {
+            let registers = model.boot_registers(&game);
+            assert_eq!(de, registers.get_de(), "{} DE", model);
+            assert_eq!(hl, registers.get_hl(), "{} HL", model);
+        }
+
+        // Nintendo's games get their title checksum in B on the CGB
+        let tetris = header(0x66, b"TETRIS", 0x01);
+        assert_eq!(0xDB00, Model::Cgb.boot_registers(&tetris).get_bc());
+        assert_eq!(0xDC00, Model::Agb.boot_registers(&tetris).get_bc());
+        assert_eq!(0x007C, Model::Cgb.boot_registers(&tetris).get_hl());
+        assert_eq!(0x0013, Model::Dmg.boot_registers(&tetris).get_bc());
+
+        let special = header(0x66, b"C", 0x01);
+        assert_eq!(0x4300, Model::Cgb.boot_registers(&special).get_bc());
+        assert_eq!(0x991A, Model::Cgb.boot_registers(&special).get_hl());
+    }
+}
@@ -0,0 +1,215 @@
+/*
+ * The palettes the CGB boot ROM colors DMG games with. The boot ROM keeps a
+ * table of RGB555 colors and a table of combinations, each picking the colors
+ * of the two sprite palettes and of the background from it.
+ *
+ * Games by Nintendo get a combination picked by their title, which the boot
+ * ROM identifies by the sum of its bytes, and by its fourth letter for the
+ * few sums shared by several titles. Any other game gets the first
+ * combination.
+ */
+
+use super::ColorPalette;
+use super::Rgb;
+
+/// Colors of the boot ROM in its RGB555 format, four to a palette from white
+/// to black
+const COLORS: [u16; 120] = [
+    0x7FFF, 0x32BF, 0x00D0, 0x0000, 0x639F, 0x4279, 0x15B0, 0x04CB, 0x7FFF, 0x6E31, 0x454A, 0x0000,
+    0x7FFF, 0x1BEF, 0x0200, 0x0000, 0x7FFF, 0x421F, 0x1CF2, 0x0000, 0x7FFF, 0x5294, 0x294A, 0x0000,
+    0x7FFF, 0x03FF, 0x012F, 0x0000, 0x7FFF, 0x03EF, 0x01D6, 0x0000, 0x7FFF, 0x42B5, 0x3DC8, 0x0000,
+    0x7E74, 0x03FF, 0x0180, 0x0000, 0x67FF, 0x77AC, 0x1A13, 0x2D6B, 0x7ED6, 0x4BFF, 0x2175, 0x0000,
+    0x53FF, 0x4A5F, 0x7E52, 0x0000, 0x4FFF, 0x7ED2, 0x3A4C, 0x1CE0, 0x03ED, 0x7FFF, 0x255F, 0x0000,
+    0x036A, 0x021F, 0x03FF, 0x7FFF, 0x7FFF, 0x01DF, 0x0112, 0x0000, 0x231F, 0x035F, 0x00F2, 0x0009,
+    0x7FFF, 0x03EA, 0x011F, 0x0000, 0x299F, 0x001A, 0x000C, 0x0000, 0x7FFF, 0x027F, 0x001F, 0x0000,
+    0x7FFF, 0x03E0, 0x0206, 0x0120, 0x7FFF, 0x7EEB, 0x001F, 0x7C00, 0x7FFF, 0x3FFF, 0x7E00, 0x001F,
+    0x7FFF, 0x03FF, 0x001F, 0x0000, 0x03FF, 0x001F, 0x000C, 0x0000, 0x7FFF, 0x033F, 0x0193, 0x0000,
+    0x0000, 0x4200, 0x037F, 0x7FFF, 0x7FFF, 0x7E8C, 0x7C00, 0x0000, 0x7FFF, 0x1BEF, 0x6180, 0x0000,
+];
+
+/// Where in COLORS the OBJ0, OBJ1 and BG colors of each combination start.
+/// A few start in the middle of a palette, as they do in the boot ROM.
+const COMBINATIONS: [[u8; 3]; 51] = [
+    [16, 16, 116],
+    [72, 72, 72],
+    [80, 80, 80],
+    [96, 96, 96],
+    [36, 36, 36],
+    [0, 0, 0],
+    [108, 108, 108],
+    [20, 20, 20],
+    [48, 48, 48],
+    [104, 104, 104],
+    [64, 32, 32],
+    [16, 112, 112],
+    [16, 8, 8],
+    [12, 16, 16],
+    [16, 116, 116],
+    [112, 16, 112],
+    [8, 68, 8],
+    [64, 64, 32],
+    [16, 16, 28],
+    [16, 16, 72],
+    [16, 16, 80],
+    [76, 76, 36],
+    [15, 15, 44],
+    [68, 68, 8],
+    [16, 16, 8],
+    [16, 16, 12],
+    [112, 112, 0],
+    [12, 12, 0],
+    [0, 0, 4],
+    [72, 88, 72],
+    [80, 88, 80],
+    [96, 88, 96],
+    [64, 88, 32],
+    [68, 16, 52],
+    [111, 0, 56],
+    [111, 16, 60],
+    [76, 88, 36],
+    [64, 112, 40],
+    [16, 92, 112],
+    [68, 88, 8],
+    [16, 0, 8],
+    [16, 112, 12],
+    [112, 12, 0],
+    [12, 112, 16],
+    [84, 112, 16],
+    [12, 112, 0],
+    [100, 12, 112],
+    [0, 112, 32],
+    [16, 12, 112],
+    [112, 12, 24],
+    [16, 112, 116],
+];
+
+/// Checksums of the titles the boot ROM has palettes for, followed by those
+/// shared by several titles
+const TITLE_CHECKSUMS: [u8; 79] = [
+    0x00, 0x88, 0x16, 0x36, 0xD1, 0xDB, 0xF2, 0x3C, 0x8C, 0x92, 0x3D, 0x5C, 0x58, 0xC9, 0x3E, 0x70,
+    0x1D, 0x59, 0x69, 0x19, 0x35, 0xA8, 0x14, 0xAA, 0x75, 0x95, 0x99, 0x34, 0x6F, 0x15, 0xFF, 0x97,
+    0x4B, 0x90, 0x17, 0x10, 0x39, 0xF7, 0xF6, 0xA2, 0x49, 0x4E, 0x43, 0x68, 0xE0, 0x8B, 0xF0, 0xCE,
+    0x0C, 0x29, 0xE8, 0xB7, 0x86, 0x9A, 0x52, 0x01, 0x9D, 0x71, 0x9C, 0xBD, 0x5D, 0x6D, 0x67, 0x3F,
+    0x6B, 0xB3, 0x46, 0x28, 0xA5, 0xC6, 0xD3, 0x27, 0x61, 0x18, 0x66, 0x6A, 0xBF, 0x0D, 0xF4,
+];
+
+/// Shared checksums start after the unique ones
+const UNIQUE_CHECKSUMS: usize = 65;
+
+/// The fourth letter of the title for each shared checksum, in rows which
+/// are tried in turn
+const FOURTH_LETTERS: &[u8; 29] = b"BEFAARBEKEK R-URAR INAILICE R";
+
+/// The combination for each unique checksum, followed by those for each of
+/// the fourth letters
+const TITLE_COMBINATIONS: [u8; 94] = [
+    0, 4, 5, 35, 34, 3, 31, 15, 10, 5, 19, 36, 7, 37, 30, 44, 21, 32, 31, 20, 5, 33, 13, 14, 5, 29,
+    5, 18, 9, 3, 2, 26, 25, 25, 41, 42, 26, 45, 42, 45, 36, 38, 26, 42, 30, 41, 34, 34, 5, 42, 6,
+    5, 33, 25, 42, 42, 40, 2, 16, 25, 42, 42, 5, 0, 39, 36, 22, 25, 6, 32, 12, 36, 11, 39, 18, 39,
+    24, 31, 50, 17, 46, 6, 27, 0, 47, 41, 41, 0, 0, 19, 34, 23, 18, 29,
+];
+
+/// The palettes of a combination in the boot ROM's table
+pub fn combination(index: usize) -> ColorPalette {
+    let [obj0, obj1, bg] = COMBINATIONS[index];
+    ColorPalette {
+        bg: shades(bg),
+        obj0: shades(obj0),
+        obj1: shades(obj1),
+    }
+}
+
+/// Finds the combination for a title by the sum of its bytes. Titles with
+/// the same sum are told apart by their fourth letter.
+pub fn title_combination(title: &[u8; 16]) -> Option<usize> {
+    let checksum = title
+        .iter()
+        .fold(0u8, |sum, &letter| sum.wrapping_add(letter));
+    let index = TITLE_CHECKSUMS
+        .iter()
+        .position(|&known| known == checksum)?;
+
+    if index < UNIQUE_CHECKSUMS {
+        return Some(TITLE_COMBINATIONS[index] as usize);
+    }
+
+    let shared = TITLE_CHECKSUMS.len() - UNIQUE_CHECKSUMS;
+    (index - UNIQUE_CHECKSUMS..FOURTH_LETTERS.len())
+        .step_by(shared)
+        .find(|&letter| FOURTH_LETTERS[letter] == title[3])
+        .map(|letter| TITLE_COMBINATIONS[UNIQUE_CHECKSUMS + letter] as usize)
+}
+
+fn shades(start: u8) -> [Rgb; 4] {
+    let mut shades = [0; 4];
+    for (i, shade) in shades.iter_mut().enumerate() {
+        *shade = rgb(COLORS[start as usize + i]);
+    }
+    shades
+}
+
+/// Widens the 5 bit red, green and blue channels to 8 bits
+fn rgb(color: u16) -> Rgb {
+    let widen = |channel: u16| {
+        let channel = (channel & 0x1F) as u32;
+        channel << 3 | channel >> 2
+    };
+    widen(color) << 16 | widen(color >> 5) << 8 | widen(color >> 10)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn rgb_from_rgb555() {
+        assert_eq!(rgb(0x7FFF), 0xFFFFFF);
+        assert_eq!(rgb(0x0000), 0x000000);
+        assert_eq!(rgb(0x32BF), 0xFFAD63);
+        assert_eq!(rgb(0x6180), 0x0063C6);
+    }
+
+    #[test]
+    fn default_combination() {
+        let palette = combination(0);
+        assert_eq!(palette.bg, [0xFFFFFF, 0x7BFF31, 0x0063C6, 0x000000]);
+        assert_eq!(palette.obj0, [0xFFFFFF, 0xFF8484, 0x943939, 0x000000]);
+        assert_eq!(palette.obj1, palette.obj0);
+    }
+
+    fn title(text: &str) -> [u8; 16] {
+        let mut title = [0; 16];
+        title[..text.len()].copy_from_slice(text.as_bytes());
+        title
+    }
+
+    #[test]
+    fn title_combinations() {
+        assert_eq!(title_combination(&title("TETRIS")), Some(3));
+        assert_eq!(title_combination(&title("ZELDA")), Some(44));
+        assert_eq!(title_combination(&title("POKEMON RED")), Some(13));
+        assert_eq!(title_combination(&title("RUSTBOY")), None);
+    }
+
+    #[test]
+    fn title_fourth_letter() {
+        // Both sum to 0x46
+        assert_eq!(title_combination(&title("SUPER MARIOLAND")), Some(22));
+        assert_eq!(title_combination(&title("METROID2")), Some(46));
+
+        assert_eq!(title_combination(&title("POKEMON BLUE")), Some(11));
+        assert_eq!(title_combination(&title("GOLF")), Some(25));
+
+        // The sum of GOLF, without a known fourth letter
+        let mut gole = title("GOLE");
+        gole[4] = 1;
+        assert_eq!(title_combination(&gole), None);
+    }
+
+    #[test]
+    fn combination_inside_palette() {
+        // Starts with the black of the previous palette
+        let palette = combination(22);
+        assert_eq!(palette.obj0, [0x000000, 0xFFFFFF, 0xFF8484, 0x943939]);
+    }
+}
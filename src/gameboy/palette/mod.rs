@@ -0,0 +1,299 @@
+/*
+ * The RGB colors the four DMG shades are shown in. The background and window,
+ * and the two sprite palettes each have their own colors, as they do when the
+ * CGB colors a DMG game.
+ *
+ * A palette file has a line per layer, `bg`, `obj0` or `obj1`, followed by
+ * four hex colors from white to black. Layers left out take the colors of
+ * the background, and `;` starts a comment:
+ *
+ *     ; Green background with red sprites
+ *     bg   FFFFFF 7BFF31 0063C5 000000
+ *     obj0 FFFFFF FF8484 943A3A 000000
+ */
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::io::Error;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::gameboy::Color;
+
+mod cgb;
+
+/// A color as 0xRRGGBB
+pub type Rgb = u32;
+
+/// What a pixel on the screen was drawn from
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Layer {
+    Background,
+    Obj0,
+    Obj1,
+}
+
+/// The colors of the four shades, from white to black, for each layer
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct ColorPalette {
+    pub bg: [Rgb; 4],
+    pub obj0: [Rgb; 4],
+    pub obj1: [Rgb; 4],
+}
+
+impl ColorPalette {
+    /// The same colors for every layer
+    pub fn uniform(shades: [Rgb; 4]) -> ColorPalette {
+        ColorPalette {
+            bg: shades,
+            obj0: shades,
+            obj1: shades,
+        }
+    }
+
+    /// The palettes the CGB boot ROM colors a DMG game by Nintendo in, if
+    /// it knows the game by its title
+    pub fn for_cgb_title(title: &[u8; 16]) -> Option<ColorPalette> {
+        cgb::title_combination(title).map(cgb::combination)
+    }
+
+    /// The palettes the CGB boot ROM colors any other DMG game in: a green
+    /// and blue background with red sprites
+    pub fn cgb_default() -> ColorPalette {
+        cgb::combination(0)
+    }
+
+    pub fn rgb(&self, layer: Layer, color: Color) -> Rgb {
+        let shades = match layer {
+            Layer::Background => &self.bg,
+            Layer::Obj0 => &self.obj0,
+            Layer::Obj1 => &self.obj1,
+        };
+        shades[u8::from(&color) as usize]
+    }
+
+    pub fn load(path: &Path) -> io::Result<ColorPalette> {
+        let text = fs::read_to_string(path)?;
+        ColorPalette::parse(&text).map_err(|e| {
+            let message = format!("{}: {}", path.display(), e);
+            Error::new(ErrorKind::InvalidData, message)
+        })
+    }
+
+    /// Parses the contents of a palette file
+    pub fn parse(text: &str) -> Result<ColorPalette, String> {
+        let mut bg = None;
+        let mut obj0 = None;
+        let mut obj1 = None;
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split(';').next().unwrap().trim();
+            let mut parts = line.split_whitespace();
+            let layer = match parts.next() {
+                Some(layer) => layer,
+                None => continue,
+            };
+
+            let shades = parse_shades(parts).map_err(|e| format!("line {}: {}", number + 1, e))?;
+            match layer {
+                "bg" => bg = Some(shades),
+                "obj0" => obj0 = Some(shades),
+                "obj1" => obj1 = Some(shades),
+                _ => return Err(format!("line {}: unknown layer {}", number + 1, layer)),
+            }
+        }
+
+        let bg = bg.ok_or("no bg colors")?;
+        Ok(ColorPalette {
+            bg,
+            obj0: obj0.unwrap_or(bg),
+            obj1: obj1.unwrap_or(bg),
+        })
+    }
+}
+
+impl Default for ColorPalette {
+    fn default() -> ColorPalette {
+        Preset::default().palette()
+    }
+}
+
+fn parse_shades<'a>(colors: impl Iterator<Item = &'a str>) -> Result<[Rgb; 4], String> {
+    let colors: Vec<&str> = colors.collect();
+    if colors.len() != 4 {
+        return Err(format!("expected 4 colors, got {}", colors.len()));
+    }
+
+    let mut shades = [0; 4];
+    for (shade, text) in shades.iter_mut().zip(colors) {
+        let hex = text.trim_start_matches('#');
+        if hex.len() != 6 {
+            return Err(format!("invalid color {}", text));
+        }
+        *shade = Rgb::from_str_radix(hex, 16).map_err(|_| format!("invalid color {}", text))?;
+    }
+    Ok(shades)
+}
+
+/// Built-in palettes. The CGB ones are those the CGB boot ROM lets the player
+/// pick for a DMG game by holding a direction, alone or with A or B.
+#[derive(Debug, Default, PartialEq, Eq, Copy, Clone)]
+pub enum Preset {
+    #[default]
+    Dmg,
+    Pocket,
+    Light,
+    HighContrast,
+    CgbUp,
+    CgbUpA,
+    CgbUpB,
+    CgbLeft,
+    CgbLeftA,
+    CgbLeftB,
+    CgbDown,
+    CgbDownA,
+    CgbDownB,
+    CgbRight,
+    CgbRightA,
+    CgbRightB,
+}
+
+impl Preset {
+    pub const ALL: [Preset; 16] = [
+        Preset::Dmg,
+        Preset::Pocket,
+        Preset::Light,
+        Preset::HighContrast,
+        Preset::CgbUp,
+        Preset::CgbUpA,
+        Preset::CgbUpB,
+        Preset::CgbLeft,
+        Preset::CgbLeftA,
+        Preset::CgbLeftB,
+        Preset::CgbDown,
+        Preset::CgbDownA,
+        Preset::CgbDownB,
+        Preset::CgbRight,
+        Preset::CgbRightA,
+        Preset::CgbRightB,
+    ];
+
+    pub fn palette(self) -> ColorPalette {
+        use self::Preset::*;
+
+        match self {
+            Dmg => ColorPalette::uniform([0x9BBC0F, 0x8BAC0F, 0x306230, 0x0F380F]),
+            Pocket => ColorPalette::uniform([0xC4CFA1, 0x8B956D, 0x4D533C, 0x1F1F1F]),
+            Light => ColorPalette::uniform([0x00B581, 0x009A71, 0x00694A, 0x004F3B]),
+            HighContrast => ColorPalette::uniform([0xFFFFFF, 0xAAAAAA, 0x555555, 0x000000]),
+            CgbUp => cgb::combination(5),
+            CgbUpA => cgb::combination(43),
+            CgbUpB => cgb::combination(28),
+            CgbLeft => cgb::combination(48),
+            CgbLeftA => cgb::combination(40),
+            CgbLeftB => cgb::combination(7),
+            CgbDown => cgb::combination(8),
+            CgbDownA => cgb::combination(3),
+            CgbDownB => cgb::combination(49),
+            CgbRight => cgb::combination(1),
+            CgbRightA => cgb::combination(0),
+            CgbRightB => cgb::combination(6),
+        }
+    }
+}
+
+impl fmt::Display for Preset {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::Preset::*;
+
+        let name = match self {
+            Dmg => "dmg",
+            Pocket => "pocket",
+            Light => "light",
+            HighContrast => "high-contrast",
+            CgbUp => "cgb-up",
+            CgbUpA => "cgb-up-a",
+            CgbUpB => "cgb-up-b",
+            CgbLeft => "cgb-left",
+            CgbLeftA => "cgb-left-a",
+            CgbLeftB => "cgb-left-b",
+            CgbDown => "cgb-down",
+            CgbDownA => "cgb-down-a",
+            CgbDownB => "cgb-down-b",
+            CgbRight => "cgb-right",
+            CgbRightA => "cgb-right-a",
+            CgbRightB => "cgb-right-b",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Preset {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Preset, String> {
+        let text = text.to_ascii_lowercase();
+        Preset::ALL
+            .iter()
+            .find(|preset| preset.to_string() == text)
+            .copied()
+            .ok_or_else(|| format!("unknown palette {}", text))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn preset_names() {
+        for &preset in Preset::ALL.iter() {
+            assert_eq!(preset.to_string().parse(), Ok(preset));
+        }
+        assert_eq!("CGB-Up-A".parse(), Ok(Preset::CgbUpA));
+        assert!("sepia".parse::<Preset>().is_err());
+    }
+
+    #[test]
+    fn layer_colors() {
+        let palette = Preset::CgbLeft.palette();
+        assert_eq!(palette.rgb(Layer::Background, Color::Dark), 0x0000FF);
+        assert_eq!(palette.rgb(Layer::Obj0, Color::Light), 0xFF8484);
+        assert_eq!(palette.rgb(Layer::Obj1, Color::Light), 0x7BFF31);
+        assert_eq!(palette.rgb(Layer::Obj1, Color::White), 0xFFFFFF);
+    }
+
+    #[test]
+    fn parse_file() {
+        let text = "; Green background with red sprites
+bg   FFFFFF 7BFF31 0063C5 000000
+obj0 #FFFFFF #ff8484 #943A3A #000000 ; sprites
+
+";
+        let palette = ColorPalette::parse(text).unwrap();
+        assert_eq!(palette.bg, [0xFFFFFF, 0x7BFF31, 0x0063C5, 0x000000]);
+        assert_eq!(palette.obj0, [0xFFFFFF, 0xFF8484, 0x943A3A, 0x000000]);
+        assert_eq!(palette.obj1, palette.bg);
+    }
+
+    #[test]
+    fn parse_file_errors() {
+        let parse = |text| ColorPalette::parse(text).unwrap_err();
+
+        assert_eq!(parse("obj0 FFFFFF AAAAAA 555555 000000"), "no bg colors");
+        assert_eq!(
+            parse("bg FFFFFF AAAAAA 555555"),
+            "line 1: expected 4 colors, got 3"
+        );
+        assert_eq!(
+            parse("bg FFFFFF AAAAAA 555555 000000\nobj2 FFFFFF AAAAAA 555555 000000"),
+            "line 2: unknown layer obj2"
+        );
+        assert_eq!(
+            parse("bg FFFFFF AAAAAA 55555G 000000"),
+            "line 1: invalid color 55555G"
+        );
+    }
+}
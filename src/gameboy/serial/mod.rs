@@ -1,21 +1,36 @@
 /*
- * Serial data transfer emulation. This implementation completely fakes the transfer
- * since there are no plans to support linking within this emulator. If/when a real
- * implementation is required, this page has good information about expected behavior.
+ * Serial data transfer emulation. This implementation fakes the other end of the
+ * cable: transfers driven by the internal clock complete after 8 bits have been
+ * shifted out and read back 0xFF, as if nothing were connected. The outgoing byte
+ * is handed to an optional sink, which is how test ROMs report their results.
+ * If/when a real implementation is required, this page has good information about
+ * expected behavior.
  *   Reference: http://gbdev.gg8.se/wiki/articles/Serial_Data_Transfer_(Link_Cable)
  */
 
 use crate::bits;
+use crate::gameboy::irq::Interrupt;
+use crate::gameboy::irq::IRQ;
+use crate::gameboy::link::SerialSink;
+
+// 8 bits shifted at 8192 Hz; ~1.048 MHz / 8192 Hz = 128 cycles per bit
+const TRANSFER_CYCLES: u16 = 8 * 128;
+const DISCONNECTED_DATA: u8 = 0xFF;
 
 #[derive(PartialEq, Default, Debug)]
 struct Control {
     transfer: bool,
+    // Only kept for reading back on the CGB, as transfers always run at the
+    // normal speed
+    fast_clock: bool,
     internal_clock: bool,
 }
 
 impl From<&Control> for u8 {
     fn from(control: &Control) -> u8 {
-        bits::from_bool(control.transfer) << 7 | bits::from_bool(control.internal_clock)
+        bits::from_bool(control.transfer) << 7
+            | bits::from_bool(control.fast_clock) << 1
+            | bits::from_bool(control.internal_clock)
     }
 }
 
@@ -23,6 +38,7 @@ impl From<u8> for Control {
     fn from(byte: u8) -> Control {
         Control {
             transfer: bits::is_set(byte, 7),
+            fast_clock: bits::is_set(byte, 1),
             internal_clock: bits::is_set(byte, 0),
         }
     }
@@ -32,6 +48,8 @@ impl From<u8> for Control {
 pub struct Serial {
     data: u8,
     control: Control,
+    remaining_cycles: u16,
+    sink: Option<Box<dyn SerialSink>>,
 }
 
 impl Serial {
@@ -39,6 +57,30 @@ impl Serial {
         Serial::default()
     }
 
+    pub fn connect(&mut self, sink: Box<dyn SerialSink>) {
+        self.sink = Some(sink);
+    }
+
+    pub fn emulate(&mut self, irq: &mut IRQ) {
+        if !self.control.transfer || !self.control.internal_clock {
+            // Transfers using an external clock never complete without a partner
+            return;
+        }
+
+        self.remaining_cycles = self.remaining_cycles.saturating_sub(1);
+        if self.remaining_cycles > 0 {
+            return;
+        }
+
+        if let Some(sink) = self.sink.as_mut() {
+            sink.receive(self.data);
+        }
+
+        self.data = DISCONNECTED_DATA;
+        self.control.transfer = false;
+        irq.set_interrupt(&Interrupt::Serial);
+    }
+
     pub fn get_data(&self) -> u8 {
         self.data
     }
@@ -53,6 +95,9 @@ impl Serial {
 
     pub fn set_control(&mut self, byte: u8) {
         self.control = Control::from(byte);
+        if self.control.transfer {
+            self.remaining_cycles = TRANSFER_CYCLES;
+        }
     }
 }
 
@@ -60,14 +105,36 @@ impl Serial {
 mod test {
     use super::*;
 
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    struct TestSink {
+        received: Rc<RefCell<Vec<u8>>>,
+    }
+
+    impl SerialSink for TestSink {
+        fn receive(&mut self, byte: u8) {
+            self.received.borrow_mut().push(byte);
+        }
+    }
+
     #[test]
     fn u8_from_control() {
         let control = Control {
             transfer: true,
+            fast_clock: false,
             internal_clock: true,
         };
 
         assert_eq!(u8::from(&control), 0x81);
+
+        let control = Control {
+            transfer: false,
+            fast_clock: true,
+            internal_clock: true,
+        };
+
+        assert_eq!(u8::from(&control), 0x03);
     }
 
     #[test]
@@ -75,6 +142,7 @@ mod test {
         let control = Control::from(0x81);
         let expected = Control {
             transfer: true,
+            fast_clock: false,
             internal_clock: true,
         };
 
@@ -92,6 +160,51 @@ mod test {
         assert_eq!(serial.get_control(), 0x81);
     }
 
+    #[test]
+    fn serial_transfer() {
+        let mut serial = Serial::new();
+        let mut irq = IRQ::enabled();
+        let received = Rc::new(RefCell::new(Vec::new()));
+
+        serial.connect(Box::new(TestSink {
+            received: received.clone(),
+        }));
+        serial.set_data(0x42);
+        serial.set_control(0x81);
+
+        for _ in 1..TRANSFER_CYCLES {
+            serial.emulate(&mut irq);
+        }
+
+        assert!(received.borrow().is_empty());
+        assert_eq!(serial.get_control(), 0x81);
+        assert_eq!(irq.ack_interrupt(), None);
+
+        serial.emulate(&mut irq);
+
+        assert_eq!(*received.borrow(), vec![0x42]);
+        assert_eq!(serial.get_control(), 0x01);
+        assert_eq!(serial.get_data(), 0xFF);
+        assert_eq!(irq.ack_interrupt(), Some(Interrupt::Serial.get_addr()));
+    }
+
+    #[test]
+    fn serial_transfer_external_clock() {
+        let mut serial = Serial::new();
+        let mut irq = IRQ::enabled();
+
+        serial.set_data(0x42);
+        serial.set_control(0x80);
+
+        for _ in 0..TRANSFER_CYCLES {
+            serial.emulate(&mut irq);
+        }
+
+        assert_eq!(serial.get_control(), 0x80);
+        assert_eq!(serial.get_data(), 0x42);
+        assert_eq!(irq.ack_interrupt(), None);
+    }
+
     #[test]
     fn serial_data() {
         let mut serial = Serial::new();
@@ -3,22 +3,20 @@ use crate::gameboy::irq::Interrupt;
 use crate::gameboy::irq::IRQ;
 
 /**
- * The TAC contains control bits for the timer. The Gameboy clock speed is
- * ~1.048 MHz. Use the input clock select frequency as a denominator to determine
- * how many machine cycles it will take to increment once.
- *
- * For example, if the input clock select is 4096 Hz, the number of machine cycles to increment
- * would be 1.048 MHz / 4096 Hz ~= 256.
+ * The TAC contains control bits for the timer. The timer is driven by a 16-bit
+ * system counter which increments every T-cycle, and DIV is its upper byte.
+ * TIMA increments whenever the counter bit picked by the input clock select
+ * falls from 1 to 0 while the timer is enabled.
  *
  * Bit  2   - Timer Enable
  * Bits 1-0 - Input Clock Select
- *    00: 4096 Hz; 256 cycles
- *    01: 262144 Hz; 4 cycles
- *    10: 65536 Hz; 16 cycles
- *    11: 16384 Hz; 64 cycles
+ *    00: 4096 Hz; bit 9
+ *    01: 262144 Hz; bit 3
+ *    10: 65536 Hz; bit 5
+ *    11: 16384 Hz; bit 7
  *
- * The divider register always increments, but TIMA only increments when the timer
- * is enabled.
+ * As only the falling edge matters, resetting DIV or changing TAC while the
+ * selected bit is set increments TIMA as well.
  */
 #[derive(Default, Debug)]
 struct Control {
@@ -28,16 +26,16 @@ struct Control {
 }
 
 impl Control {
-    fn counter_mask(&self) -> u16 {
-        let cycles = match self.clock_select() {
-            0b00 => 256,
-            0b01 => 4,
-            0b10 => 16,
-            0b11 => 64,
+    fn counter_bit(&self) -> u16 {
+        let bit = match self.clock_select() {
+            0b00 => 9,
+            0b01 => 3,
+            0b10 => 5,
+            0b11 => 7,
             _ => unreachable!(),
         };
 
-        cycles - 1
+        1 << bit
     }
 
     fn clock_select(&self) -> u8 {
@@ -63,11 +61,25 @@ impl From<&Control> for u8 {
     }
 }
 
+/// When TIMA overflows it reads 0x00 for one M-cycle before TMA is loaded
+/// and the interrupt is requested, and writes during either cycle behave
+/// differently
+#[derive(Debug, PartialEq, Copy, Clone)]
+enum Reload {
+    None,
+    /// TIMA has overflowed and reads 0x00. Writing TIMA cancels the reload.
+    Pending,
+    /// TIMA is being loaded from TMA. Writes to TIMA are ignored, and writes
+    /// to TMA are loaded into TIMA as well.
+    Reloading,
+}
+
 pub struct Timer {
     tima: u8,
     tma: u8,
     tac: Control,
     counter: u16,
+    reload: Reload,
 }
 
 impl Timer {
@@ -77,40 +89,56 @@ impl Timer {
             tma: 0,
             tac: Control::default(),
             counter: 0,
+            reload: Reload::None,
         }
     }
 
+    /// Advances the timer by one M-cycle, before the CPU accesses the bus
     pub fn emulate(&mut self, irq: &mut IRQ) {
-        self.counter = self.counter.wrapping_add(1);
-        if !self.tac.enabled {
-            return;
-        }
+        self.reload = match self.reload {
+            Reload::Pending => {
+                self.tima = self.tma;
+                irq.set_interrupt(&Interrupt::Timer);
+                Reload::Reloading
+            }
+            Reload::Reloading | Reload::None => Reload::None,
+        };
+
+        let signal = self.signal();
+        self.counter = self.counter.wrapping_add(4);
+        self.detect_falling_edge(signal);
+    }
+
+    /// The input to TIMA, whose falling edge increments it
+    fn signal(&self) -> bool {
+        self.tac.enabled && self.counter & self.tac.counter_bit() != 0
+    }
 
-        if !self.can_increment_tima() {
+    fn detect_falling_edge(&mut self, previous: bool) {
+        if !previous || self.signal() {
             return;
         }
 
         let (value, overflow) = self.tima.overflowing_add(1);
+        self.tima = value;
         if overflow {
-            self.tima = self.tma;
-            irq.set_interrupt(&Interrupt::Timer);
-        } else {
-            self.tima = value;
+            self.reload = Reload::Pending;
         }
     }
 
-    fn can_increment_tima(&self) -> bool {
-        let mask = self.tac.counter_mask();
-        self.counter & mask == 0
+    pub fn get_div(&self) -> u8 {
+        (self.counter >> 8) as u8
     }
 
-    pub fn get_div(&self) -> u8 {
-        // Set frequency of 16384 Hz, so will count up every 2^6 = 64 cycles
-        (self.counter >> 6) as u8
+    /// Sets the system counter that DIV is the upper byte of, in T-cycles
+    pub fn set_counter(&mut self, counter: u16) {
+        self.counter = counter;
     }
 
     pub fn reset_div(&mut self) {
+        let signal = self.signal();
         self.counter = 0;
+        self.detect_falling_edge(signal);
     }
 
     pub fn get_tima(&self) -> u8 {
@@ -118,7 +146,14 @@ impl Timer {
     }
 
     pub fn set_tima(&mut self, byte: u8) {
-        self.tima = byte;
+        match self.reload {
+            Reload::Pending => {
+                self.tima = byte;
+                self.reload = Reload::None;
+            }
+            Reload::Reloading => {}
+            Reload::None => self.tima = byte,
+        }
     }
 
     pub fn get_tma(&self) -> u8 {
@@ -127,6 +162,9 @@ impl Timer {
 
     pub fn set_tma(&mut self, byte: u8) {
         self.tma = byte;
+        if self.reload == Reload::Reloading {
+            self.tima = byte;
+        }
     }
 
     pub fn get_tac(&self) -> u8 {
@@ -134,33 +172,54 @@ impl Timer {
     }
 
     pub fn set_tac(&mut self, byte: u8) {
+        let signal = self.signal();
         self.tac = Control::from(byte);
+        self.detect_falling_edge(signal);
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::asm::Alu;
+    use crate::asm::Assembler;
+    use crate::asm::Cond;
+    use crate::asm::Ind;
+    use crate::asm::R8;
+    use crate::gameboy::clock::NoClock;
+    use crate::gameboy::display::NoDisplay;
+    use crate::gameboy::GameBoy;
+    use crate::gameboy::Model;
+    use crate::gameboy::Renderer;
+
+    /// A timer at 262144 Hz with TIMA about to overflow
+    fn overflowing_timer() -> Timer {
+        let mut timer = Timer::new();
+        timer.set_tac(0b101);
+        timer.set_tima(0xFF);
+        timer.set_tma(0x10);
+        timer
+    }
 
     #[test]
-    fn control_counter_mask() {
+    fn control_counter_bit() {
         let mut control = Control::default();
 
         control.clock_bit_1 = true;
         control.clock_bit_0 = true;
-        assert_eq!(control.counter_mask(), 0b0011_1111);
+        assert_eq!(control.counter_bit(), 1 << 7);
 
         control.clock_bit_1 = true;
         control.clock_bit_0 = false;
-        assert_eq!(control.counter_mask(), 0b0000_1111);
+        assert_eq!(control.counter_bit(), 1 << 5);
 
         control.clock_bit_1 = false;
         control.clock_bit_0 = true;
-        assert_eq!(control.counter_mask(), 0b0000_0011);
+        assert_eq!(control.counter_bit(), 1 << 3);
 
         control.clock_bit_1 = false;
         control.clock_bit_0 = false;
-        assert_eq!(control.counter_mask(), 0b1111_1111);
+        assert_eq!(control.counter_bit(), 1 << 9);
     }
 
     #[test]
@@ -212,22 +271,166 @@ mod test {
 
     #[test]
     fn emulate_overflow() {
+        let mut timer = overflowing_timer();
+        let mut irq = IRQ::enabled();
+
+        for _ in 0..4 {
+            timer.emulate(&mut irq);
+        }
+
+        // TIMA reads 0x00 for one M-cycle before it is reloaded
+        assert_eq!(timer.get_tima(), 0x00);
+        assert_eq!(irq.ack_interrupt(), None);
+
+        timer.emulate(&mut irq);
+        assert_eq!(timer.get_tima(), 0x10);
+        assert_eq!(timer.get_tma(), 0x10);
+        assert_eq!(irq.ack_interrupt(), Some(Interrupt::Timer.get_addr()));
+    }
+
+    #[test]
+    fn div_write_falling_edge() {
         let mut timer = Timer::new();
         let mut irq = IRQ::enabled();
+        timer.set_tac(0b101);
 
-        timer.tac.enabled = true;
-        timer.tac.clock_bit_1 = false;
-        timer.tac.clock_bit_0 = true;
+        // Bit 3 is clear, so resetting DIV does nothing
+        timer.emulate(&mut irq);
+        timer.reset_div();
+        assert_eq!(timer.get_tima(), 0);
 
-        timer.set_tima(0xFF);
-        timer.set_tma(0x10);
+        // Bit 3 is set, so resetting DIV makes it fall
+        timer.emulate(&mut irq);
+        timer.emulate(&mut irq);
+        timer.reset_div();
+        assert_eq!(timer.get_tima(), 1);
+        assert_eq!(timer.get_div(), 0);
+    }
+
+    #[test]
+    fn tac_write_falling_edge() {
+        let mut timer = Timer::new();
+        let mut irq = IRQ::enabled();
+        timer.set_tac(0b101);
+        timer.emulate(&mut irq);
+        timer.emulate(&mut irq);
+
+        // Selecting a clear bit or disabling the timer makes the input fall
+        timer.set_tac(0b100);
+        assert_eq!(timer.get_tima(), 1);
+        timer.set_tac(0b101);
+        timer.set_tac(0b001);
+        assert_eq!(timer.get_tima(), 2);
+
+        // Selecting another set bit doesn't
+        timer.set_counter(0x0028);
+        timer.set_tac(0b101);
+        timer.set_tac(0b110);
+        assert_eq!(timer.get_tima(), 2);
+    }
+
+    #[test]
+    fn tima_write_cancels_reload() {
+        let mut timer = overflowing_timer();
+        let mut irq = IRQ::enabled();
 
         for _ in 0..4 {
             timer.emulate(&mut irq);
         }
+        timer.set_tima(0x42);
+        timer.emulate(&mut irq);
+
+        assert_eq!(timer.get_tima(), 0x42);
+        assert_eq!(irq.ack_interrupt(), None);
+    }
+
+    #[test]
+    fn tima_write_while_reloading() {
+        let mut timer = overflowing_timer();
+        let mut irq = IRQ::enabled();
+
+        for _ in 0..5 {
+            timer.emulate(&mut irq);
+        }
+        timer.set_tima(0x42);
 
         assert_eq!(timer.get_tima(), 0x10);
-        assert_eq!(timer.get_tma(), 0x10);
         assert_eq!(irq.ack_interrupt(), Some(Interrupt::Timer.get_addr()));
+
+        // Once reloaded, TIMA can be written again
+        timer.emulate(&mut irq);
+        timer.set_tima(0x42);
+        assert_eq!(timer.get_tima(), 0x42);
+    }
+
+    #[test]
+    fn tma_write_while_reloading() {
+        let mut timer = overflowing_timer();
+        let mut irq = IRQ::enabled();
+
+        for _ in 0..5 {
+            timer.emulate(&mut irq);
+        }
+        timer.set_tma(0x20);
+        assert_eq!(timer.get_tima(), 0x20);
+
+        // Outside of the reload TMA is only used at the next overflow
+        timer.emulate(&mut irq);
+        timer.set_tma(0x30);
+        assert_eq!(timer.get_tima(), 0x20);
+    }
+
+    /// Runs a ROM that sets TIMA to 0xFF at 4096 Hz, waits for `delay`
+    /// loops and writes DIV. Returns TIMA and IF as read right after.
+    fn div_write_rom(delay: u8) -> (u8, u8) {
+        let mut asm = Assembler::new("DIV WRITE");
+        asm.di()
+            .ld_n(R8::A, 0x10)
+            .ld_ind_a(Ind::High(0x06))
+            .ld_n(R8::A, 0b100)
+            .ld_ind_a(Ind::High(0x07))
+            // Start from a known system counter
+            .alu(Alu::Xor, R8::A)
+            .ld_ind_a(Ind::High(0x04))
+            .ld_n(R8::A, 0xFF)
+            .ld_ind_a(Ind::High(0x05))
+            .alu(Alu::Xor, R8::A)
+            .ld_ind_a(Ind::High(0x0F))
+            .ld_n(R8::B, delay)
+            .label("delay")
+            .dec(R8::B)
+            .jr_cc(Cond::NZ, "delay")
+            .ld_ind_a(Ind::High(0x04))
+            .ld_a_ind(Ind::High(0x05))
+            .ld(R8::B, R8::A)
+            .ld_a_ind(Ind::High(0x0F))
+            .ld(R8::C, R8::A);
+        let done = asm.here();
+        asm.label("done").jr("done");
+
+        let rom = asm.build();
+        let mut gameboy = GameBoy::new(
+            &rom,
+            Model::Dmg,
+            Renderer::default(),
+            Box::new(NoClock::new()),
+            Box::new(NoDisplay::new()),
+        );
+        while gameboy.registers().pc != done {
+            gameboy.step();
+        }
+
+        let registers = gameboy.registers();
+        (registers.b, registers.c & 0x04)
+    }
+
+    #[test]
+    fn div_write_reloads_tima() {
+        // Bit 9 of the counter is still clear, so TIMA is left alone
+        assert_eq!(div_write_rom(1), (0xFF, 0x00));
+
+        // Bit 9 is set, so the DIV write overflows TIMA, which is reloaded
+        // from TMA with the interrupt requested by the time it's read
+        assert_eq!(div_write_rom(40), (0x10, 0x04));
     }
 }
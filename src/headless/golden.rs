@@ -0,0 +1,427 @@
+/*
+ * Golden image regression harness. A ROM is run headless for a fixed number of
+ * frames, and the last complete frame is compared pixel for pixel against a
+ * reference PNG. On a mismatch, the actual frame and a diff image (mismatched
+ * pixels in red over a faded copy of the reference) are written to
+ * target/golden so the failure can be inspected.
+ *
+ * Scenes are our own cases, assembled in the tests below, and their reference
+ * images are kept in golden/ as <name>.png. Test ROMs such as dmg-acid2 are not
+ * vendored: set RUSTBOY_TEST_ROMS to a directory holding <name>.gb and its
+ * reference <name>.png to check them. They are skipped with a notice otherwise.
+ * For dmg-acid2, the reference is reference-dmg.png from its repository.
+ * Run with RUSTBOY_BLESS=1 to (re)write the reference images from the current
+ * output.
+ *
+ * Frames are drawn with the high contrast palette, whose shades follow the
+ * dmg-acid2 reference images: 0xFF, 0xAA, 0x55 and 0x00.
+ */
+
+use std::cell::Cell;
+use std::env;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::BufWriter;
+use std::path::Path;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use crate::gameboy::clock::NoClock;
+use crate::gameboy::display::VideoDisplay;
+use crate::gameboy::display::SCREEN_HEIGHT;
+use crate::gameboy::display::SCREEN_WIDTH;
+use crate::gameboy::palette::Preset;
+use crate::gameboy::palette::Rgb;
+use crate::gameboy::GameBoy;
+use crate::gameboy::Model;
+use crate::gameboy::Renderer;
+
+// 154 lines of 114 cycles each
+const CYCLES_PER_FRAME: u64 = 154 * 114;
+
+/// Name and number of frames to run for each test ROM that isn't vendored.
+/// Enough frames for the boot ROM to finish, as with the scenes.
+const TEST_ROMS: &[(&str, u32)] = &[("dmg-acid2", 600)];
+
+#[derive(Clone, PartialEq)]
+struct Frame {
+    pixels: Vec<Rgb>,
+}
+
+impl Frame {
+    fn new() -> Frame {
+        Frame {
+            pixels: vec![0xFFFFFF; SCREEN_WIDTH * SCREEN_HEIGHT],
+        }
+    }
+
+    fn to_rgb(&self) -> Vec<u8> {
+        self.pixels
+            .iter()
+            .flat_map(|rgb| rgb.to_be_bytes()[1..].to_vec())
+            .collect()
+    }
+}
+
+/// Counts the frames drawn. A blank screen is shown while the LCD is off, but
+/// isn't counted as a drawn frame.
+struct FrameCounter {
+    frames: Rc<Cell<u32>>,
+}
+
+impl VideoDisplay for FrameCounter {
+    fn vsync(&mut self, _frame: &[Rgb]) {
+        self.frames.set(self.frames.get() + 1);
+    }
+
+    fn blank(&mut self, _white: Rgb) {}
+}
+
+/// Runs the ROM until the given number of frames have been drawn and returns
+/// the last one. Gives up after twice the expected number of cycles, in case
+/// the LCD is left off.
+fn capture(rom: &[u8], frames: u32, renderer: Renderer) -> Frame {
+    let drawn = Rc::new(Cell::new(0));
+    let display = FrameCounter {
+        frames: drawn.clone(),
+    };
+
+    let rom = rom.to_vec();
+    let mut gameboy = GameBoy::new(
+        &rom,
+        Model::Dmg,
+        renderer,
+        Box::new(NoClock::new()),
+        Box::new(display),
+    );
+    gameboy.set_color_palette(Preset::HighContrast.palette());
+
+    let cycle_limit = 2 * CYCLES_PER_FRAME * frames as u64;
+    let mut cycles = 0;
+    while drawn.get() < frames && cycles < cycle_limit {
+        cycles += gameboy.step() as u64;
+    }
+
+    Frame {
+        pixels: gameboy.framebuffer().to_vec(),
+    }
+}
+
+fn read_png(path: &Path) -> io::Result<Vec<u8>> {
+    let mut decoder = png::Decoder::new(File::open(path)?);
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+
+    let mut reader = decoder.read_info().map_err(to_io_error)?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer).map_err(to_io_error)?;
+
+    if info.width as usize != SCREEN_WIDTH || info.height as usize != SCREEN_HEIGHT {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("expected 160x144 image, got {}x{}", info.width, info.height),
+        ));
+    }
+
+    let channels = info.color_type.samples();
+    let rgb = buffer[..info.buffer_size()]
+        .chunks(channels)
+        .flat_map(|pixel| match pixel.len() {
+            1 | 2 => vec![pixel[0], pixel[0], pixel[0]],
+            _ => pixel[..3].to_vec(),
+        })
+        .collect();
+
+    Ok(rgb)
+}
+
+fn write_png(path: &Path, rgb: &[u8]) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let writer = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(writer, SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header().map_err(to_io_error)?;
+    writer.write_image_data(rgb).map_err(to_io_error)
+}
+
+fn to_io_error<E: std::error::Error + Send + Sync + 'static>(e: E) -> io::Error {
+    io::Error::other(e)
+}
+
+/// Marks mismatched pixels in red over a faded copy of the expected image.
+/// Returns the image along with the number of mismatched pixels.
+fn diff_image(actual: &[u8], expected: &[u8]) -> (Vec<u8>, usize) {
+    let mut mismatches = 0;
+    let mut diff = Vec::with_capacity(expected.len());
+
+    for (a, e) in actual.chunks(3).zip(expected.chunks(3)) {
+        if a == e {
+            diff.extend(e.iter().map(|&v| 0xC0 + v / 4));
+        } else {
+            mismatches += 1;
+            diff.extend_from_slice(&[0xFF, 0x00, 0x00]);
+        }
+    }
+
+    (diff, mismatches)
+}
+
+/// Compares the frame with the reference image, writing the actual frame and
+/// a diff image to the output directory if they differ.
+fn check_frame(name: &str, frame: &Frame, reference: &Path, out_dir: &Path) -> Result<(), String> {
+    let actual = frame.to_rgb();
+
+    if env::var_os("RUSTBOY_BLESS").is_some() {
+        return write_png(reference, &actual).map_err(|e| e.to_string());
+    }
+
+    let expected = read_png(reference).map_err(|e| format!("{}: {}", reference.display(), e))?;
+    let (diff, mismatches) = diff_image(&actual, &expected);
+    if mismatches == 0 {
+        return Ok(());
+    }
+
+    let actual_path = out_dir.join(format!("{}.actual.png", name));
+    let diff_path = out_dir.join(format!("{}.diff.png", name));
+    write_png(&actual_path, &actual).map_err(|e| e.to_string())?;
+    write_png(&diff_path, &diff).map_err(|e| e.to_string())?;
+
+    Err(format!(
+        "{}: {} pixels differ; see {}",
+        name,
+        mismatches,
+        diff_path.display()
+    ))
+}
+
+fn manifest_path(path: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join(path)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::asm::Alu;
+    use crate::asm::Assembler;
+    use crate::asm::Cond;
+    use crate::asm::Ind;
+    use crate::asm::Shift;
+    use crate::asm::R16;
+    use crate::asm::R8;
+
+    /// Name, ROM builder and number of frames to run for a scene
+    type Scene = (&'static str, fn() -> Vec<u8>, u32);
+
+    const SCENES: &[Scene] = &[("checkerboard", checkerboard, 600), ("bg-off", bg_off, 600)];
+
+    /// A scrolled checkerboard of two background tiles with a sprite on top
+    fn checkerboard() -> Vec<u8> {
+        let mut asm = Assembler::new("CHECKERBOARD");
+        draw_checkerboard(&mut asm);
+
+        // Turn on the LCD, BG and sprites
+        asm.ld_n(R8::A, 0x93)
+            .ld_ind_a(Ind::High(0x40))
+            .label("done")
+            .jr("done");
+
+        asm.build()
+    }
+
+    /// The checkerboard scene with the window over it, after a frame with
+    /// LCDC.0 cleared, which leaves only the sprite on a blank screen
+    fn bg_off() -> Vec<u8> {
+        let mut asm = Assembler::new("BG OFF");
+        draw_checkerboard(&mut asm);
+
+        // The window shows the same map, from (48, 32)
+        asm.ld_n(R8::A, 32)
+            .ld_ind_a(Ind::High(0x4A))
+            .ld_n(R8::A, 7 + 48)
+            .ld_ind_a(Ind::High(0x4B))
+            .ld_n(R8::A, 0xB3)
+            .ld_ind_a(Ind::High(0x40));
+
+        // Let a frame be drawn with the background on before clearing LCDC.0
+        asm.label("wait_line_143")
+            .ld_a_ind(Ind::High(0x44))
+            .alu_n(Alu::Cp, 143)
+            .jr_cc(Cond::NZ, "wait_line_143")
+            .label("wait_line_144")
+            .ld_a_ind(Ind::High(0x44))
+            .alu_n(Alu::Cp, 144)
+            .jr_cc(Cond::NZ, "wait_line_144")
+            .ld_n(R8::A, 0xB2)
+            .ld_ind_a(Ind::High(0x40))
+            .label("done")
+            .jr("done");
+
+        asm.build()
+    }
+
+    /// Sets up the tiles, map, sprite, palettes and scroll of the checkerboard
+    /// scene, leaving the LCD off
+    fn draw_checkerboard(asm: &mut Assembler) {
+        // Only touch VRAM with the LCD off, which must happen during VBlank
+        asm.label("wait_vblank")
+            .ld_a_ind(Ind::High(0x44))
+            .alu_n(Alu::Cp, 144)
+            .jr_cc(Cond::NZ, "wait_vblank")
+            .alu(Alu::Xor, R8::A)
+            .ld_ind_a(Ind::High(0x40));
+
+        // Tile 1 uses all four colors; tile 2 is solid black
+        asm.ld16(R16::HL, 0x8010)
+            .ld_n(R8::B, 8)
+            .label("tile_1")
+            .ld_n(R8::A, 0xF0)
+            .ld_ind_a(Ind::HLInc)
+            .ld_n(R8::A, 0xCC)
+            .ld_ind_a(Ind::HLInc)
+            .dec(R8::B)
+            .jr_cc(Cond::NZ, "tile_1")
+            .ld_n(R8::B, 16)
+            .ld_n(R8::A, 0xFF)
+            .label("tile_2")
+            .ld_ind_a(Ind::HLInc)
+            .dec(R8::B)
+            .jr_cc(Cond::NZ, "tile_2");
+
+        // Alternate tiles 0 and 1 by column and row: (col ^ row) & 1
+        asm.ld16(R16::HL, 0x9800)
+            .ld16(R16::BC, 0x0400)
+            .label("map")
+            .ld(R8::A, R8::L)
+            .shift(Shift::Swap, R8::A)
+            .rrca()
+            .alu(Alu::Xor, R8::L)
+            .alu_n(Alu::And, 0x01)
+            .ld_ind_a(Ind::HLInc)
+            .dec16(R16::BC)
+            .ld(R8::A, R8::B)
+            .alu(Alu::Or, R8::C)
+            .jr_cc(Cond::NZ, "map");
+
+        // Sprite 0 at (40, 40) using tile 2
+        asm.ld16(R16::HL, 0xFE00)
+            .ld_n(R8::A, 16 + 40)
+            .ld_ind_a(Ind::HLInc)
+            .ld_n(R8::A, 8 + 40)
+            .ld_ind_a(Ind::HLInc)
+            .ld_n(R8::A, 2)
+            .ld_ind_a(Ind::HLInc)
+            .alu(Alu::Xor, R8::A)
+            .ld_ind_a(Ind::HLInc);
+
+        // Identity palettes and scroll by half a tile
+        asm.ld_n(R8::A, 0b11_10_01_00)
+            .ld_ind_a(Ind::High(0x47))
+            .ld_ind_a(Ind::High(0x48))
+            .ld_n(R8::A, 4)
+            .ld_ind_a(Ind::High(0x42))
+            .ld_ind_a(Ind::High(0x43));
+    }
+
+    fn test_frame() -> Frame {
+        let mut frame = Frame::new();
+        for (i, pixel) in frame.pixels.iter_mut().enumerate() {
+            *pixel = Preset::HighContrast.palette().bg[i % 4];
+        }
+        frame
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("rustboy-golden-{}", name));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn png_round_trip() {
+        let dir = temp_dir("round-trip");
+        let path = dir.join("frame.png");
+        let frame = test_frame();
+
+        write_png(&path, &frame.to_rgb()).unwrap();
+
+        assert_eq!(read_png(&path).unwrap(), frame.to_rgb());
+    }
+
+    #[test]
+    fn diff_marks_mismatches() {
+        let expected = [0xFF, 0xFF, 0xFF, 0x00, 0x00, 0x00];
+        let actual = [0xFF, 0xFF, 0xFF, 0x55, 0x55, 0x55];
+
+        let (diff, mismatches) = diff_image(&actual, &expected);
+
+        assert_eq!(mismatches, 1);
+        assert_eq!(diff, vec![0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn check_frame_writes_diff() {
+        let dir = temp_dir("mismatch");
+        let reference = dir.join("reference.png");
+        let frame = test_frame();
+        write_png(&reference, &Frame::new().to_rgb()).unwrap();
+
+        let result = check_frame("scene", &frame, &reference, &dir);
+
+        assert!(result.is_err());
+        assert!(dir.join("scene.diff.png").exists());
+        assert_eq!(
+            read_png(&dir.join("scene.actual.png")).unwrap(),
+            frame.to_rgb()
+        );
+    }
+
+    #[test]
+    fn golden_images() {
+        let out_dir = manifest_path("target/golden");
+        let mut failures = Vec::new();
+
+        let test_roms = env::var_os("RUSTBOY_TEST_ROMS").map(PathBuf::from);
+        for &(name, frames) in TEST_ROMS {
+            let rom_path = match &test_roms {
+                Some(dir) => dir.join(format!("{}.gb", name)),
+                None => {
+                    println!("skipping {}: RUSTBOY_TEST_ROMS is not set", name);
+                    continue;
+                }
+            };
+            let rom = match fs::read(&rom_path) {
+                Ok(rom) => rom,
+                Err(e) => {
+                    println!("skipping {}: {}: {}", name, rom_path.display(), e);
+                    continue;
+                }
+            };
+
+            let frame = capture(&rom, frames, Renderer::default());
+            let reference = rom_path.with_extension("png");
+            if let Err(e) = check_frame(name, &frame, &reference, &out_dir) {
+                failures.push(e);
+            }
+        }
+
+        // Scenes have no mid-line effects, so both renderers must draw them
+        // the same
+        for &(name, build, frames) in SCENES {
+            let reference = manifest_path(&format!("golden/{}.png", name));
+            for &renderer in &[Renderer::Fifo, Renderer::Scanline] {
+                let frame = capture(&build(), frames, renderer);
+                let label = format!("{}-{}", name, renderer);
+                if let Err(e) = check_frame(&label, &frame, &reference, &out_dir) {
+                    failures.push(e);
+                }
+            }
+        }
+
+        assert!(failures.is_empty(), "{}", failures.join("\n"));
+    }
+}
@@ -0,0 +1,11 @@
+/*
+ * Frontends that run the emulator without a window, for automated testing.
+ */
+
+mod suite;
+
+#[cfg(test)]
+mod golden;
+
+pub use self::suite::run_suite;
+pub use self::suite::SuiteOptions;
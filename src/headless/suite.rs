@@ -0,0 +1,596 @@
+/*
+ * Runs a directory of test ROMs as fast as possible and reports the results.
+ * Two result protocols are understood:
+ *
+ *   Blargg:  the ROM prints its result over the serial port, ending with
+ *            either "Passed" or "Failed". Whatever it prints after that, such
+ *            as a failure code, is kept until the serial port goes quiet.
+ *   Mooneye: the ROM executes LD B,B once complete with the Fibonacci numbers
+ *            3/5/8/13/21/34 in B/C/D/E/H/L on success, or 0x42 in each on failure.
+ *
+ * ROMs that do neither within their cycle budget or wall clock timeout are
+ * reported as timed out along with a backtrace of where they were stuck; ROMs
+ * that panic the emulator are reported as crashed. The last frame of a ROM
+ * that failed or timed out can be saved as a PPM image to see what it showed.
+ */
+
+use std::cell::RefCell;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::panic;
+use std::panic::AssertUnwindSafe;
+use std::path::Path;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::time::Duration;
+use std::time::Instant;
+
+use crate::gameboy::clock::NoClock;
+use crate::gameboy::debug::crashdump::panic_message;
+use crate::gameboy::display::NoDisplay;
+use crate::gameboy::display::SCREEN_HEIGHT;
+use crate::gameboy::display::SCREEN_WIDTH;
+use crate::gameboy::link::SerialSink;
+use crate::gameboy::palette::Rgb;
+use crate::gameboy::GameBoy;
+use crate::gameboy::Model;
+use crate::gameboy::RamInit;
+use crate::gameboy::Renderer;
+
+// Two minutes of emulated time at ~1.048 MHz
+const DEFAULT_CYCLE_BUDGET: u64 = 120 * 1_048_576;
+const DEFAULT_TIMEOUT_SECS: u64 = 60;
+
+// Checking the wall clock on every step is needlessly expensive
+const STEPS_PER_TIMEOUT_CHECK: u32 = 4096;
+
+// Once a Blargg ROM prints its result, how long to wait for more output
+const CYCLES_PER_FRAME: u64 = 154 * 114;
+const SERIAL_QUIET_CYCLES: u64 = 10 * CYCLES_PER_FRAME;
+const SERIAL_DRAIN_CYCLES: u64 = 120 * CYCLES_PER_FRAME;
+
+// Length of "Passed" and "Failed"
+const MARKER_LEN: usize = 6;
+
+const LD_B_B: u8 = 0x40;
+const MOONEYE_PASS: [u8; 6] = [3, 5, 8, 13, 21, 34];
+const MOONEYE_FAIL: [u8; 6] = [0x42; 6];
+
+pub struct SuiteOptions {
+    pub model: Model,
+    pub renderer: Renderer,
+    pub ram_init: RamInit,
+    pub boot_rom: Option<Vec<u8>>,
+    pub skip_boot: bool,
+    pub cycle_budget: u64,
+    pub timeout: Duration,
+    pub report: Option<PathBuf>,
+    /// Where to save the last frame of ROMs that don't pass
+    pub frames: Option<PathBuf>,
+}
+
+impl Default for SuiteOptions {
+    fn default() -> SuiteOptions {
+        SuiteOptions {
+            model: Model::default(),
+            renderer: Renderer::default(),
+            ram_init: RamInit::default(),
+            boot_rom: None,
+            skip_boot: false,
+            cycle_budget: DEFAULT_CYCLE_BUDGET,
+            timeout: Duration::from_secs(DEFAULT_TIMEOUT_SECS),
+            report: None,
+            frames: None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+enum Outcome {
+    Passed,
+    Failed,
+    Timeout,
+    Crashed,
+}
+
+impl Outcome {
+    fn name(self) -> &'static str {
+        match self {
+            Outcome::Passed => "passed",
+            Outcome::Failed => "failed",
+            Outcome::Timeout => "timeout",
+            Outcome::Crashed => "crashed",
+        }
+    }
+}
+
+struct TestResult {
+    name: String,
+    outcome: Outcome,
+    cycles: u64,
+    elapsed: Duration,
+    detail: String,
+}
+
+/// Captures everything sent over the serial port
+struct SerialCapture {
+    output: Rc<RefCell<Vec<u8>>>,
+}
+
+impl SerialSink for SerialCapture {
+    fn receive(&mut self, byte: u8) {
+        self.output.borrow_mut().push(byte);
+    }
+}
+
+/// Runs every ROM found under the directory, prints a result table and
+/// optionally writes a JSON report. Returns true if every ROM passed.
+pub fn run_suite(dir: &Path, options: &SuiteOptions) -> io::Result<bool> {
+    let mut roms = Vec::new();
+    find_roms(dir, &mut roms)?;
+    roms.sort();
+
+    let mut results = Vec::new();
+    for path in roms {
+        let name = path
+            .strip_prefix(dir)
+            .unwrap_or(&path)
+            .to_string_lossy()
+            .into_owned();
+        let data = fs::read(&path)?;
+        results.push(run_rom(name, &data, options));
+    }
+
+    print_table(&results);
+    if let Some(report) = &options.report {
+        let mut file = fs::File::create(report)?;
+        write_report(&mut file, &results)?;
+    }
+
+    Ok(results.iter().all(|r| r.outcome == Outcome::Passed))
+}
+
+fn find_roms(dir: &Path, roms: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_roms(&path, roms)?;
+        } else if is_rom(&path) {
+            roms.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+fn is_rom(path: &Path) -> bool {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some(ext) => ext.eq_ignore_ascii_case("gb") || ext.eq_ignore_ascii_case("gbc"),
+        None => false,
+    }
+}
+
+fn run_rom(name: String, data: &[u8], options: &SuiteOptions) -> TestResult {
+    let start = Instant::now();
+    let output = Rc::new(RefCell::new(Vec::new()));
+    let mut cycles = 0;
+    let mut frame = Vec::new();
+
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        run_until_done(data, options, &output, &mut cycles, &mut frame, start)
+    }));
+
+    let (outcome, detail) = match result {
+        Ok((outcome, backtrace)) => {
+            let mut detail = String::from_utf8_lossy(&output.borrow()).into_owned();
+            if let Some(backtrace) = backtrace {
+                detail.push('\n');
+                detail.push_str(&backtrace);
+            }
+            (outcome, detail)
+        }
+        Err(payload) => (Outcome::Crashed, panic_message(&*payload)),
+    };
+
+    if let Some(dir) = &options.frames {
+        if outcome != Outcome::Passed && !frame.is_empty() {
+            let path = dir.join(&name).with_extension("ppm");
+            if let Err(e) = write_ppm(&path, &frame) {
+                println!("[suite] could not write {}: {}", path.display(), e);
+            }
+        }
+    }
+
+    TestResult {
+        name,
+        outcome,
+        cycles,
+        elapsed: start.elapsed(),
+        detail: detail.trim().to_string(),
+    }
+}
+
+fn run_until_done(
+    data: &[u8],
+    options: &SuiteOptions,
+    output: &Rc<RefCell<Vec<u8>>>,
+    cycles: &mut u64,
+    frame: &mut Vec<Rgb>,
+    start: Instant,
+) -> (Outcome, Option<String>) {
+    let rom = data.to_vec();
+    let mut gameboy = GameBoy::new(
+        &rom,
+        options.model,
+        options.renderer,
+        Box::new(NoClock::new()),
+        Box::new(NoDisplay::new()),
+    );
+    gameboy.connect_serial(Box::new(SerialCapture {
+        output: output.clone(),
+    }));
+
+    gameboy.init_ram(options.ram_init);
+    if let Some(boot_rom) = &options.boot_rom {
+        // Checked with GameBoy::check_boot_rom when the options are parsed
+        gameboy.set_boot_rom(boot_rom.clone()).unwrap();
+    }
+    if options.skip_boot {
+        gameboy.skip_boot();
+    }
+
+    let result = wait_for_result(&mut gameboy, options, output, cycles, start);
+    frame.extend_from_slice(gameboy.framebuffer());
+    result
+}
+
+fn wait_for_result(
+    gameboy: &mut GameBoy,
+    options: &SuiteOptions,
+    output: &Rc<RefCell<Vec<u8>>>,
+    cycles: &mut u64,
+    start: Instant,
+) -> (Outcome, Option<String>) {
+    let mut output_len = 0;
+    let mut last_output = 0;
+    let mut serial_result = None;
+    let mut steps_until_check = STEPS_PER_TIMEOUT_CHECK;
+
+    while *cycles < options.cycle_budget {
+        *cycles += gameboy.step() as u64;
+
+        if gameboy.last_op_code() == LD_B_B {
+            let regs = gameboy.registers();
+            let signature = [regs.b, regs.c, regs.d, regs.e, regs.h, regs.l];
+            if signature == MOONEYE_PASS {
+                return (Outcome::Passed, None);
+            } else if signature == MOONEYE_FAIL {
+                return (Outcome::Failed, None);
+            }
+        }
+
+        let output = output.borrow();
+        if output.len() != output_len {
+            if serial_result.is_none() {
+                serial_result = find_result(&output, output_len).map(|o| (o, *cycles));
+            }
+            output_len = output.len();
+            last_output = *cycles;
+        }
+
+        if let Some((outcome, found)) = serial_result {
+            if *cycles - last_output >= SERIAL_QUIET_CYCLES
+                || *cycles - found >= SERIAL_DRAIN_CYCLES
+            {
+                return (outcome, None);
+            }
+        }
+
+        steps_until_check -= 1;
+        if steps_until_check == 0 {
+            if start.elapsed() > options.timeout {
+                break;
+            }
+            steps_until_check = STEPS_PER_TIMEOUT_CHECK;
+        }
+    }
+
+    match serial_result {
+        Some((outcome, _)) => (outcome, None),
+        None => (Outcome::Timeout, Some(gameboy.backtrace())),
+    }
+}
+
+/// Looks for the result a Blargg ROM prints. Only the output after the
+/// first `searched` bytes, and the few before that a marker could have
+/// started in, is searched, so long logs aren't scanned over and over.
+fn find_result(output: &[u8], searched: usize) -> Option<Outcome> {
+    let tail = &output[searched.saturating_sub(MARKER_LEN - 1)..];
+    let contains = |marker: &[u8]| tail.windows(marker.len()).any(|w| w == marker);
+
+    if contains(b"Passed") {
+        Some(Outcome::Passed)
+    } else if contains(b"Failed") {
+        Some(Outcome::Failed)
+    } else {
+        None
+    }
+}
+
+/// Writes a frame as a binary PPM image, which needs no image library
+fn write_ppm(path: &Path, frame: &[Rgb]) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let mut out = io::BufWriter::new(fs::File::create(path)?);
+    write!(out, "P6\n{} {}\n255\n", SCREEN_WIDTH, SCREEN_HEIGHT)?;
+    for rgb in frame {
+        out.write_all(&rgb.to_be_bytes()[1..])?;
+    }
+    out.flush()
+}
+
+fn print_table(results: &[TestResult]) {
+    let width = results
+        .iter()
+        .map(|r| r.name.len())
+        .max()
+        .unwrap_or(0)
+        .max(3);
+
+    println!(
+        "{:<width$}  {:<8} {:>12} {:>9}",
+        "ROM",
+        "RESULT",
+        "CYCLES",
+        "TIME",
+        width = width
+    );
+    for result in results {
+        println!(
+            "{:<width$}  {:<8} {:>12} {:>8.2}s",
+            result.name,
+            result.outcome.name(),
+            result.cycles,
+            result.elapsed.as_secs_f64(),
+            width = width
+        );
+    }
+
+    let passed = results
+        .iter()
+        .filter(|r| r.outcome == Outcome::Passed)
+        .count();
+    println!("\n{} passed, {} not passed", passed, results.len() - passed);
+}
+
+fn write_report(out: &mut dyn Write, results: &[TestResult]) -> io::Result<()> {
+    writeln!(out, "[")?;
+    for (i, result) in results.iter().enumerate() {
+        let separator = if i + 1 < results.len() { "," } else { "" };
+        writeln!(
+            out,
+            "  {{\"rom\": {}, \"outcome\": \"{}\", \"cycles\": {}, \"elapsed_ms\": {}, \"detail\": {}}}{}",
+            json_string(&result.name),
+            result.outcome.name(),
+            result.cycles,
+            result.elapsed.as_millis(),
+            json_string(&result.detail),
+            separator
+        )?;
+    }
+    writeln!(out, "]")
+}
+
+fn json_string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len() + 2);
+    escaped.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::asm::Alu;
+    use crate::asm::Assembler;
+    use crate::asm::Cond;
+    use crate::asm::Ind;
+    use crate::asm::R16;
+    use crate::asm::R8;
+    use std::env;
+    use std::process;
+
+    fn mooneye_rom(signature: [u8; 6]) -> Vec<u8> {
+        let [b, c, d, e, h, l] = signature;
+        let mut asm = Assembler::new("MOONEYE");
+        asm.ld_n(R8::B, b)
+            .ld_n(R8::C, c)
+            .ld_n(R8::D, d)
+            .ld_n(R8::E, e)
+            .ld_n(R8::H, h)
+            .ld_n(R8::L, l)
+            .ld(R8::B, R8::B)
+            .label("done")
+            .jr("done");
+        asm.build()
+    }
+
+    fn blargg_rom(message: &str) -> Vec<u8> {
+        let mut asm = Assembler::new("BLARGG");
+        asm.ld16(R16::HL, "message")
+            .label("loop")
+            .ld_a_ind(Ind::HLInc)
+            .alu(Alu::Or, R8::A)
+            .jr_cc(Cond::Z, "done")
+            .ld_ind_a(Ind::High(0x01))
+            .ld_n(R8::A, 0x81)
+            .ld_ind_a(Ind::High(0x02))
+            .label("wait")
+            .ld_a_ind(Ind::High(0x02))
+            .alu_n(Alu::And, 0x80)
+            .jr_cc(Cond::NZ, "wait")
+            .jr("loop")
+            .label("done")
+            .jr("done")
+            .label("message")
+            .db(message.as_bytes())
+            .db(&[0]);
+        asm.build()
+    }
+
+    #[test]
+    fn json_string_escapes() {
+        assert_eq!(json_string("a\"b\\c\n\u{1}"), "\"a\\\"b\\\\c\\n\\u0001\"");
+    }
+
+    #[test]
+    fn report_format() {
+        let results = vec![
+            TestResult {
+                name: String::from("cpu_instrs/01-special.gb"),
+                outcome: Outcome::Passed,
+                cycles: 100,
+                elapsed: Duration::from_millis(12),
+                detail: String::from("01-special\n\nPassed"),
+            },
+            TestResult {
+                name: String::from("timer/div_write.gb"),
+                outcome: Outcome::Timeout,
+                cycles: 200,
+                elapsed: Duration::from_millis(34),
+                detail: String::new(),
+            },
+        ];
+        let mut out = Vec::new();
+
+        write_report(&mut out, &results).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "[\n  {\"rom\": \"cpu_instrs/01-special.gb\", \"outcome\": \"passed\", \"cycles\": 100, \
+             \"elapsed_ms\": 12, \"detail\": \"01-special\\n\\nPassed\"},\n  \
+             {\"rom\": \"timer/div_write.gb\", \"outcome\": \"timeout\", \"cycles\": 200, \
+             \"elapsed_ms\": 34, \"detail\": \"\"}\n]\n"
+        );
+    }
+
+    #[test]
+    fn run_rom_mooneye() {
+        let options = SuiteOptions::default();
+
+        let result = run_rom(
+            String::from("pass.gb"),
+            &mooneye_rom(MOONEYE_PASS),
+            &options,
+        );
+        assert_eq!(result.outcome, Outcome::Passed);
+
+        let result = run_rom(
+            String::from("fail.gb"),
+            &mooneye_rom(MOONEYE_FAIL),
+            &options,
+        );
+        assert_eq!(result.outcome, Outcome::Failed);
+    }
+
+    #[test]
+    fn run_rom_blargg() {
+        let options = SuiteOptions::default();
+
+        let result = run_rom(
+            String::from("pass.gb"),
+            &blargg_rom("test\nPassed"),
+            &options,
+        );
+        assert_eq!(result.outcome, Outcome::Passed);
+        assert_eq!(result.detail, "test\nPassed");
+
+        let result = run_rom(String::from("fail.gb"), &blargg_rom("Failed #2"), &options);
+        assert_eq!(result.outcome, Outcome::Failed);
+        assert_eq!(result.detail, "Failed #2");
+    }
+
+    #[test]
+    fn find_result_in_new_output() {
+        assert_eq!(find_result(b"01-special\nPass", 0), None);
+        assert_eq!(
+            find_result(b"01-special\nPassed", 15),
+            Some(Outcome::Passed)
+        );
+        assert_eq!(find_result(b"Failed #2\nmore", 5), Some(Outcome::Failed));
+
+        // Markers before the part being searched were already seen
+        assert_eq!(find_result(b"Failed #2\nmore", 6), None);
+    }
+
+    #[test]
+    fn run_rom_saves_failed_frames() {
+        let dir = env::temp_dir().join(format!("rustboy-frames-{}", process::id()));
+        let options = SuiteOptions {
+            frames: Some(dir.clone()),
+            ..SuiteOptions::default()
+        };
+
+        run_rom(String::from("pass.gb"), &blargg_rom("Passed"), &options);
+        run_rom(String::from("sub/fail.gb"), &blargg_rom("Failed"), &options);
+
+        assert!(!dir.join("pass.ppm").exists());
+        let image = fs::read(dir.join("sub/fail.ppm")).unwrap();
+        assert!(image.starts_with(b"P6\n160 144\n255\n"));
+        assert_eq!(image.len(), 15 + SCREEN_WIDTH * SCREEN_HEIGHT * 3);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn run_rom_crashed() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x147] = 0xFE; // Unsupported MBC
+
+        let result = run_rom(String::from("bad.gb"), &rom, &SuiteOptions::default());
+
+        assert_eq!(result.outcome, Outcome::Crashed);
+        assert_eq!(result.detail, "unimplemented MBC: 0xfe");
+
+        let mut asm = Assembler::new("ILLEGAL");
+        asm.db(&[0xD3]);
+
+        let result = run_rom(
+            String::from("illegal.gb"),
+            &asm.build(),
+            &SuiteOptions::default(),
+        );
+
+        assert_eq!(result.outcome, Outcome::Crashed);
+        assert_eq!(result.detail, "Unknown operation 0xD3");
+    }
+
+    #[test]
+    fn run_rom_timeout() {
+        let rom = vec![0; 0x8000];
+        let options = SuiteOptions {
+            cycle_budget: 1000,
+            ..SuiteOptions::default()
+        };
+
+        let result = run_rom(String::from("nop.gb"), &rom, &options);
+
+        assert_eq!(result.outcome, Outcome::Timeout);
+        assert!(result.cycles >= 1000);
+        assert!(result.detail.starts_with("backtrace:\n  #0 00:"));
+    }
+}
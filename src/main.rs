@@ -1,26 +1,306 @@
+#[cfg(test)]
+mod asm;
 mod bits;
 mod display;
 mod gameboy;
+mod headless;
 
 use crate::display::SDLFrontend;
+use crate::gameboy::clock::WallClock;
+use crate::gameboy::debug::cdl::CodeDataLog;
+use crate::gameboy::debug::crashdump;
+use crate::gameboy::debug::crashdump::CrashDump;
+use crate::gameboy::debug::symbols::Symbols;
+use crate::gameboy::palette::ColorPalette;
+use crate::gameboy::palette::Preset;
+use crate::gameboy::GameBoy;
+use crate::gameboy::Model;
+use crate::gameboy::RamInit;
+use crate::gameboy::Renderer;
+use crate::headless::SuiteOptions;
 
 use std::env;
 use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::BufWriter;
+use std::path::Path;
+use std::path::PathBuf;
 use std::process;
+use std::time::Duration;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
 
 fn main() {
     let args: Vec<String> = env::args().collect();
     if args.len() < 2 {
-        println!("usage: rustboy [rom]");
-        process::exit(1);
+        print_usage();
     }
 
-    let filename = &args[1];
-    let data = fs::read(filename).unwrap_or_else(|e| {
-        println!("error: {}", e);
-        process::exit(1);
-    });
+    if args[1] == "test-suite" {
+        run_test_suite(&args[2..]);
+    }
+
+    if args[1] == "inspect" {
+        inspect_crash_dump(&args[2..]);
+    }
+
+    run_game(&args[1..]);
+}
+
+fn print_usage() -> ! {
+    println!("usage: rustboy [--model name] [--renderer name] [--ram-init policy]");
+    println!("               [--palette name|file] [--boot-rom file] [--skip-boot]");
+    println!("               [--profile file] [--cdl] [--lint] [--no-blocking]");
+    println!("               [--break location] [--watchdog frames] [rom]");
+    println!("       rustboy test-suite [--model name] [--renderer name] [--ram-init policy]");
+    println!("                          [--boot-rom file] [--skip-boot] [--cycles n]");
+    println!("                          [--timeout secs] [--report file] [--frames dir]");
+    println!("                          [dir]");
+    println!("       rustboy inspect [crash dump] [wram|vram|oam|hram|sram.N]");
+    println!();
+    println!("--break pauses emulation at a label, bank:addr or address until F5 is pressed");
+    println!("--watchdog reports a crash after that many frames stuck in a jump to itself");
+    process::exit(1);
+}
+
+fn run_game(args: &[String]) {
+    let mut model = Model::default();
+    let mut renderer = Renderer::default();
+    let mut ram_init = RamInit::default();
+    let mut palette = None;
+    let mut boot_rom = None;
+    let mut skip_boot = false;
+    let mut profile = None;
+    let mut log_cdl = false;
+    let mut lint = false;
+    let mut blocking = true;
+    let mut breakpoints = Vec::new();
+    let mut watchdog = None;
+    let mut filename = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--model" => model = parse_value(args.next()),
+            "--renderer" => renderer = parse_value(args.next()),
+            "--ram-init" => ram_init = parse_ram_init(args.next()),
+            "--palette" => palette = Some(parse_palette(args.next())),
+            "--boot-rom" => boot_rom = Some(parse_value::<String>(args.next())),
+            "--skip-boot" => skip_boot = true,
+            "--profile" => profile = Some(PathBuf::from(parse_value::<String>(args.next()))),
+            "--cdl" => log_cdl = true,
+            "--lint" => lint = true,
+            "--no-blocking" => blocking = false,
+            "--break" => breakpoints.push(parse_value::<String>(args.next())),
+            "--watchdog" => watchdog = Some(parse_value(args.next())),
+            _ if filename.is_none() => filename = Some(arg),
+            _ => print_usage(),
+        }
+    }
+
+    let filename = filename.unwrap_or_else(|| print_usage());
+    let data = fs::read(filename).unwrap_or_else(|e| exit_with_error(e));
 
     println!("[start] RustBoy");
-    SDLFrontend::run(&data, 2);
+    let frontend = SDLFrontend::new(2);
+    let clock = Box::new(WallClock::z80());
+    let mut gameboy = GameBoy::new(&data, model, renderer, clock, frontend.display());
+
+    gameboy.set_crash_dump_path(Path::new(filename).with_extension("crash.txt"));
+    gameboy.init_ram(ram_init);
+
+    if let Some(path) = boot_rom {
+        let data = fs::read(path).unwrap_or_else(|e| exit_with_error(e));
+        gameboy
+            .set_boot_rom(data)
+            .unwrap_or_else(|e| exit_with_error(e));
+    }
+
+    if skip_boot {
+        gameboy.skip_boot();
+    }
+
+    // Symbols are picked up from next to the ROM, as written by RGBDS
+    let sym_path = Path::new(filename).with_extension("sym");
+    if sym_path.exists() {
+        let symbols = Symbols::load(&sym_path).unwrap_or_else(|e| exit_with_error(e));
+        println!(
+            "[symbols] loaded {} from {}",
+            symbols.len(),
+            sym_path.display()
+        );
+        gameboy.set_symbols(symbols);
+    }
+
+    // Breakpoints are labels, `bank:addr` or plain addresses, which are in
+    // bank 1 for the switchable region as nothing has been mapped yet
+    for breakpoint in breakpoints {
+        let location = gameboy.symbols().resolve(&breakpoint, 1);
+        let location = location.unwrap_or_else(|| {
+            println!("error: unknown breakpoint {}", breakpoint);
+            process::exit(1);
+        });
+        gameboy.add_breakpoint(location);
+    }
+
+    if profile.is_some() {
+        gameboy.enable_profiler();
+    }
+
+    if lint {
+        gameboy.enable_lint();
+    }
+
+    gameboy.set_access_blocking(blocking);
+
+    if let Some(frames) = watchdog {
+        gameboy.enable_watchdog(frames);
+    }
+
+    // The log is kept next to the ROM and grows with every run
+    let cdl_path = Path::new(filename).with_extension("cdl");
+    if log_cdl {
+        let cdl = CodeDataLog::open(&cdl_path, data.len(), gameboy.cartridge_ram_size())
+            .unwrap_or_else(|e| exit_with_error(e));
+        gameboy.enable_cdl(cdl);
+    }
+
+    // The chosen palette, or else the CGB's for the game, comes first and
+    // P cycles on through the presets
+    let palette = palette.or_else(|| {
+        let colorization = gameboy.cgb_colorization();
+        if colorization.is_some() {
+            println!("[palette] CGB colors for {}", gameboy.title());
+        }
+        colorization
+    });
+    let mut palettes: Vec<ColorPalette> = palette.into_iter().collect();
+    palettes.extend(Preset::ALL.iter().map(|preset| preset.palette()));
+
+    frontend.run(&mut gameboy, palettes);
+
+    if let Some(path) = profile {
+        write_profile(&gameboy, &path).unwrap_or_else(|e| exit_with_error(e));
+    }
+
+    if lint {
+        println!("[lint] {} warnings", gameboy.lint_warnings().len());
+    }
+
+    if let Some(cdl) = gameboy.cdl() {
+        cdl.save(&cdl_path).unwrap_or_else(|e| exit_with_error(e));
+        println!("[cdl] written to {}", cdl_path.display());
+        println!("[cdl] {}", cdl.coverage());
+    }
+}
+
+/// Writes the hotspot report to the given path and the collapsed stacks for
+/// flamegraph tools next to it
+fn write_profile(gameboy: &GameBoy, path: &Path) -> io::Result<()> {
+    let profiler = gameboy.profiler().unwrap();
+
+    let mut report = BufWriter::new(File::create(path)?);
+    profiler.write_report(&mut report, 50, gameboy.symbols())?;
+
+    let mut stacks = BufWriter::new(File::create(path.with_extension("folded"))?);
+    profiler.write_collapsed(&mut stacks, gameboy.symbols())?;
+
+    println!("[profile] written to {}", path.display());
+    Ok(())
+}
+
+/// Prints the state of the machine from a crash dump, and optionally one of
+/// its memory regions
+fn inspect_crash_dump(args: &[String]) -> ! {
+    let (path, region) = match args {
+        [path] => (path, None),
+        [path, region] => (path, Some(region)),
+        _ => print_usage(),
+    };
+
+    let dump = CrashDump::load(Path::new(path)).unwrap_or_else(|e| exit_with_error(e));
+    let mut out = io::stdout();
+    dump.write_summary(&mut out)
+        .unwrap_or_else(|e| exit_with_error(e));
+
+    if let Some(name) = region {
+        let region = dump.region(name).unwrap_or_else(|| {
+            println!("error: no {} region in the crash dump", name);
+            process::exit(1);
+        });
+        println!("[memory {} {:04X}]", region.name, region.start);
+        crashdump::write_hex(&mut out, region).unwrap_or_else(|e| exit_with_error(e));
+    }
+
+    process::exit(0);
+}
+
+fn run_test_suite(args: &[String]) -> ! {
+    let mut options = SuiteOptions::default();
+    let mut boot_rom = None;
+    let mut dir = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--model" => options.model = parse_value(args.next()),
+            "--renderer" => options.renderer = parse_value(args.next()),
+            "--ram-init" => options.ram_init = parse_ram_init(args.next()),
+            "--boot-rom" => boot_rom = Some(parse_value::<String>(args.next())),
+            "--skip-boot" => options.skip_boot = true,
+            "--cycles" => options.cycle_budget = parse_value(args.next()),
+            "--timeout" => options.timeout = Duration::from_secs(parse_value(args.next())),
+            "--report" => options.report = Some(PathBuf::from(parse_value::<String>(args.next()))),
+            "--frames" => options.frames = Some(PathBuf::from(parse_value::<String>(args.next()))),
+            _ if dir.is_none() => dir = Some(arg),
+            _ => print_usage(),
+        }
+    }
+
+    if let Some(path) = boot_rom {
+        let data = fs::read(path).unwrap_or_else(|e| exit_with_error(e));
+        GameBoy::check_boot_rom(options.model, &data).unwrap_or_else(|e| exit_with_error(e));
+        options.boot_rom = Some(data);
+    }
+
+    let dir = dir.unwrap_or_else(|| print_usage());
+    match headless::run_suite(Path::new(dir), &options) {
+        Ok(true) => process::exit(0),
+        Ok(false) => process::exit(1),
+        Err(e) => exit_with_error(e),
+    }
+}
+
+/// Parses a RAM init policy. Plain `random` picks a seed, which is printed
+/// so that the run can be repeated.
+fn parse_ram_init(value: Option<&String>) -> RamInit {
+    if value.map(|v| v.as_str()) != Some("random") {
+        return parse_value(value);
+    }
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH);
+    let ram_init = RamInit::Random(now.map_or(0, |d| d.as_nanos() as u64));
+    println!("[ram] {}", ram_init);
+    ram_init
+}
+
+/// Parses a palette preset name, or otherwise loads a palette file
+fn parse_palette(value: Option<&String>) -> ColorPalette {
+    let value = value.unwrap_or_else(|| print_usage());
+    match value.parse::<Preset>() {
+        Ok(preset) => preset.palette(),
+        Err(_) => ColorPalette::load(Path::new(value)).unwrap_or_else(|e| exit_with_error(e)),
+    }
+}
+
+fn exit_with_error(error: io::Error) -> ! {
+    println!("error: {}", error);
+    process::exit(1);
+}
+
+fn parse_value<T: std::str::FromStr>(value: Option<&String>) -> T {
+    value
+        .and_then(|v| v.parse().ok())
+        .unwrap_or_else(|| print_usage())
 }